@@ -0,0 +1,42 @@
+use crate::{Error, Manager, Pool};
+use deadpool::managed::{Object, PoolError};
+use log::{debug, warn};
+use std::future::Future;
+use tokio_util::sync::CancellationToken;
+
+/// Takes a session from `pool` unless `token` is cancelled first, see [`get_until`]
+pub async fn get_cancellable(
+    pool: &Pool,
+    token: &CancellationToken,
+) -> Result<Object<Manager>, PoolError<Error>> {
+    get_until(pool, token.cancelled()).await
+}
+
+/// Takes a session from `pool` unless `cancelled` completes first
+///
+/// A session still being created is not abandoned halfway, but closed once created instead of being returned to the
+/// pool, so that it does not linger at SessionDriver until it expires.
+pub async fn get_until<F: Future<Output = ()>>(
+    pool: &Pool,
+    cancelled: F,
+) -> Result<Object<Manager>, PoolError<Error>> {
+    let mut getting = tokio::spawn({
+        let pool = pool.clone();
+        async move { pool.get().await }
+    });
+
+    tokio::select! {
+        got = &mut getting => got.unwrap_or_else(|e| Err(PoolError::Backend(Error::Other(e.into())))),
+        _ = cancelled => {
+            tokio::spawn(async move {
+                if let Ok(Ok(object)) = getting.await {
+                    debug!("Closing session acquired after cancellation");
+                    if let Err(e) = Object::take(object).close().await {
+                        warn!("Unable to close session acquired after cancellation: {}", e);
+                    }
+                }
+            });
+            Err(PoolError::Backend(Error::Cancelled))
+        }
+    }
+}
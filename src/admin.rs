@@ -0,0 +1,235 @@
+use crate::audit::Command;
+use crate::auth;
+use crate::capacity::Capacity;
+use crate::expiry::Expiry;
+use crate::labels::{Labels, Selector};
+use crate::latency::{Class, Summary};
+use crate::metrics::Metrics;
+use crate::output::Line;
+use crate::usage::Snapshot;
+use crate::webhook::EventKind;
+use crate::{AppState, Backend, Browser, Browsers, WebDriverMeta, check_driver, unknown_session};
+use axum::Router;
+use axum::extract::{Path, Query, Request, State};
+use axum::http::StatusCode;
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::{get, put};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Routes of the administrative API, guarded by a bearer token
+pub fn router(token: String) -> Router<AppState> {
+    Router::new()
+        .route("/admin/sessions", get(sessions))
+        .route("/admin/sessions/{id}", get(session).delete(kill))
+        .route("/admin/sessions/{id}/commands", get(commands))
+        .route("/admin/sessions/{id}/logs", get(logs))
+        .route("/admin/stats", get(stats))
+        .route("/admin/webdriver", put(replace_webdriver))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::<str>::from(token),
+            authenticate,
+        ))
+}
+
+async fn authenticate(
+    State(token): State<Arc<str>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Response> {
+    if !auth::bearer(request.headers(), &token) {
+        warn!("Rejected unauthorised request to {}", request.uri().path());
+        return Err(auth::unauthorized("Missing or unknown admin token"));
+    }
+
+    Ok(next.run(request).await)
+}
+
+#[derive(Debug, Serialize)]
+pub struct SessionDetails {
+    pub id: Uuid,
+    pub address: SocketAddr,
+    pub pid: Option<u32>,
+    pub created: u64,
+    pub tenant: Option<String>,
+    /// Latest sample of the WebDriver's process group, see `--usage-interval`
+    pub usage: Option<Snapshot>,
+    /// Time the WebDriver took to answer commands, per class of command
+    pub latency: BTreeMap<Class, Summary>,
+    pub labels: Labels,
+}
+
+/// Sessions having the labels given as e.g. `?labels=team=checkout`, or all of them
+async fn sessions(
+    State(browsers): State<Browsers>,
+    Query(selector): Query<Selector>,
+) -> Json<Vec<SessionDetails>> {
+    let mut sessions = Vec::new();
+    for shard in browsers.shards() {
+        for (id, browser) in shard.read().await.iter() {
+            if !selector.matches(&browser.labels) {
+                continue;
+            }
+            sessions.push(SessionDetails {
+                id: *id,
+                address: browser.address,
+                pid: pid(browser).await,
+                created: unix_seconds(browser.created),
+                tenant: browser.tenant.as_ref().map(|t| t.name.clone()),
+                usage: browser.usage.snapshot(),
+                latency: browser.latencies.summary(),
+                labels: browser.labels.clone(),
+            });
+        }
+    }
+
+    Json(sessions)
+}
+
+async fn session(
+    State(browsers): State<Browsers>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<SessionDetails>, Response> {
+    let shard = browsers.shard(&id).read().await;
+    let browser = shard.get(&id).ok_or_else(|| unknown_session(id))?;
+
+    Ok(Json(SessionDetails {
+        id,
+        address: browser.address,
+        pid: pid(browser).await,
+        created: unix_seconds(browser.created),
+        tenant: browser.tenant.as_ref().map(|t| t.name.clone()),
+        usage: browser.usage.snapshot(),
+        latency: browser.latencies.summary(),
+        labels: browser.labels.clone(),
+    }))
+}
+
+async fn commands(
+    State(browsers): State<Browsers>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<Command>>, Response> {
+    let shard = browsers.shard(&id).read().await;
+    let browser = shard.get(&id).ok_or_else(|| unknown_session(id))?;
+
+    Ok(Json(browser.audit.commands().await))
+}
+
+async fn logs(
+    State(browsers): State<Browsers>,
+    Path(id): Path<Uuid>,
+) -> Result<Json<Vec<Line>>, Response> {
+    let shard = browsers.shard(&id).read().await;
+    let browser = shard.get(&id).ok_or_else(|| unknown_session(id))?;
+
+    Ok(Json(browser.output.lines()))
+}
+
+async fn kill(
+    State(browsers): State<Browsers>,
+    State(expiry): State<Expiry>,
+    State(metrics): State<Arc<Metrics>>,
+    State(http): State<reqwest::Client>,
+    State(webdriver_meta): State<Arc<WebDriverMeta>>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, Response> {
+    let browser = browsers
+        .remove(&id)
+        .await
+        .ok_or_else(|| unknown_session(id))?;
+    expiry.cancel(id);
+    metrics.sessions_killed.inc();
+
+    if let Some(webhook) = &webdriver_meta.webhook {
+        webhook.notify(&http, EventKind::Killed, id, &browser.capabilities);
+    }
+    // Without any grace, the WebDriver and what it started are sent SIGKILL right away
+    browser.end(id, Duration::ZERO).await;
+    info!("Killed {:?}", id);
+
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Serialize)]
+pub struct StatsSnapshot {
+    pub active: usize,
+    pub created: u64,
+    pub deleted: u64,
+    pub expired: u64,
+    pub killed: u64,
+    pub crashed: u64,
+    /// Seconds since start
+    pub uptime: u64,
+    pub max_sessions: Option<usize>,
+}
+
+async fn stats(
+    State(browsers): State<Browsers>,
+    State(metrics): State<Arc<Metrics>>,
+    State(capacity): State<Arc<Capacity>>,
+) -> Json<StatsSnapshot> {
+    Json(StatsSnapshot {
+        active: browsers.len().await,
+        created: metrics.sessions_created.get(),
+        deleted: metrics.sessions_deleted.get(),
+        expired: metrics.sessions_expired.get(),
+        killed: metrics.sessions_killed.get(),
+        crashed: metrics.sessions_crashed.get(),
+        uptime: metrics.started.elapsed().as_secs(),
+        max_sessions: capacity.max_sessions(),
+    })
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WebDriverChange {
+    pub path: PathBuf,
+}
+
+/// Starts new sessions with another WebDriver executable, whereas running WebDrivers are kept until their session ends
+async fn replace_webdriver(
+    State(webdriver_meta): State<Arc<WebDriverMeta>>,
+    Json(change): Json<WebDriverChange>,
+) -> Result<Json<serde_json::Value>, Response> {
+    let Backend::Process(current) = &webdriver_meta.backend else {
+        return Err((
+            StatusCode::CONFLICT,
+            "WebDrivers are not started from an executable",
+        )
+            .into_response());
+    };
+    let version = check_driver(&change.path, webdriver_meta.check_version)
+        .await
+        .map_err(|e| (StatusCode::BAD_REQUEST, e.to_string()).into_response())?;
+
+    let previous = std::mem::replace(
+        &mut *current.write().await,
+        change.path.clone().into_boxed_path(),
+    );
+    *webdriver_meta.driver_version.write().await = version.clone();
+    info!("Replaced WebDriver {:?} by {:?}", previous, change.path);
+
+    Ok(Json(serde_json::json!({
+        "path": change.path,
+        "version": version,
+    })))
+}
+
+pub async fn pid(browser: &Browser) -> Option<u32> {
+    match &browser.process {
+        Some(process) => process.lock().await.pid,
+        None => None,
+    }
+}
+
+pub fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
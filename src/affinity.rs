@@ -0,0 +1,41 @@
+use axum::http::response::Builder;
+use axum::http::{HeaderMap, HeaderName, HeaderValue};
+
+/// Names this instance on new sessions, so a load balancer in front of several can route a session's requests to it
+pub struct Affinity {
+    pub header: HeaderName,
+    pub node: HeaderValue,
+}
+
+impl Affinity {
+    pub fn tag(&self, response: Builder) -> Builder {
+        response.header(&self.header, &self.node)
+    }
+
+    /// Removes the header from a request, returning the node it names if that is another one
+    pub fn take(&self, headers: &mut HeaderMap) -> Option<String> {
+        let node = headers.remove(&self.header)?;
+        (node != self.node).then(|| String::from_utf8_lossy(node.as_bytes()).into_owned())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn recognises_other_nodes() {
+        let affinity = Affinity {
+            header: HeaderName::from_static("x-sessiondriver-node"),
+            node: HeaderValue::from_static("a"),
+        };
+
+        let mut headers = HeaderMap::new();
+        headers.insert("X-SessionDriver-Node", HeaderValue::from_static("a"));
+        assert_eq!(affinity.take(&mut headers), None);
+        assert!(headers.is_empty());
+
+        headers.insert("X-SessionDriver-Node", HeaderValue::from_static("b"));
+        assert_eq!(affinity.take(&mut headers).as_deref(), Some("b"));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::forwarded::ClientAddress;
+use crate::w3c;
+use axum::Router;
+use axum::extract::{Extension, Request, State};
+use axum::http::StatusCode;
+use axum::middleware::{self, Next};
+use axum::response::Response;
+use ipnet::IpNet;
+use log::warn;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// Networks clients are allowed to connect from
+pub struct Allowlist(Vec<IpNet>);
+
+impl Allowlist {
+    pub fn new(networks: Vec<IpNet>) -> Self {
+        Self(networks)
+    }
+
+    pub fn contains(&self, address: IpAddr) -> bool {
+        let address = address.to_canonical();
+        self.0.iter().any(|network| network.contains(&address))
+    }
+}
+
+/// Accepts networks in CIDR notation as well as single addresses
+pub fn parse_network(s: &str) -> Result<IpNet, String> {
+    match s.parse::<IpNet>() {
+        Ok(network) => Ok(network),
+        Err(e) => s
+            .parse::<IpAddr>()
+            .map(IpNet::from)
+            .map_err(|_| e.to_string()),
+    }
+}
+
+/// Answers requests to `router` from outside of `networks` with `403`, unless no network is given
+pub fn restrict<S>(router: Router<S>, networks: Vec<IpNet>) -> Router<S>
+where
+    S: Clone + Send + Sync + 'static,
+{
+    if networks.is_empty() {
+        return router;
+    }
+    let allowlist = Arc::new(Allowlist::new(networks));
+    router.layer(middleware::from_fn_with_state(allowlist, filter))
+}
+
+pub async fn filter(
+    State(allowlist): State<Arc<Allowlist>>,
+    Extension(ClientAddress(client)): Extension<ClientAddress>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Response> {
+    if !allowlist.contains(client) {
+        warn!(
+            "Rejected request from {} to {}",
+            client,
+            request.uri().path()
+        );
+        return Err(w3c::error(
+            StatusCode::FORBIDDEN,
+            w3c::UNKNOWN_ERROR,
+            "Requests from this address are not allowed",
+        ));
+    }
+
+    Ok(next.run(request).await)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_networks_and_addresses() {
+        let allowlist = Allowlist::new(vec![
+            parse_network("10.1.0.0/16").unwrap(),
+            parse_network("127.0.0.1").unwrap(),
+        ]);
+
+        assert!(allowlist.contains("10.1.2.3".parse().unwrap()));
+        assert!(allowlist.contains("127.0.0.1".parse().unwrap()));
+        assert!(allowlist.contains("::ffff:10.1.2.3".parse().unwrap()));
+        assert!(!allowlist.contains("10.2.0.1".parse().unwrap()));
+        assert!(!allowlist.contains("127.0.0.2".parse().unwrap()));
+        assert!(parse_network("10.1.0.0/33").is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use crate::tenant::{self, Tenant};
+use crate::{Browsers, internal_server_error, w3c};
+use axum::body::Body;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Response};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+/// A kind of file sessions leave behind, e.g. recordings, which is served once the session has ended
+pub struct Artifact {
+    pub name: &'static str,
+    pub extension: &'static str,
+    pub content_type: &'static str,
+}
+
+impl Artifact {
+    /// Path of the file of `session`, which is kept in a directory of its tenant so that no other tenant is served it
+    pub fn path(&self, directory: &Path, tenant: Option<&Tenant>, session: Uuid) -> PathBuf {
+        let directory = match tenant {
+            Some(tenant) => directory.join(&tenant.name),
+            None => directory.to_path_buf(),
+        };
+        directory.join(format!("{}.{}", session, self.extension))
+    }
+
+    pub async fn download(
+        &self,
+        browsers: &Browsers,
+        directory: Option<&Path>,
+        tenant: Option<&Tenant>,
+        id: Uuid,
+    ) -> Result<Response, Response> {
+        let path = self.locate(browsers, directory, tenant, id).await?;
+        let file = tokio::fs::File::open(&path)
+            .await
+            .map_err(|_| self.not_found(id))?;
+
+        Ok((
+            [("Content-Type", self.content_type)],
+            Body::from_stream(ReaderStream::new(file)),
+        )
+            .into_response())
+    }
+
+    pub async fn remove(
+        &self,
+        browsers: &Browsers,
+        directory: Option<&Path>,
+        tenant: Option<&Tenant>,
+        id: Uuid,
+    ) -> Result<StatusCode, Response> {
+        let path = self.locate(browsers, directory, tenant, id).await?;
+        match tokio::fs::remove_file(&path).await {
+            Ok(()) => Ok(StatusCode::NO_CONTENT),
+            Err(e) if e.kind() == ErrorKind::NotFound => Err(self.not_found(id)),
+            Err(e) => Err(internal_server_error(e)),
+        }
+    }
+
+    /// Path of the file of an ended session of `tenant`
+    async fn locate(
+        &self,
+        browsers: &Browsers,
+        directory: Option<&Path>,
+        tenant: Option<&Tenant>,
+        id: Uuid,
+    ) -> Result<PathBuf, Response> {
+        let Some(directory) = directory else {
+            return Err(w3c::error(
+                StatusCode::NOT_FOUND,
+                w3c::UNKNOWN_COMMAND,
+                format!("No {}s are kept", self.name),
+            ));
+        };
+
+        let running = browsers
+            .shard(&id)
+            .read()
+            .await
+            .get(&id)
+            .is_some_and(|browser| tenant::owns(tenant, browser));
+        if running {
+            return Err(w3c::error(
+                StatusCode::CONFLICT,
+                w3c::UNKNOWN_ERROR,
+                format!("The {} is written until the session ends", self.name),
+            ));
+        }
+
+        Ok(self.path(directory, tenant, id))
+    }
+
+    fn not_found(&self, id: Uuid) -> Response {
+        w3c::error(
+            StatusCode::NOT_FOUND,
+            w3c::UNKNOWN_ERROR,
+            format!("No {} of session {}", self.name, id),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::Quota;
+
+    #[test]
+    fn keeps_files_of_tenants_apart() {
+        const RECORDING: Artifact = Artifact {
+            name: "recording",
+            extension: "mp4",
+            content_type: "video/mp4",
+        };
+        let tenant = Tenant {
+            name: String::from("checkout"),
+            tokens: Vec::new(),
+            quota: Quota::new(None),
+            tti: None,
+        };
+        let session = Uuid::new_v4();
+
+        assert_eq!(
+            RECORDING.path(Path::new("/recordings"), None, session),
+            PathBuf::from(format!("/recordings/{}.mp4", session))
+        );
+        assert_eq!(
+            RECORDING.path(Path::new("/recordings"), Some(&tenant), session),
+            PathBuf::from(format!("/recordings/checkout/{}.mp4", session))
+        );
+    }
+}
@@ -0,0 +1,184 @@
+use async_lock::Mutex;
+use axum::body::{Body, Bytes};
+use axum::http::Method;
+use hyper::body::{Frame, SizeHint};
+use log::warn;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+/// Number of commands retained in memory per session
+const RETAINED_COMMANDS: usize = 1000;
+
+/// Number of request body bytes recorded per command
+const BODY_LIMIT: usize = 1024;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Command {
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub method: String,
+    pub path: String,
+    pub body: String,
+    pub status: Option<u16>,
+    pub latency_ms: u64,
+}
+
+/// Commands proxied to the WebDriver of a single session
+pub struct AuditLog {
+    directory: Option<PathBuf>,
+    commands: Mutex<VecDeque<Command>>,
+    file: Mutex<Option<File>>,
+}
+
+impl AuditLog {
+    pub fn new(directory: Option<&Path>) -> Self {
+        Self {
+            directory: directory.map(Path::to_path_buf),
+            commands: Mutex::new(VecDeque::new()),
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Starts persisting commands to `<directory>/<session>.jsonl`, including those recorded so far
+    pub async fn attach(&self, session: Uuid) {
+        let Some(directory) = &self.directory else {
+            return;
+        };
+
+        let path = directory.join(format!("{}.jsonl", session));
+        let mut file = match OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .await
+        {
+            Ok(file) => file,
+            Err(e) => {
+                warn!("Unable to open audit log {:?}: {}", path, e);
+                return;
+            }
+        };
+
+        let commands = self.commands.lock().await;
+        for command in commands.iter() {
+            if let Err(e) = write(&mut file, command).await {
+                warn!("Unable to write audit log {:?}: {}", path, e);
+            }
+        }
+        *self.file.lock().await = Some(file);
+    }
+
+    pub async fn record(
+        &self,
+        method: &Method,
+        path: &str,
+        body: &[u8],
+        status: Option<u16>,
+        latency: Duration,
+    ) {
+        let command = Command {
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis() as u64)
+                .unwrap_or_default(),
+            method: method.to_string(),
+            path: path.to_owned(),
+            body: String::from_utf8_lossy(&body[..body.len().min(BODY_LIMIT)]).into_owned(),
+            status,
+            latency_ms: latency.as_millis() as u64,
+        };
+
+        let mut commands = self.commands.lock().await;
+        if let Some(file) = self.file.lock().await.as_mut()
+            && let Err(e) = write(file, &command).await
+        {
+            warn!("Unable to write audit log: {}", e);
+        }
+
+        if commands.len() == RETAINED_COMMANDS {
+            commands.pop_front();
+        }
+        commands.push_back(command);
+    }
+
+    pub async fn commands(&self) -> Vec<Command> {
+        self.commands.lock().await.iter().cloned().collect()
+    }
+}
+
+/// The beginning of a request body, as much of it as is recorded
+#[derive(Clone, Default)]
+pub struct Head(Arc<std::sync::Mutex<Vec<u8>>>);
+
+impl Head {
+    pub fn of(body: &[u8]) -> Self {
+        let head = Self::default();
+        head.push(body);
+        head
+    }
+
+    fn push(&self, data: &[u8]) {
+        let mut head = self.0.lock().expect("Head lock poisoned");
+        let missing = BODY_LIMIT.saturating_sub(head.len());
+        head.extend_from_slice(&data[..data.len().min(missing)]);
+    }
+
+    pub fn bytes(&self) -> Vec<u8> {
+        self.0.lock().expect("Head lock poisoned").clone()
+    }
+}
+
+/// Request body passed on as it arrives, keeping its [`Head`] to be recorded rather than reading all of it
+pub struct Tee {
+    body: Body,
+    head: Head,
+}
+
+impl Tee {
+    pub fn new(body: Body) -> (Self, Head) {
+        let head = Head::default();
+        let tee = Self {
+            body,
+            head: head.clone(),
+        };
+        (tee, head)
+    }
+}
+
+impl hyper::body::Body for Tee {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        let frame = ready!(Pin::new(&mut self.body).poll_frame(cx));
+        if let Some(data) = frame.as_ref().and_then(|f| f.as_ref().ok()?.data_ref()) {
+            self.head.push(data);
+        }
+        Poll::Ready(frame)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+async fn write(file: &mut File, command: &Command) -> std::io::Result<()> {
+    let mut line = serde_json::to_vec(command)?;
+    line.push(b'\n');
+    file.write_all(&line).await
+}
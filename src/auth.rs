@@ -0,0 +1,269 @@
+use crate::capacity::{self, Permit};
+use crate::tenant::Tenant;
+use crate::w3c;
+use axum::extract::{Request, State};
+use axum::http::{HeaderMap, HeaderName, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use log::warn;
+use std::collections::HashMap;
+use std::path::Path;
+use std::sync::atomic::AtomicUsize;
+use std::sync::{Arc, RwLock};
+use subtle::ConstantTimeEq;
+
+/// Tokens clients have to present, each optionally limited to a number of concurrent sessions
+/// (Any client is accepted while there are none)
+pub struct Tokens {
+    quotas: RwLock<HashMap<String, Quota>>,
+    tenants: RwLock<Tenants>,
+}
+
+#[derive(Default)]
+struct Tenants {
+    by_token: HashMap<String, Arc<Tenant>>,
+    by_name: HashMap<String, Arc<Tenant>>,
+    /// Header naming the tenant of requests not carrying one of its tokens
+    header: Option<HeaderName>,
+}
+
+/// Sessions the token of a request may still create, attached to authenticated requests
+#[derive(Clone)]
+pub struct Quota {
+    sessions: Arc<AtomicUsize>,
+    max: Option<usize>,
+}
+
+impl Quota {
+    pub fn new(max: Option<usize>) -> Self {
+        Self {
+            sessions: Arc::default(),
+            max,
+        }
+    }
+
+    /// Reserves a session for the token, released once the returned permit is dropped
+    pub fn reserve(&self) -> Option<Permit> {
+        capacity::acquire(&self.sessions, self.max)
+    }
+}
+
+impl Tokens {
+    pub fn new(tokens: Vec<String>, sessions_per_token: Option<usize>) -> Self {
+        let accepted = Self {
+            quotas: RwLock::new(HashMap::new()),
+            tenants: RwLock::default(),
+        };
+        accepted.replace(tokens, sessions_per_token);
+        accepted
+    }
+
+    /// Swaps the accepted tokens, sessions held by tokens which remain keep counting towards their quota
+    pub fn replace(&self, tokens: Vec<String>, sessions_per_token: Option<usize>) {
+        let mut quotas = self.quotas.write().expect("Token lock poisoned");
+        *quotas = tokens
+            .into_iter()
+            .map(|token| {
+                let sessions = quotas
+                    .get(&token)
+                    .map(|quota| quota.sessions.clone())
+                    .unwrap_or_default();
+                let quota = Quota {
+                    sessions,
+                    max: sessions_per_token,
+                };
+                (token, quota)
+            })
+            .collect();
+    }
+
+    /// Swaps the tenants, sessions held by tenants which remain keep counting towards their quota
+    pub fn replace_tenants(&self, tenants: Vec<Tenant>, header: Option<HeaderName>) {
+        let mut current = self.tenants.write().expect("Token lock poisoned");
+        let mut replaced = Tenants {
+            header,
+            ..Tenants::default()
+        };
+        for mut tenant in tenants {
+            if let Some(previous) = current.by_name.get(&tenant.name) {
+                tenant.quota.sessions = previous.quota.sessions.clone();
+            }
+            let tenant = Arc::new(tenant);
+            for token in &tenant.tokens {
+                replaced.by_token.insert(token.clone(), tenant.clone());
+            }
+            replaced.by_name.insert(tenant.name.clone(), tenant);
+        }
+        *current = replaced;
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.quotas.read().expect("Token lock poisoned").is_empty()
+            && self
+                .tenants
+                .read()
+                .expect("Token lock poisoned")
+                .by_token
+                .is_empty()
+    }
+
+    /// Tenant of the presented token, otherwise the one named by the tenant header
+    pub fn tenant(&self, headers: &HeaderMap) -> Option<Arc<Tenant>> {
+        let tenants = self.tenants.read().expect("Token lock poisoned");
+        if let Some(tenant) = presented(headers).and_then(|token| tenants.by_token.get(&token)) {
+            return Some(tenant.clone());
+        }
+
+        let name = headers.get(tenants.header.as_ref()?)?.to_str().ok()?;
+        tenants.by_name.get(name).cloned()
+    }
+
+    pub fn tenant_named(&self, name: &str) -> Option<Arc<Tenant>> {
+        let tenants = self.tenants.read().expect("Token lock poisoned");
+        tenants.by_name.get(name).cloned()
+    }
+
+    /// Reads one token per line, skipping blank lines and `#` comments
+    pub fn read(path: &Path) -> std::io::Result<Vec<String>> {
+        Ok(std::fs::read_to_string(path)?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(String::from)
+            .collect())
+    }
+
+    fn quota(&self, headers: &HeaderMap) -> Option<Quota> {
+        let token = presented(headers)?;
+        if let Some(quota) = self
+            .quotas
+            .read()
+            .expect("Token lock poisoned")
+            .get(token.as_str())
+        {
+            return Some(quota.clone());
+        }
+
+        let tenants = self.tenants.read().expect("Token lock poisoned");
+        tenants
+            .by_token
+            .get(&token)
+            .map(|tenant| tenant.quota.clone())
+    }
+}
+
+/// Token of a `Bearer` or, as WebDriver clients embed credentials in URLs, `Basic` header (as password)
+fn presented(headers: &HeaderMap) -> Option<String> {
+    let value = headers.get(header::AUTHORIZATION)?.to_str().ok()?;
+    if let Some(token) = value.strip_prefix("Bearer ") {
+        return Some(token.to_owned());
+    }
+
+    let credentials = STANDARD.decode(value.strip_prefix("Basic ")?).ok()?;
+    let credentials = String::from_utf8(credentials).ok()?;
+    let (_, password) = credentials.split_once(':')?;
+    Some(password.to_owned())
+}
+
+/// Whether `headers` carry `Authorization: Bearer <token>`, compared in constant time
+pub fn bearer(headers: &HeaderMap, token: &str) -> bool {
+    headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|presented| presented.as_bytes().ct_eq(token.as_bytes()).into())
+}
+
+pub async fn authenticate(
+    State(tokens): State<Arc<Tokens>>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, Response> {
+    let quota = tokens.quota(request.headers());
+    if quota.is_none() && !tokens.is_empty() {
+        warn!(
+            "Rejected unauthenticated request to {}",
+            request.uri().path()
+        );
+        return Err(unauthorized("Missing or unknown token"));
+    }
+
+    // A tenant's quota takes the place of that of the token
+    match tokens.tenant(request.headers()) {
+        Some(tenant) => {
+            request.extensions_mut().insert(tenant.quota.clone());
+            request.extensions_mut().insert(tenant);
+        }
+        None => {
+            if let Some(quota) = quota {
+                request.extensions_mut().insert(quota);
+            }
+        }
+    }
+    Ok(next.run(request).await)
+}
+
+/// Answers a request lacking the bearer token it needs
+pub fn unauthorized(message: &str) -> Response {
+    (
+        [(header::WWW_AUTHENTICATE, "Bearer")],
+        w3c::error(StatusCode::UNAUTHORIZED, w3c::UNKNOWN_ERROR, message),
+    )
+        .into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::HeaderValue;
+
+    fn headers(authorization: &str) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_str(authorization).unwrap(),
+        );
+        headers
+    }
+
+    #[test]
+    fn accepts_bearer_and_basic_credentials() {
+        let tokens = Tokens::new(vec![String::from("secret")], None);
+
+        assert!(tokens.quota(&headers("Bearer secret")).is_some());
+        assert!(tokens.quota(&headers("Basic dXNlcjpzZWNyZXQ=")).is_some());
+        assert!(tokens.quota(&headers("Bearer other")).is_none());
+        assert!(tokens.quota(&HeaderMap::new()).is_none());
+
+        assert!(bearer(&headers("Bearer secret"), "secret"));
+        assert!(!bearer(&headers("Bearer secret2"), "secret"));
+        assert!(!bearer(&headers("Basic dXNlcjpzZWNyZXQ="), "secret"));
+    }
+
+    #[test]
+    fn limits_sessions_per_token() {
+        let tokens = Tokens::new(vec![String::from("a"), String::from("b")], Some(1));
+        let a = tokens.quota(&headers("Bearer a")).unwrap();
+        let b = tokens.quota(&headers("Bearer b")).unwrap();
+
+        let permit = a.reserve().unwrap();
+        assert!(a.reserve().is_none());
+        assert!(b.reserve().is_some());
+
+        drop(permit);
+        assert!(a.reserve().is_some());
+    }
+
+    #[test]
+    fn keeps_sessions_of_remaining_tokens() {
+        let tokens = Tokens::new(vec![String::from("a"), String::from("b")], Some(1));
+        let _permit = tokens.quota(&headers("Bearer a")).unwrap().reserve();
+
+        tokens.replace(vec![String::from("a")], Some(1));
+        let a = tokens.quota(&headers("Bearer a")).unwrap();
+        assert!(a.reserve().is_none());
+        assert!(tokens.quota(&headers("Bearer b")).is_none());
+    }
+}
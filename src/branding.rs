@@ -0,0 +1,86 @@
+use axum::extract::{Request, State};
+use axum::http::{HeaderValue, Version, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::sync::Arc;
+
+/// Response headers of WebDrivers which tell what (and which version) is answering, not passed on to clients
+pub const IDENTIFYING: [&str; 3] = ["server", "via", "x-powered-by"];
+
+/// Headers naming this instance in its responses, see `--server-header` and `--via`
+pub struct Branding {
+    pub server: Option<HeaderValue>,
+    /// Pseudonym of this instance in `Via`, e.g. `1.1 sessiondriver`
+    pub via: Option<String>,
+}
+
+impl Branding {
+    pub fn is_empty(&self) -> bool {
+        self.server.is_none() && self.via.is_none()
+    }
+}
+
+pub async fn brand(
+    State(branding): State<Arc<Branding>>,
+    request: Request,
+    next: Next,
+) -> Response {
+    let version = protocol(request.version());
+    let mut response = next.run(request).await;
+
+    let headers = response.headers_mut();
+    if let Some(server) = &branding.server {
+        headers.insert(header::SERVER, server.clone());
+    }
+    if let Some(via) = &branding.via
+        && let Ok(via) = HeaderValue::try_from(format!("{} {}", version, via))
+    {
+        headers.insert(header::VIA, via);
+    }
+
+    response
+}
+
+/// The protocol a request was received with, as given in `Via`
+fn protocol(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "0.9",
+        Version::HTTP_10 => "1.0",
+        Version::HTTP_2 => "2",
+        Version::HTTP_3 => "3",
+        _ => "1.1",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Router;
+    use axum::middleware;
+    use axum::routing::get;
+    use tokio::net::TcpListener;
+
+    #[tokio::test]
+    async fn names_this_instance() {
+        let branding = Branding {
+            server: Some(HeaderValue::from_static("grid")),
+            via: Some(String::from("sessiondriver")),
+        };
+        let app = Router::new()
+            .route(
+                "/status",
+                get(|| async { ([(header::SERVER, "geckodriver")], "") }),
+            )
+            .layer(middleware::from_fn_with_state(Arc::new(branding), brand));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let response = reqwest::get(format!("http://{}/status", address))
+            .await
+            .unwrap();
+        let headers = response.headers();
+        assert_eq!(headers[header::SERVER], "grid");
+        assert_eq!(headers[header::VIA], "1.1 sessiondriver");
+    }
+}
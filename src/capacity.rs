@@ -0,0 +1,236 @@
+use serde_json::Value;
+use std::fmt;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+/// Marks `usize::MAX` as the absence of a limit
+const UNLIMITED: usize = usize::MAX;
+
+/// Tracks how many more sessions this instance is willing to accept
+pub struct Capacity {
+    max_sessions: AtomicUsize,
+    reserved: Arc<AtomicUsize>,
+    draining: AtomicBool,
+}
+
+pub enum Reservation {
+    Granted(Permit),
+    Exhausted,
+    Draining,
+}
+
+/// A reserved session, released once dropped
+pub struct Permit(Arc<AtomicUsize>);
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Increments `reserved` unless doing so would exceed `max`
+pub fn acquire(reserved: &Arc<AtomicUsize>, max: Option<usize>) -> Option<Permit> {
+    let max = max.unwrap_or(UNLIMITED);
+    reserved
+        .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |current| {
+            (current < max).then_some(current + 1)
+        })
+        .ok()
+        .map(|_| Permit(reserved.clone()))
+}
+
+/// Caps the concurrent sessions requesting a capability value, e.g. `browserName=chrome:5`
+#[derive(Debug, Clone)]
+pub struct Limit {
+    capability: String,
+    value: String,
+    max: usize,
+    reserved: Arc<AtomicUsize>,
+}
+
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}={}", self.capability, self.value)
+    }
+}
+
+impl Limit {
+    /// Whether a session created for `requested` (`{ "capabilities": ... }`) may have the capability value
+    fn matches(&self, requested: &Value) -> bool {
+        let always = requested["capabilities"]["alwaysMatch"].as_object();
+        let first = match requested["capabilities"]["firstMatch"].as_array() {
+            Some(first) if !first.is_empty() => first.iter().collect(),
+            _ => vec![&Value::Null],
+        };
+
+        first.into_iter().any(|first| {
+            let value = first
+                .get(&self.capability)
+                .or_else(|| always.and_then(|always| always.get(&self.capability)));
+            match value {
+                Some(Value::String(value)) => *value == self.value,
+                // e.g. `acceptInsecureCerts=true:2`
+                Some(value) => {
+                    serde_json::from_str::<Value>(&self.value).is_ok_and(|v| v == *value)
+                }
+                None => false,
+            }
+        })
+    }
+}
+
+/// Parses `--capability-limit`
+pub fn parse_limit(s: &str) -> Result<Limit, String> {
+    let invalid = || format!("Expected <capability>=<value>:<max>, got {:?}", s);
+    let (capability, rest) = s.split_once('=').ok_or_else(invalid)?;
+    let (value, max) = rest.rsplit_once(':').ok_or_else(invalid)?;
+
+    Ok(Limit {
+        capability: capability.to_owned(),
+        value: value.to_owned(),
+        max: max.parse().map_err(|_| invalid())?,
+        reserved: Arc::default(),
+    })
+}
+
+/// Reserves a slot of every limit `requested` may fall under, otherwise returns the first one exhausted
+///
+/// A request whose `firstMatch` entries name several values counts towards each of their limits.
+pub fn reserve_limits<'a>(
+    limits: &'a [Limit],
+    requested: &Value,
+) -> Result<Vec<Permit>, &'a Limit> {
+    limits
+        .iter()
+        .filter(|limit| limit.matches(requested))
+        .map(|limit| acquire(&limit.reserved, Some(limit.max)).ok_or(limit))
+        .collect()
+}
+
+impl Capacity {
+    pub fn new(max_sessions: Option<usize>) -> Self {
+        Self {
+            max_sessions: AtomicUsize::new(max_sessions.unwrap_or(UNLIMITED)),
+            reserved: Arc::new(AtomicUsize::new(0)),
+            draining: AtomicBool::new(false),
+        }
+    }
+
+    pub fn max_sessions(&self) -> Option<usize> {
+        Some(self.max_sessions.load(Ordering::SeqCst)).filter(|&max| max != UNLIMITED)
+    }
+
+    /// Changes the limit, sessions above a lowered limit are kept until they end
+    pub fn set_max_sessions(&self, max_sessions: Option<usize>) {
+        self.max_sessions
+            .store(max_sessions.unwrap_or(UNLIMITED), Ordering::SeqCst);
+    }
+
+    /// Reserves a slot for a new session, released once the returned permit is dropped
+    pub fn reserve(&self) -> Reservation {
+        if self.is_draining() {
+            return Reservation::Draining;
+        }
+
+        match acquire(&self.reserved, self.max_sessions()) {
+            Some(permit) => Reservation::Granted(permit),
+            None => Reservation::Exhausted,
+        }
+    }
+
+    pub fn drain(&self) {
+        self.draining.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::SeqCst)
+    }
+
+    /// Readiness and message reported by `GET /status`
+    pub fn status(&self, active: usize) -> (bool, String) {
+        if self.is_draining() {
+            return (false, String::from("Draining"));
+        }
+
+        match self.max_sessions() {
+            Some(max) => {
+                let ready = self.reserved.load(Ordering::SeqCst) < max;
+                let message = if ready {
+                    format!("{}/{} sessions", active, max)
+                } else {
+                    format!("At capacity ({}/{} sessions)", active, max)
+                };
+                (ready, message)
+            }
+            None => (true, format!("{} sessions", active)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn exhausts_and_releases_slots() {
+        let capacity = Capacity::new(Some(1));
+
+        let permit = match capacity.reserve() {
+            Reservation::Granted(permit) => permit,
+            _ => panic!("first slot must be granted"),
+        };
+        assert!(matches!(capacity.reserve(), Reservation::Exhausted));
+        assert!(!capacity.status(1).0);
+
+        drop(permit);
+        assert!(matches!(capacity.reserve(), Reservation::Granted(_)));
+        assert!(capacity.status(0).0);
+    }
+
+    #[test]
+    fn keeps_sessions_above_lowered_limit() {
+        let capacity = Capacity::new(Some(2));
+        let first = capacity.reserve();
+        let second = capacity.reserve();
+
+        capacity.set_max_sessions(Some(1));
+        assert!(matches!(capacity.reserve(), Reservation::Exhausted));
+        drop(first);
+        assert!(matches!(capacity.reserve(), Reservation::Exhausted));
+        drop(second);
+        assert!(matches!(capacity.reserve(), Reservation::Granted(_)));
+
+        capacity.set_max_sessions(None);
+        assert_eq!(capacity.status(5), (true, String::from("5 sessions")));
+    }
+
+    #[test]
+    fn limits_sessions_by_capability() {
+        let limits = vec![parse_limit("browserName=chrome:1").unwrap()];
+        let chrome =
+            serde_json::json!({ "capabilities": { "alwaysMatch": { "browserName": "chrome" } } });
+        let either = serde_json::json!({ "capabilities": {
+            "firstMatch": [{ "browserName": "firefox" }, { "browserName": "chrome" }]
+        }});
+        let firefox =
+            serde_json::json!({ "capabilities": { "firstMatch": [{ "browserName": "firefox" }] } });
+
+        let permits = reserve_limits(&limits, &chrome).unwrap();
+        assert_eq!(permits.len(), 1);
+        assert!(reserve_limits(&limits, &either).is_err());
+        assert!(reserve_limits(&limits, &firefox).unwrap().is_empty());
+        drop(permits);
+        assert!(reserve_limits(&limits, &either).is_ok());
+        assert!(parse_limit("browserName=chrome").is_err());
+    }
+
+    #[test]
+    fn rejects_while_draining() {
+        let capacity = Capacity::new(None);
+        assert!(capacity.status(0).0);
+
+        capacity.drain();
+        assert!(matches!(capacity.reserve(), Reservation::Draining));
+        assert_eq!(capacity.status(0), (false, String::from("Draining")));
+    }
+}
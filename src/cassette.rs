@@ -0,0 +1,113 @@
+use async_lock::Mutex;
+use axum::http::Method;
+use log::warn;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use tokio::fs::File;
+use tokio::io::AsyncWriteExt;
+
+/// Names the cassette a session is recorded to (see `--cassette-dir`) or replayed from (see `--replay-dir`)
+pub const CAPABILITY: &str = "sessiondriver:cassette";
+
+/// A request of a session along with the WebDriver's answer
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub method: String,
+    /// Below the session, e.g. `/url`, or `/session` for the new-session request
+    pub path: String,
+    pub body: Value,
+    pub status: u16,
+    pub response: Value,
+    /// Fault injected into the client's answer, which `status` and `response` are not (see `--allow-chaos`)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub fault: Option<String>,
+}
+
+impl Interaction {
+    pub fn new(method: &Method, path: &str, body: &[u8], status: u16, response: &[u8]) -> Self {
+        Self {
+            method: method.to_string(),
+            path: path.to_owned(),
+            body: json(body),
+            status,
+            response: json(response),
+            fault: None,
+        }
+    }
+}
+
+/// Bodies WebDrivers exchange are JSON, anything else (e.g. an empty body) is kept as a string
+fn json(bytes: &[u8]) -> Value {
+    serde_json::from_slice(bytes)
+        .unwrap_or_else(|_| Value::String(String::from_utf8_lossy(bytes).into_owned()))
+}
+
+/// The cassette asked for by new-session capabilities (`{ "capabilities": ... }`), the first of `firstMatch` if several
+pub fn requested(requested: &Value) -> Result<Option<String>, String> {
+    let capabilities = &requested["capabilities"];
+    let name = std::iter::once(&capabilities["alwaysMatch"])
+        .chain(capabilities["firstMatch"].as_array().into_iter().flatten())
+        .find_map(|set| set.get(CAPABILITY));
+    let Some(name) = name else {
+        return Ok(None);
+    };
+
+    // Names a file within the directory
+    match name.as_str() {
+        Some(name)
+            if !name.is_empty()
+                && !name.starts_with('.')
+                && name
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.')) =>
+        {
+            Ok(Some(name.to_owned()))
+        }
+        _ => Err(format!(
+            "Invalid {}: Expected a name of letters, digits, '-', '_' and '.'",
+            CAPABILITY
+        )),
+    }
+}
+
+pub fn path(directory: &Path, name: &str) -> PathBuf {
+    directory.join(format!("{}.jsonl", name))
+}
+
+/// Records the interactions of a session to `<directory>/<name>.jsonl`, replacing an earlier recording
+pub struct Cassette {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl Cassette {
+    pub async fn create(directory: &Path, name: &str) -> std::io::Result<Self> {
+        tokio::fs::create_dir_all(directory).await?;
+        let path = path(directory, name);
+        let file = File::create(&path).await?;
+
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+        })
+    }
+
+    pub async fn record(&self, interaction: &Interaction) {
+        let mut line = serde_json::to_vec(interaction).expect("JSON from JSON");
+        line.push(b'\n');
+        if let Err(e) = self.file.lock().await.write_all(&line).await {
+            warn!("Unable to write cassette {:?}: {}", self.path, e);
+        }
+    }
+}
+
+/// Reads what has been recorded by a [`Cassette`]
+pub async fn read(directory: &Path, name: &str) -> std::io::Result<Vec<Interaction>> {
+    let content = tokio::fs::read_to_string(path(directory, name)).await?;
+    content
+        .lines()
+        .filter(|line| !line.trim().is_empty())
+        .map(|line| serde_json::from_str(line).map_err(std::io::Error::other))
+        .collect()
+}
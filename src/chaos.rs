@@ -0,0 +1,149 @@
+use axum::body::Bytes;
+use hyper::body::{Body, Frame};
+use serde::Deserialize;
+use serde_json::Value;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+use uuid::Uuid;
+
+pub const CAPABILITY: &str = "sessiondriver:chaos";
+
+/// Faults injected into a fraction of a session's responses, once its WebDriver has answered (see `--allow-chaos`)
+///
+/// Requested as e.g. `"sessiondriver:chaos": { "delay": 0.1, "maxDelay": 2000, "drop": 0.01, "error": 0.05 }`, at most
+/// one fault being injected per response.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(rename_all = "camelCase", deny_unknown_fields)]
+pub struct Chaos {
+    /// Fraction of responses held back for up to `max_delay`
+    #[serde(default)]
+    pub delay: f64,
+    /// In milliseconds
+    #[serde(default = "default_max_delay")]
+    pub max_delay: u64,
+    /// Fraction of responses cut off, such that clients see the connection fail
+    #[serde(default)]
+    pub drop: f64,
+    /// Fraction of responses replaced by `500 Internal Server Error`
+    #[serde(default)]
+    pub error: f64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Fault {
+    Delay(Duration),
+    Drop,
+    Error,
+}
+
+impl Fault {
+    /// How the fault is noted in cassettes, see [`crate::cassette::Interaction::fault`]
+    pub fn name(&self) -> &'static str {
+        match self {
+            Fault::Delay(_) => "delay",
+            Fault::Drop => "drop",
+            Fault::Error => "error",
+        }
+    }
+}
+
+fn default_max_delay() -> u64 {
+    5000
+}
+
+impl Chaos {
+    /// Faults asked for by new-session capabilities (`{ "capabilities": ... }`), the first of `firstMatch` if several
+    pub fn requested(requested: &Value) -> Result<Option<Self>, String> {
+        let capabilities = &requested["capabilities"];
+        let value = std::iter::once(&capabilities["alwaysMatch"])
+            .chain(capabilities["firstMatch"].as_array().into_iter().flatten())
+            .find_map(|set| set.get(CAPABILITY));
+        let Some(value) = value else {
+            return Ok(None);
+        };
+
+        let chaos =
+            Self::deserialize(value).map_err(|e| format!("Invalid {}: {}", CAPABILITY, e))?;
+        let fractions = [chaos.delay, chaos.drop, chaos.error];
+        if fractions.iter().any(|f| !(0.0..=1.0).contains(f)) || fractions.iter().sum::<f64>() > 1.0
+        {
+            return Err(format!(
+                "Invalid {}: Fractions are to be between 0 and 1 and add up to at most 1",
+                CAPABILITY
+            ));
+        }
+
+        Ok(Some(chaos))
+    }
+
+    /// Decides which fault, if any, to inject into a response
+    pub fn fault(&self) -> Option<Fault> {
+        self.pick(random(), random())
+    }
+
+    fn pick(&self, roll: f64, delay: f64) -> Option<Fault> {
+        if roll < self.drop {
+            Some(Fault::Drop)
+        } else if roll < self.drop + self.error {
+            Some(Fault::Error)
+        } else if roll < self.drop + self.error + self.delay {
+            Some(Fault::Delay(Duration::from_millis(
+                (delay * self.max_delay as f64) as u64,
+            )))
+        } else {
+            None
+        }
+    }
+}
+
+/// Body failing right away, so that the connection breaks once the status and headers have been sent
+pub struct Dropped;
+
+impl Body for Dropped {
+    type Data = Bytes;
+    type Error = std::io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        _: &mut Context<'_>,
+    ) -> Poll<Option<Result<Frame<Self::Data>, Self::Error>>> {
+        Poll::Ready(Some(Err(std::io::Error::other(format!(
+            "Dropped by {}",
+            CAPABILITY
+        )))))
+    }
+}
+
+/// Uniformly distributed in `[0, 1)`, taken from the random bits of a UUID rather than pulling in a generator
+fn random() -> f64 {
+    // The upper 48 bits precede the version and variant
+    (Uuid::new_v4().as_u128() >> 80) as f64 / (1u64 << 48) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn injects_requested_faults() {
+        let requested = json!({
+            "capabilities": {
+                "firstMatch": [{ CAPABILITY: { "drop": 0.1, "error": 0.2, "delay": 0.3, "maxDelay": 1000 } }]
+            }
+        });
+        let chaos = Chaos::requested(&requested).unwrap().unwrap();
+        assert_eq!(chaos.pick(0.05, 0.0), Some(Fault::Drop));
+        assert_eq!(chaos.pick(0.25, 0.0), Some(Fault::Error));
+        assert_eq!(
+            chaos.pick(0.5, 0.5),
+            Some(Fault::Delay(Duration::from_millis(500)))
+        );
+        assert_eq!(chaos.pick(0.7, 0.0), None);
+
+        let excessive = json!({ "capabilities": { "alwaysMatch": { CAPABILITY: { "error": 0.8, "drop": 0.4 } } } });
+        assert!(Chaos::requested(&excessive).is_err());
+        assert_eq!(Chaos::requested(&json!({ "capabilities": {} })), Ok(None));
+    }
+}
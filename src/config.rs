@@ -0,0 +1,111 @@
+use clap::{Command, CommandFactory, FromArgMatches};
+use std::path::PathBuf;
+
+/// Parses arguments, falling back to the TOML file given by `--config` where neither a flag nor an
+/// environment variable is set
+pub fn parse<A: CommandFactory + FromArgMatches>() -> Result<A, Box<dyn std::error::Error>> {
+    let matches = command::<A>()?.get_matches();
+    A::from_arg_matches(&matches).map_err(|e| e.exit())
+}
+
+/// Parses arguments again, reading the current contents of the configuration file
+pub fn reload<A: CommandFactory + FromArgMatches>() -> Result<A, Box<dyn std::error::Error>> {
+    let matches = command::<A>()?.try_get_matches()?;
+    Ok(A::from_arg_matches(&matches)?)
+}
+
+fn command<A: CommandFactory>() -> Result<Command, Box<dyn std::error::Error>> {
+    let mut command = A::command();
+
+    let path = command
+        .clone()
+        .ignore_errors(true)
+        .get_matches()
+        .get_one::<PathBuf>("config")
+        .cloned();
+    if let Some(path) = path {
+        let table: toml::Table = toml::from_str(&std::fs::read_to_string(&path)?)
+            .map_err(|e| format!("Unable to parse {:?}: {}", path, e))?;
+        command = with_defaults(command, table).map_err(|e| format!("{} in {:?}", e, path))?;
+    }
+
+    Ok(command)
+}
+
+/// Makes every key of `table` (e.g. `max-sessions` or `max_sessions`) the default of its argument
+fn with_defaults(mut command: Command, table: toml::Table) -> Result<Command, String> {
+    for (key, value) in table {
+        let id = key.replace('-', "_");
+        if id == "config"
+            || !command
+                .get_arguments()
+                .any(|arg| arg.get_id() == id.as_str())
+        {
+            return Err(format!("Unknown option {:?}", key));
+        }
+
+        let values = match value {
+            toml::Value::Array(values) => values
+                .into_iter()
+                .map(|value| scalar(&key, value))
+                .collect::<Result<Vec<_>, _>>()?,
+            value => vec![scalar(&key, value)?],
+        };
+        command = command.mut_arg(id, |arg| arg.default_values(values).required(false));
+    }
+
+    Ok(command)
+}
+
+fn scalar(key: &str, value: toml::Value) -> Result<String, String> {
+    match value {
+        toml::Value::String(value) => Ok(value),
+        toml::Value::Integer(value) => Ok(value.to_string()),
+        toml::Value::Float(value) => Ok(value.to_string()),
+        toml::Value::Boolean(value) => Ok(value.to_string()),
+        _ => Err(format!("Unsupported value of {:?}", key)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clap::{Arg, ArgAction};
+
+    fn command() -> Command {
+        Command::new("test")
+            .arg(Arg::new("config").long("config"))
+            .arg(Arg::new("port").long("port").default_value("4444"))
+            .arg(Arg::new("max_sessions").long("max-sessions"))
+            .arg(
+                Arg::new("auth_token")
+                    .long("auth-token")
+                    .action(ArgAction::Append),
+            )
+    }
+
+    #[test]
+    fn arguments_override_file() {
+        let table =
+            toml::from_str("port = 5000\nmax-sessions = 4\nauth_token = ['a', 'b']").unwrap();
+        let command = with_defaults(command(), table).unwrap();
+
+        let matches = command
+            .clone()
+            .try_get_matches_from(["test", "--port", "6000"])
+            .unwrap();
+        assert_eq!(matches.get_one::<String>("port").unwrap(), "6000");
+        assert_eq!(matches.get_one::<String>("max_sessions").unwrap(), "4");
+        let tokens: Vec<_> = matches.get_many::<String>("auth_token").unwrap().collect();
+        assert_eq!(tokens, ["a", "b"]);
+
+        let matches = command.try_get_matches_from(["test"]).unwrap();
+        assert_eq!(matches.get_one::<String>("port").unwrap(), "5000");
+    }
+
+    #[test]
+    fn rejects_unknown_options() {
+        let table = toml::from_str("prot = 5000").unwrap();
+        assert!(with_defaults(command(), table).is_err());
+    }
+}
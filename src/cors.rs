@@ -0,0 +1,25 @@
+use axum::http::{HeaderName, HeaderValue, Method};
+use std::time::Duration;
+use tower_http::cors::{AllowOrigin, Any, CorsLayer};
+
+/// Lets pages served from `origins` (`*` for any) use SessionDriver, answering their preflight requests
+pub fn layer(
+    origins: Vec<HeaderValue>,
+    methods: Vec<Method>,
+    headers: Vec<HeaderName>,
+    max_age: Duration,
+) -> CorsLayer {
+    let origins = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        AllowOrigin::list(origins)
+    };
+
+    CorsLayer::new()
+        .allow_origin(origins)
+        .allow_methods(methods)
+        .allow_headers(headers)
+        // e.g. X-SessionDriver-Node and Retry-After
+        .expose_headers(Any)
+        .max_age(max_age)
+}
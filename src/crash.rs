@@ -0,0 +1,147 @@
+use crate::output::{DriverOutput, Stream};
+use async_lock::Mutex;
+use log::{info, warn};
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::process::ExitStatus;
+use std::time::{SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Number of crashes remembered to answer further requests of their sessions with
+const REMEMBERED: usize = 1000;
+
+/// Keeps what is left of WebDrivers that exited on their own in `<directory>/<session>/`
+pub struct CrashArchive {
+    pub directory: PathBuf,
+    /// Bytes of the WebDriver's most recent output kept
+    pub output_limit: usize,
+    /// Whether WebDrivers may dump core, which is then moved along (see `--crash-core-dumps`)
+    pub core_dumps: bool,
+    recent: Mutex<VecDeque<(Uuid, Crash)>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Crash {
+    pub status: String,
+    pub artifacts: PathBuf,
+}
+
+impl CrashArchive {
+    pub fn new(directory: PathBuf, output_limit: usize, core_dumps: bool) -> Self {
+        Self {
+            directory,
+            output_limit,
+            core_dumps,
+            recent: Mutex::new(VecDeque::new()),
+        }
+    }
+
+    /// Writes the WebDriver's exit status (`exit.json`), recent output (`output.log`) and core dump if there is one
+    pub async fn collect(
+        &self,
+        session: Uuid,
+        status: ExitStatus,
+        pid: Option<u32>,
+        output: &DriverOutput,
+        capabilities: &serde_json::Value,
+    ) {
+        let artifacts = self.directory.join(session.to_string());
+        if let Err(e) = self
+            .write(&artifacts, status, pid, output, capabilities)
+            .await
+        {
+            warn!("Unable to collect crash of {:?}: {}", session, e);
+        } else {
+            info!("Collected crash of {:?} in {:?}", session, artifacts);
+        }
+
+        let mut recent = self.recent.lock().await;
+        if recent.len() == REMEMBERED {
+            recent.pop_front();
+        }
+        recent.push_back((
+            session,
+            Crash {
+                status: status.to_string(),
+                artifacts,
+            },
+        ));
+    }
+
+    async fn write(
+        &self,
+        artifacts: &Path,
+        status: ExitStatus,
+        pid: Option<u32>,
+        output: &DriverOutput,
+        capabilities: &serde_json::Value,
+    ) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(artifacts).await?;
+
+        #[cfg(unix)]
+        let (signal, core_dumped) = {
+            use std::os::unix::process::ExitStatusExt;
+            (status.signal(), status.core_dumped())
+        };
+        #[cfg(not(unix))]
+        let (signal, core_dumped) = (None::<i32>, false);
+        let exit = serde_json::json!({
+            "status": status.to_string(),
+            "code": status.code(),
+            "signal": signal,
+            "pid": pid,
+            "timestamp": SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis() as u64)
+                .unwrap_or_default(),
+            "capabilities": capabilities,
+        });
+        tokio::fs::write(artifacts.join("exit.json"), exit.to_string()).await?;
+
+        let mut log = String::new();
+        for line in output.lines() {
+            let stream = match line.stream {
+                Stream::Stdout => "stdout",
+                Stream::Stderr => "stderr",
+            };
+            log.push_str(&format!("{} {} {}\n", line.timestamp, stream, line.message));
+        }
+        // Cut at a line, so the kept output starts cleanly
+        if log.len() > self.output_limit {
+            let mut cut = log.len() - self.output_limit;
+            while !log.is_char_boundary(cut) {
+                cut += 1;
+            }
+            let cut = log[cut..].find('\n').map_or(log.len(), |i| cut + i + 1);
+            log.drain(..cut);
+        }
+        tokio::fs::write(artifacts.join("output.log"), log).await?;
+
+        if self.core_dumps && core_dumped {
+            // Where the kernel writes them given a relative core_pattern such as "core" or "core.%p"
+            let candidates = pid
+                .map(|pid| format!("core.{}", pid))
+                .into_iter()
+                .chain([String::from("core")]);
+            for candidate in candidates {
+                if tokio::fs::rename(&candidate, artifacts.join(&candidate))
+                    .await
+                    .is_ok()
+                {
+                    break;
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    pub async fn lookup(&self, session: Uuid) -> Option<Crash> {
+        self.recent
+            .lock()
+            .await
+            .iter()
+            .find(|(crashed, _)| *crashed == session)
+            .map(|(_, crash)| crash.clone())
+    }
+}
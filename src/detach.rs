@@ -0,0 +1,33 @@
+use deadpool::managed::{self, Object};
+
+/// Takes a session out of its pool for good, e.g. for a long interactive debugging session
+///
+/// The session is never recycled and no longer counts towards the pool's size, so that the pool creates a replacement
+/// once a session is next asked for. Closing it (see [`Client::close`]) is up to the caller, as is keeping it from
+/// expiring at SessionDriver in the meantime.
+///
+/// [`Client::close`]: fantoccini::Client::close
+pub fn detach<M: managed::Manager>(object: Object<M>) -> M::Type {
+    Object::take(object)
+}
+
+#[cfg(all(test, feature = "testing"))]
+mod tests {
+    use super::*;
+    use crate::testing::{MockManager, MockPool};
+
+    #[tokio::test]
+    async fn replaces_detached_sessions() {
+        let pool = MockPool::builder(MockManager::new())
+            .max_size(1)
+            .build()
+            .unwrap();
+
+        let detached = detach(pool.get().await.unwrap());
+        assert_eq!(pool.status().size, 0);
+
+        let replacement = pool.get().await.unwrap();
+        assert_ne!(replacement.id, detached.id);
+        assert_eq!(pool.manager().created(), 2);
+    }
+}
@@ -0,0 +1,57 @@
+use fantoccini::Client;
+use log::{info, warn};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::time::timeout;
+
+/// Time an unhealthy session is given to answer all of the commands collecting its diagnostics
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Writes the current URL, page source and a screenshot of a session which failed to be recycled to `directory`, as
+/// `<session>-<milliseconds since the Unix epoch>.{url,html,png}`, see [`crate::Config::diagnostics_dir`]
+///
+/// The session is not navigated anywhere. Each is collected on its own, as an unhealthy session may fail some.
+pub(crate) async fn capture(client: &Client, directory: &Path) {
+    if let Err(e) = tokio::fs::create_dir_all(directory).await {
+        warn!(
+            "Unable to create diagnostics directory {:?}: {}",
+            directory, e
+        );
+        return;
+    }
+
+    let collected = async {
+        let session = match client.session_id().await {
+            Ok(Some(session)) => session,
+            _ => String::from("unknown"),
+        };
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis())
+            .unwrap_or_default();
+        let stem = directory.join(format!("{}-{}", session, timestamp));
+
+        match client.current_url().await {
+            Ok(url) => write(&stem.with_extension("url"), url.as_str()).await,
+            Err(e) => warn!("Unable to collect URL of {}: {}", session, e),
+        }
+        match client.source().await {
+            Ok(source) => write(&stem.with_extension("html"), source).await,
+            Err(e) => warn!("Unable to collect page source of {}: {}", session, e),
+        }
+        match client.screenshot().await {
+            Ok(png) => write(&stem.with_extension("png"), png).await,
+            Err(e) => warn!("Unable to take screenshot of {}: {}", session, e),
+        }
+        info!("Collected diagnostics of {} at {:?}", session, stem);
+    };
+    if timeout(TIMEOUT, collected).await.is_err() {
+        warn!("Gave up collecting diagnostics after {:?}", TIMEOUT);
+    }
+}
+
+async fn write(path: &Path, contents: impl AsRef<[u8]>) {
+    if let Err(e) = tokio::fs::write(path, contents).await {
+        warn!("Unable to write {:?}: {}", path, e);
+    }
+}
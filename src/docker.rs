@@ -0,0 +1,140 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::process::Stdio;
+use tokio::process::Command;
+use uuid::Uuid;
+
+/// Label attached to every container started, e.g. to find leftovers with `docker ps --filter label=sessiondriver`
+const LABEL: &str = "sessiondriver";
+
+/// Starts each WebDriver as a container of `image` instead of a bare process
+pub struct Docker {
+    pub binary: PathBuf,
+    pub image: String,
+    pub network: Option<String>,
+    pub shm_size: String,
+    /// Port the WebDriver listens on inside the container
+    pub port: u16,
+}
+
+/// A running container, removed through [`Container::remove`]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Container {
+    binary: PathBuf,
+    pub name: String,
+}
+
+impl Docker {
+    /// Pulls the image unless it is present already
+    pub async fn prepare(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let present = Command::new(&self.binary)
+            .args(["image", "inspect", &self.image])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await
+            .map_err(|e| format!("Unable to run {:?}: {}", self.binary, e))?;
+        if present.success() {
+            return Ok(());
+        }
+
+        info!("Pulling {}", self.image);
+        let pulled = Command::new(&self.binary)
+            .args(["pull", &self.image])
+            .stdout(Stdio::null())
+            .status()
+            .await?;
+        if !pulled.success() {
+            return Err(format!("Unable to pull {} ({})", self.image, pulled).into());
+        }
+
+        Ok(())
+    }
+
+    /// `docker run` in the foreground, so the container's output and exit are those of the returned command
+    pub fn command(&self, host: IpAddr, port: u16) -> (Command, Container) {
+        let name = format!("sessiondriver-{}", Uuid::new_v4());
+
+        let mut command = Command::new(&self.binary);
+        command
+            .args(["run", "--rm", "--init", "--name", &name])
+            .args(["--label", LABEL])
+            .arg(format!("--shm-size={}", self.shm_size))
+            // Bracketed if IPv6, e.g. [::1]:4445:4444
+            .arg(format!(
+                "--publish={}:{}",
+                SocketAddr::new(host, port),
+                self.port
+            ));
+        if let Some(network) = &self.network {
+            command.arg(format!("--network={}", network));
+        }
+        command
+            .arg(&self.image)
+            .arg(format!("--port={}", self.port))
+            .arg("--host=0.0.0.0");
+
+        let container = Container {
+            binary: self.binary.clone(),
+            name,
+        };
+        (command, container)
+    }
+}
+
+impl Container {
+    pub async fn remove(self) {
+        let removed = Command::new(&self.binary)
+            .args(["rm", "--force", &self.name])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await;
+        match removed {
+            Ok(status) if status.success() => info!("Removed container {}", self.name),
+            Ok(status) => warn!("Unable to remove container {} ({})", self.name, status),
+            Err(e) => warn!("Unable to remove container {}: {}", self.name, e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publishes_driver_port() {
+        let docker = Docker {
+            binary: PathBuf::from("docker"),
+            image: String::from("geckodriver:latest"),
+            network: Some(String::from("ci")),
+            shm_size: String::from("2g"),
+            port: 4444,
+        };
+
+        let (command, container) = docker.command("127.0.0.1".parse().unwrap(), 4445);
+        let args: Vec<_> = command
+            .as_std()
+            .get_args()
+            .map(|arg| arg.to_string_lossy().into_owned())
+            .collect();
+        assert!(container.name.starts_with("sessiondriver-"));
+        assert!(args.contains(&container.name));
+        assert!(args.contains(&String::from("--publish=127.0.0.1:4445:4444")));
+        assert!(args.contains(&String::from("--network=ci")));
+
+        let (command, _) = docker.command("::1".parse().unwrap(), 4445);
+        assert!(
+            command
+                .as_std()
+                .get_args()
+                .any(|arg| arg == "--publish=[::1]:4445:4444")
+        );
+        assert_eq!(
+            args[args.len() - 3..],
+            ["geckodriver:latest", "--port=4444", "--host=0.0.0.0"]
+        );
+    }
+}
@@ -0,0 +1,171 @@
+use crate::tenant::{self, Tenant};
+use crate::{AppState, Browsers, internal_server_error, w3c};
+use axum::Router;
+use axum::body::Body;
+use axum::extract::{Extension, Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::get;
+use log::{debug, warn};
+use serde::Serialize;
+use std::path::PathBuf;
+use std::sync::Arc;
+use tokio_util::io::ReaderStream;
+use uuid::Uuid;
+
+/// Suffixes of files browsers are still writing to
+const PARTIAL: [&str; 2] = [".part", ".crdownload"];
+
+/// Directory a single session's browser downloads files to, removed along with the session
+pub struct Downloads {
+    pub directory: PathBuf,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Download {
+    pub name: String,
+    pub size: u64,
+}
+
+impl Downloads {
+    /// A new directory within `root`, which is only created by [`Downloads::create`]
+    pub fn new(root: &std::path::Path) -> Self {
+        Self {
+            directory: root.join(Uuid::new_v4().to_string()),
+        }
+    }
+
+    pub async fn create(&self) -> std::io::Result<()> {
+        tokio::fs::create_dir_all(&self.directory).await
+    }
+
+    /// Completed downloads
+    pub async fn list(&self) -> std::io::Result<Vec<Download>> {
+        let mut downloads = Vec::new();
+        let mut entries = match tokio::fs::read_dir(&self.directory).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(downloads),
+            Err(e) => return Err(e),
+        };
+        while let Some(entry) = entries.next_entry().await? {
+            let metadata = entry.metadata().await?;
+            let name = entry.file_name().to_string_lossy().into_owned();
+            if !metadata.is_file() || PARTIAL.iter().any(|suffix| name.ends_with(suffix)) {
+                continue;
+            }
+            downloads.push(Download {
+                name,
+                size: metadata.len(),
+            });
+        }
+        downloads.sort_by(|a, b| a.name.cmp(&b.name));
+
+        Ok(downloads)
+    }
+
+    /// Path of a download, unless `name` points outside of the directory
+    pub fn path(&self, name: &str) -> Option<PathBuf> {
+        if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+            return None;
+        }
+
+        Some(self.directory.join(name))
+    }
+
+    pub async fn remove(self) {
+        match tokio::fs::remove_dir_all(&self.directory).await {
+            Ok(()) => debug!("Removed downloads {:?}", self.directory),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Unable to remove downloads {:?}: {}", self.directory, e),
+        }
+    }
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/session/{id}/sessiondriver/downloads", get(list))
+        .route("/session/{id}/sessiondriver/downloads/{name}", get(fetch))
+}
+
+async fn list(
+    State(browsers): State<Browsers>,
+    Path(id): Path<Uuid>,
+    tenant: Option<Extension<Arc<Tenant>>>,
+) -> Result<Response, Response> {
+    let tenant = tenant.as_ref().map(|Extension(tenant)| tenant.as_ref());
+    let shard = browsers.shard(&id).read().await;
+    let downloads = shard
+        .get(&id)
+        .filter(|b| tenant::owns(tenant, b))
+        .and_then(|b| b.downloads.as_ref());
+    let Some(downloads) = downloads else {
+        return Err(not_enabled(id));
+    };
+    let downloads = downloads.list().await.map_err(internal_server_error)?;
+
+    Ok(Json(serde_json::json!({ "value": downloads })).into_response())
+}
+
+async fn fetch(
+    State(browsers): State<Browsers>,
+    Path((id, name)): Path<(Uuid, String)>,
+    tenant: Option<Extension<Arc<Tenant>>>,
+) -> Result<Response, Response> {
+    let tenant = tenant.as_ref().map(|Extension(tenant)| tenant.as_ref());
+    let path = browsers
+        .shard(&id)
+        .read()
+        .await
+        .get(&id)
+        .filter(|b| tenant::owns(tenant, b))
+        .and_then(|b| b.downloads.as_ref())
+        .map(|downloads| downloads.path(&name));
+    let Some(path) = path else {
+        return Err(not_enabled(id));
+    };
+    let not_found = || {
+        w3c::error(
+            StatusCode::NOT_FOUND,
+            w3c::UNKNOWN_ERROR,
+            format!("No downloaded file named {:?}", name),
+        )
+    };
+    let path = path.ok_or_else(not_found)?;
+    let file = tokio::fs::File::open(&path)
+        .await
+        .map_err(|_| not_found())?;
+
+    Ok((
+        [("Content-Type", "application/octet-stream")],
+        Body::from_stream(ReaderStream::new(file)),
+    )
+        .into_response())
+}
+
+fn not_enabled(id: Uuid) -> Response {
+    w3c::error(
+        StatusCode::NOT_FOUND,
+        w3c::INVALID_SESSION_ID,
+        format!("No active session with ID {} keeping downloads", id),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stays_within_directory() {
+        let downloads = Downloads {
+            directory: PathBuf::from("/downloads/session"),
+        };
+
+        assert_eq!(
+            downloads.path("report.pdf"),
+            Some(PathBuf::from("/downloads/session/report.pdf"))
+        );
+        assert_eq!(downloads.path(".."), None);
+        assert_eq!(downloads.path("../other/report.pdf"), None);
+        assert_eq!(downloads.path(""), None);
+    }
+}
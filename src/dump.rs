@@ -0,0 +1,129 @@
+use async_lock::Mutex;
+use axum::http::{HeaderMap, Method, StatusCode};
+use log::warn;
+use serde_json::Value;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tokio::fs::{File, OpenOptions};
+use tokio::io::AsyncWriteExt;
+use uuid::Uuid;
+
+/// Vendor capability a session turns dumping on with
+pub const CAPABILITY: &str = "sessiondriver:debugDump";
+
+/// Requests and responses of a single session written to `<directory>/<session>.log` as they went over the wire
+pub struct DebugDump {
+    directory: PathBuf,
+    body_limit: usize,
+    /// What has been dumped before the session's ID was known
+    pending: Mutex<Vec<u8>>,
+    file: Mutex<Option<File>>,
+}
+
+impl DebugDump {
+    /// Whether new-session capabilities (`{ "capabilities": ... }`) ask for a dump
+    pub fn requested(requested: &Value) -> bool {
+        let capabilities = &requested["capabilities"];
+        capabilities["alwaysMatch"][CAPABILITY] == true
+            || capabilities["firstMatch"]
+                .as_array()
+                .is_some_and(|sets| sets.iter().any(|set| set[CAPABILITY] == true))
+    }
+
+    pub fn new(directory: &Path, body_limit: usize) -> Self {
+        Self {
+            directory: directory.to_path_buf(),
+            body_limit,
+            pending: Mutex::new(Vec::new()),
+            file: Mutex::new(None),
+        }
+    }
+
+    /// Starts writing to the session's file, beginning with what has been dumped so far
+    pub async fn attach(&self, session: Uuid) {
+        let path = self.directory.join(format!("{}.log", session));
+        let opened = async {
+            tokio::fs::create_dir_all(&self.directory).await?;
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path)
+                .await?;
+            file.write_all(&std::mem::take(&mut *self.pending.lock().await))
+                .await?;
+            Ok::<_, std::io::Error>(file)
+        };
+        match opened.await {
+            Ok(file) => *self.file.lock().await = Some(file),
+            Err(e) => warn!("Unable to open debug dump {:?}: {}", path, e),
+        }
+    }
+
+    pub async fn request(&self, method: &Method, path: &str, headers: &HeaderMap, body: &[u8]) {
+        let head = format!(">>> {} {} {}", timestamp(), method, path);
+        self.write(head, headers, body).await;
+    }
+
+    pub async fn response(
+        &self,
+        status: StatusCode,
+        headers: &HeaderMap,
+        body: &[u8],
+        latency: Duration,
+    ) {
+        let head = format!(
+            "<<< {} {} ({} ms)",
+            timestamp(),
+            status,
+            latency.as_millis()
+        );
+        self.write(head, headers, body).await;
+    }
+
+    async fn write(&self, head: String, headers: &HeaderMap, body: &[u8]) {
+        let mut text = head.into_bytes();
+        text.push(b'\n');
+        for (name, value) in headers {
+            text.extend_from_slice(name.as_str().as_bytes());
+            text.extend_from_slice(b": ");
+            text.extend_from_slice(value.as_bytes());
+            text.push(b'\n');
+        }
+        text.push(b'\n');
+        text.extend_from_slice(&body[..body.len().min(self.body_limit)]);
+        if body.len() > self.body_limit {
+            let truncated = format!("\n[{} bytes truncated]", body.len() - self.body_limit);
+            text.extend_from_slice(truncated.as_bytes());
+        }
+        text.extend_from_slice(b"\n\n");
+
+        let mut file = self.file.lock().await;
+        match file.as_mut() {
+            Some(file) => {
+                if let Err(e) = file.write_all(&text).await {
+                    warn!("Unable to write debug dump: {}", e);
+                }
+            }
+            None => self.pending.lock().await.extend_from_slice(&text),
+        }
+    }
+}
+
+fn timestamp() -> String {
+    humantime::format_rfc3339_millis(SystemTime::now()).to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn is_requested_by_capability() {
+        let requested =
+            serde_json::json!({ "capabilities": { "firstMatch": [{}, { CAPABILITY: true }] } });
+        assert!(DebugDump::requested(&requested));
+        let requested =
+            serde_json::json!({ "capabilities": { "alwaysMatch": { CAPABILITY: "yes" } } });
+        assert!(!DebugDump::requested(&requested));
+    }
+}
@@ -0,0 +1,109 @@
+use crate::{Error, Manager, Pool};
+use deadpool::managed::Object;
+use fantoccini::Client;
+use serde::Deserialize;
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::time::Duration;
+
+/// Status of a session's WebDriver (`/session/driver/{uuid}/status`)
+#[derive(Debug, Clone, Deserialize)]
+pub struct DriverStatus {
+    pub ready: bool,
+    pub message: String,
+}
+
+/// What SessionDriver knows about a session (`/session/{uuid}/sessiondriver/info`)
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionInfo {
+    /// Seconds since the Unix epoch
+    pub created: u64,
+    pub address: String,
+    pub port: u16,
+    pub pid: Option<u32>,
+    pub capabilities: serde_json::Value,
+    pub requests: u64,
+    /// Seconds since the last request
+    pub idle: u64,
+    /// Seconds until the session expires unless used
+    pub remaining: u64,
+    #[serde(default)]
+    pub recoveries: u32,
+}
+
+#[derive(Deserialize)]
+struct Value<T> {
+    value: T,
+}
+
+impl Manager {
+    /// Reads the `value` SessionDriver answers `GET {webdriver}/{path}` with
+    pub(crate) async fn vendor<T: DeserializeOwned>(&self, path: String) -> Result<T, Error> {
+        let response = self
+            .http
+            .get(format!("{}/{}", self.webdriver().await?, path))
+            .send()
+            .await?
+            .error_for_status()?;
+        let body: Value<T> = response.json().await?;
+        Ok(body.value)
+    }
+
+    pub(crate) async fn driver_status(&self, client: &Client) -> Result<DriverStatus, Error> {
+        let session = session(client).await?;
+        self.vendor(format!("session/driver/{}/status", session))
+            .await
+    }
+}
+
+async fn session(client: &Client) -> Result<String, Error> {
+    client.session_id().await?.ok_or(Error::Stateless)
+}
+
+/// SessionDriver's own endpoints for a session of a [`Pool`]
+pub trait SessionDriverExt: Sync {
+    /// Whether the session's WebDriver is ready, which counts as activity
+    fn driver_status(&self) -> impl Future<Output = Result<DriverStatus, Error>> + Send;
+
+    /// Does not count as activity
+    fn session_info(&self) -> impl Future<Output = Result<SessionInfo, Error>> + Send;
+
+    /// Time until the session expires unless used
+    fn remaining(&self) -> impl Future<Output = Result<Duration, Error>> + Send {
+        async {
+            let info = self.session_info().await?;
+            Ok(Duration::from_secs(info.remaining))
+        }
+    }
+
+    /// Keeps the session from expiring for another `--tti`, without a command sent to the browser
+    fn keep_alive(&self) -> impl Future<Output = Result<(), Error>> + Send {
+        async {
+            self.driver_status().await?;
+            Ok(())
+        }
+    }
+}
+
+impl SessionDriverExt for Object<Manager> {
+    async fn driver_status(&self) -> Result<DriverStatus, Error> {
+        let pool = pool(self)?;
+        pool.manager().driver_status(self).await
+    }
+
+    async fn session_info(&self) -> Result<SessionInfo, Error> {
+        let pool = pool(self)?;
+        let session = session(self).await?;
+        pool.manager()
+            .vendor(format!("session/{}/sessiondriver/info", session))
+            .await
+    }
+}
+
+fn pool(object: &Object<Manager>) -> Result<Pool, Error> {
+    Object::pool(object).ok_or_else(|| {
+        Error::Other(std::io::Error::other(
+            "Session has been taken from its pool, which was dropped",
+        ))
+    })
+}
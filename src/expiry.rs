@@ -0,0 +1,200 @@
+use crate::AppState;
+use crate::webhook::EventKind;
+use log::{debug, info};
+use reqwest::Client;
+use std::collections::HashMap;
+use std::future::poll_fn;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::sync::mpsc;
+use tokio::time::timeout;
+use tokio_util::time::DelayQueue;
+use tokio_util::time::delay_queue::Key;
+use tracing::{Instrument, debug_span};
+use uuid::Uuid;
+
+/// Ends sessions once they have been idle for their TTI, see `--tti`
+///
+/// A single task keeps the deadlines of every session in a timer wheel, which each request pushes back in constant
+/// time, rather than every session having a task of its own replaced on each request.
+#[derive(Clone)]
+pub struct Expiry(mpsc::UnboundedSender<Change>);
+
+pub enum Change {
+    /// The session is idle from now on, for the TTI of its tenant if it has one
+    Touch(Uuid, Option<Duration>),
+    Cancel(Uuid),
+}
+
+/// Time a WebDriver is given to answer before its session is considered busy, see `--activity-check`
+const ACTIVITY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// A request being proxied to a session's WebDriver, counted until dropped
+pub struct InFlight<'a>(&'a AtomicUsize);
+
+impl<'a> InFlight<'a> {
+    pub fn enter(count: &'a AtomicUsize) -> Self {
+        count.fetch_add(1, Ordering::Relaxed);
+        Self(count)
+    }
+}
+
+impl Drop for InFlight<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// What is due once a deadline passes
+enum Stage {
+    /// Warn about the session expiring in the given time, see `--expiry-warning`
+    Warn(Duration),
+    Expire,
+}
+
+impl Expiry {
+    /// The scheduler along with the changes to be handed to [`run`]
+    pub fn new() -> (Self, mpsc::UnboundedReceiver<Change>) {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        (Self(sender), receiver)
+    }
+
+    pub fn touch(&self, uuid: Uuid, tti: Option<Duration>) {
+        let _ = self.0.send(Change::Touch(uuid, tti));
+    }
+
+    /// Forgets about a session which has ended otherwise
+    pub fn cancel(&self, uuid: Uuid) {
+        let _ = self.0.send(Change::Cancel(uuid));
+    }
+}
+
+pub async fn run(state: AppState, mut changes: mpsc::UnboundedReceiver<Change>) {
+    let mut queue: DelayQueue<Uuid> = DelayQueue::new();
+    let mut deadlines: HashMap<Uuid, (Key, Stage)> = HashMap::new();
+
+    loop {
+        tokio::select! {
+            change = changes.recv() => match change {
+                Some(Change::Touch(uuid, tti)) => {
+                    let tti = match tti {
+                        Some(tti) => tti,
+                        None => *state.webdriver.tti.read().await,
+                    };
+                    let warning = match &state.webdriver.webhook {
+                        Some(_) => state.webdriver.expiry_warning.filter(|warning| *warning < tti),
+                        None => None,
+                    };
+                    let (timeout, stage) = match warning {
+                        Some(warning) => (tti - warning, Stage::Warn(warning)),
+                        None => (tti, Stage::Expire),
+                    };
+                    match deadlines.get_mut(&uuid) {
+                        Some((key, due)) => {
+                            queue.reset(key, timeout);
+                            *due = stage;
+                        }
+                        None => {
+                            let key = queue.insert(uuid, timeout);
+                            deadlines.insert(uuid, (key, stage));
+                        }
+                    }
+                }
+                Some(Change::Cancel(uuid)) => {
+                    if let Some((key, _)) = deadlines.remove(&uuid) {
+                        queue.remove(&key);
+                    }
+                }
+                None => return,
+            },
+            // Ready with nothing while the queue is empty, which is looked at again after the next change
+            Some(expired) = poll_fn(|cx| queue.poll_expired(cx)) => {
+                let uuid = expired.into_inner();
+                match deadlines.remove(&uuid) {
+                    Some((_, Stage::Warn(remaining))) => {
+                        let key = queue.insert(uuid, remaining);
+                        deadlines.insert(uuid, (key, Stage::Expire));
+                        tokio::spawn(warn(state.clone(), uuid, remaining));
+                    }
+                    Some((_, Stage::Expire)) => {
+                        tokio::spawn(
+                            expire(state.clone(), uuid)
+                                .instrument(debug_span!("expire", session = %uuid)),
+                        );
+                    }
+                    None => {}
+                }
+            }
+        }
+    }
+}
+
+async fn warn(state: AppState, uuid: Uuid, remaining: Duration) {
+    let Some(webhook) = &state.webdriver.webhook else {
+        return;
+    };
+    let capabilities = state
+        .browsers
+        .shard(&uuid)
+        .read()
+        .await
+        .get(&uuid)
+        .map(|b| b.capabilities.clone());
+    if let Some(capabilities) = capabilities {
+        debug!("{:?} expires in {:?}", uuid, remaining);
+        webhook.warn(&state.http, uuid, &capabilities, remaining);
+    }
+}
+
+/// Whether the WebDriver leaves a request about the session unanswered for a while, as it handles one command of a
+/// session at a time, e.g. when a script was started by a connection not passing this instance
+async fn busy(http: &Client, session_url: &str) -> bool {
+    let request = http.get(format!("{}/url", session_url)).send();
+    timeout(ACTIVITY_TIMEOUT, request).await.is_err()
+}
+
+async fn expire(state: AppState, uuid: Uuid) {
+    if state.webdriver.activity_check {
+        let session = state
+            .browsers
+            .shard(&uuid)
+            .read()
+            .await
+            .get(&uuid)
+            .map(|b| {
+                let in_flight = b.in_flight.load(Ordering::Relaxed);
+                let tti = b.tenant.as_ref().and_then(|t| t.tti);
+                (in_flight, b.http.clone(), b.session_url(), tti)
+            });
+        if let Some((in_flight, http, session_url, tti)) = session
+            && (in_flight > 0 || busy(&http, &session_url).await)
+        {
+            debug!("Not expiring {:?} (Busy)", uuid);
+            state.expiry.touch(uuid, tti);
+            return;
+        }
+    }
+
+    if let Some(screenshots) = &state.webdriver.screenshots {
+        let session = state
+            .browsers
+            .shard(&uuid)
+            .read()
+            .await
+            .get(&uuid)
+            .map(|b| (b.http.clone(), b.session_url()));
+        if let Some((http, session_url)) = session {
+            screenshots.capture(&http, &session_url, uuid).await;
+        }
+    }
+
+    let removed = state.browsers.remove(&uuid).await;
+    if let Some(browser) = removed {
+        state.metrics.sessions_expired.inc();
+        info!("Removed {:?}", uuid);
+        if let Some(webhook) = &state.webdriver.webhook {
+            webhook.notify(&state.http, EventKind::Expired, uuid, &browser.capabilities);
+        }
+        browser.end(uuid, state.webdriver.stop_grace).await;
+    }
+}
@@ -0,0 +1,117 @@
+use crate::allowlist::Allowlist;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::http::{HeaderMap, header};
+use axum::middleware::Next;
+use axum::response::Response;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+/// Address of the client a request originates from, which is that of a trusted proxy's client if it forwarded it
+#[derive(Debug, Clone, Copy)]
+pub struct ClientAddress(pub IpAddr);
+
+/// Records the client of every request as `ClientAddress`, honouring `Forwarded` (or else `X-Forwarded-For`) of
+/// requests made by `trusted` proxies
+pub async fn identify(
+    State(trusted): State<Arc<Allowlist>>,
+    ConnectInfo(peer): ConnectInfo<SocketAddr>,
+    mut request: Request,
+    next: Next,
+) -> Response {
+    let client = client(&trusted, peer.ip(), request.headers());
+    request.extensions_mut().insert(ClientAddress(client));
+
+    next.run(request).await
+}
+
+/// Walks the forwarding chain from the nearest hop back to the first address not of a trusted proxy
+fn client(trusted: &Allowlist, peer: IpAddr, headers: &HeaderMap) -> IpAddr {
+    let peer = peer.to_canonical();
+    if !trusted.contains(peer) {
+        return peer;
+    }
+
+    let mut client = peer;
+    for hop in hops(headers).into_iter().rev() {
+        // Obfuscated or unknown hops (e.g. `for=unknown`) can not be looked past
+        let Some(hop) = hop else {
+            break;
+        };
+        client = hop.to_canonical();
+        if !trusted.contains(client) {
+            break;
+        }
+    }
+    client
+}
+
+/// Forwarded addresses from the original client to the nearest proxy
+fn hops(headers: &HeaderMap) -> Vec<Option<IpAddr>> {
+    let forwarded: Vec<&str> = headers
+        .get_all(header::FORWARDED)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect();
+    if !forwarded.is_empty() {
+        return forwarded
+            .iter()
+            .flat_map(|value| value.split(','))
+            .map(|element| {
+                element
+                    .split(';')
+                    .filter_map(|pair| pair.trim().split_once('='))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("for"))
+                    .and_then(|(_, node)| parse_node(node.trim_matches('"')))
+            })
+            .collect();
+    }
+
+    headers
+        .get_all(X_FORWARDED_FOR)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|node| parse_node(node.trim()))
+        .collect()
+}
+
+/// Accepts `192.0.2.1`, `192.0.2.1:4711`, `2001:db8::1` and `[2001:db8::1]:4711`
+fn parse_node(node: &str) -> Option<IpAddr> {
+    node.parse::<IpAddr>()
+        .or_else(|_| node.parse::<SocketAddr>().map(|address| address.ip()))
+        .or_else(|_| node.trim_start_matches('[').trim_end_matches(']').parse())
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::allowlist::parse_network;
+    use axum::http::HeaderValue;
+
+    #[test]
+    fn honours_trusted_hops_only() {
+        let trusted = Allowlist::new(vec![parse_network("10.0.0.0/8").unwrap()]);
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            X_FORWARDED_FOR,
+            HeaderValue::from_static("198.51.100.1, 203.0.113.7, 10.0.0.2"),
+        );
+
+        let proxy = "10.0.0.1".parse().unwrap();
+        let resolved = client(&trusted, proxy, &headers);
+        assert_eq!(resolved, "203.0.113.7".parse::<IpAddr>().unwrap());
+
+        let stranger = "192.0.2.9".parse().unwrap();
+        assert_eq!(client(&trusted, stranger, &headers), stranger);
+
+        headers.insert(
+            header::FORWARDED,
+            HeaderValue::from_static(r#"for="[2001:db8::1]:4711";proto=https, for=10.0.0.3"#),
+        );
+        let resolved = client(&trusted, proxy, &headers);
+        assert_eq!(resolved, "2001:db8::1".parse::<IpAddr>().unwrap());
+    }
+}
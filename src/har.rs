@@ -0,0 +1,427 @@
+use crate::artifacts::Artifact;
+use crate::tenant::Tenant;
+use crate::{AppState, Browsers, HOP_BY_HOP, WebDriverMeta, internal_server_error};
+use async_lock::Mutex;
+use axum::Router;
+use axum::body::{Body, Bytes, to_bytes};
+use axum::extract::{Extension, Path, Request, State};
+use axum::http::{HeaderMap, Method, StatusCode, header};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use hyper_util::rt::TokioIo;
+use log::{debug, info, warn};
+use reqwest::{Client, Url};
+use serde_json::{Value, json};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::Arc;
+use std::time::{Instant, SystemTime};
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+const HAR: Artifact = Artifact {
+    name: "capture",
+    extension: "har",
+    content_type: "application/json",
+};
+
+/// Captures the HTTP traffic of each session's browser, which is pointed at a proxy of its own
+pub struct HarArchive {
+    pub directory: PathBuf,
+}
+
+impl HarArchive {
+    /// Starts a proxy on `host` for a session about to be created
+    pub async fn start(&self, host: IpAddr) -> std::io::Result<Capture> {
+        tokio::fs::create_dir_all(&self.directory).await?;
+        let listener = TcpListener::bind((host, 0)).await?;
+        let address = listener.local_addr()?;
+        // Named after the session once it has been created
+        let partial = self
+            .directory
+            .join(format!("{}.har.partial", Uuid::new_v4()));
+        let mut file = BufWriter::new(File::create(&partial).await?);
+        let header = format!(
+            r#"{{"log":{{"version":"1.2","creator":{{"name":"SessionDriver","version":{}}},"entries":["#,
+            Value::from(env!("CARGO_PKG_VERSION"))
+        );
+        file.write_all(header.as_bytes()).await?;
+        let tap = Arc::new(Tap {
+            http: Client::builder()
+                .redirect(reqwest::redirect::Policy::none())
+                .no_proxy()
+                .build()
+                .map_err(std::io::Error::other)?,
+            entries: Mutex::new(Entries {
+                file,
+                count: 0,
+                closed: false,
+            }),
+        });
+
+        let app = Router::new().fallback(forward).with_state(tap.clone());
+        let server = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                warn!("Capturing proxy at {} failed: {}", address, e);
+            }
+        });
+        debug!("Capturing traffic at {}", address);
+
+        Ok(Capture {
+            address,
+            tap,
+            server,
+            partial: Some(partial),
+            path: None,
+        })
+    }
+}
+
+/// Proxy of a single session, whose entries are written as they are made and completed once the session ends
+pub struct Capture {
+    pub address: SocketAddr,
+    tap: Arc<Tap>,
+    server: JoinHandle<()>,
+    /// File entries are written to until the session ends
+    partial: Option<PathBuf>,
+    path: Option<PathBuf>,
+}
+
+impl Capture {
+    pub fn assign(&mut self, archive: &HarArchive, session: Uuid, tenant: Option<&Tenant>) {
+        self.path = Some(HAR.path(&archive.directory, tenant, session));
+    }
+
+    pub async fn finish(mut self) {
+        self.server.abort();
+        let Some(partial) = self.partial.take() else {
+            return;
+        };
+        let (completed, count) = {
+            let mut entries = self.tap.entries.lock().await;
+            // Requests still being forwarded are no longer captured
+            entries.closed = true;
+            let completed = async {
+                entries.file.write_all(b"]}}").await?;
+                entries.file.flush().await
+            };
+            (completed.await, entries.count)
+        };
+
+        let Some(path) = self.path.take() else {
+            discard(&partial).await;
+            return;
+        };
+        let completed = async {
+            completed?;
+            if let Some(directory) = path.parent() {
+                tokio::fs::create_dir_all(directory).await?;
+            }
+            tokio::fs::rename(&partial, &path).await
+        };
+        let completed = completed.await;
+        match completed {
+            Ok(()) => info!("Captured {} request(s) to {:?}", count, path),
+            Err(e) => {
+                warn!("Unable to write {:?}: {}", path, e);
+                discard(&partial).await;
+            }
+        }
+    }
+}
+
+impl Drop for Capture {
+    fn drop(&mut self) {
+        self.server.abort();
+        // Not finished, e.g. as the session could not be created
+        if let Some(partial) = self.partial.take() {
+            let _ = std::fs::remove_file(partial);
+        }
+    }
+}
+
+async fn discard(partial: &std::path::Path) {
+    if let Err(e) = tokio::fs::remove_file(partial).await {
+        warn!("Unable to remove {:?}: {}", partial, e);
+    }
+}
+
+struct Tap {
+    http: Client,
+    entries: Mutex<Entries>,
+}
+
+/// Entries of a capture, which are written to its file right away rather than being kept until the session ends
+struct Entries {
+    file: BufWriter<File>,
+    count: usize,
+    closed: bool,
+}
+
+impl Entries {
+    async fn push(&mut self, entry: Value) {
+        if self.closed {
+            return;
+        }
+        let separator = if self.count == 0 { "" } else { "," };
+        match self
+            .file
+            .write_all(format!("{}{}", separator, entry).as_bytes())
+            .await
+        {
+            Ok(()) => self.count += 1,
+            Err(e) => warn!("Unable to capture a request: {}", e),
+        }
+    }
+}
+
+/// A request made through the proxy, which may have failed without a `response`
+struct Exchange<'a> {
+    started: SystemTime,
+    elapsed: Instant,
+    method: &'a Method,
+    url: &'a str,
+    headers: &'a HeaderMap,
+    body: &'a [u8],
+    response: Option<(StatusCode, &'a HeaderMap, &'a [u8])>,
+}
+
+impl Exchange<'_> {
+    /// Entry of the HAR 1.2 format
+    fn entry(&self) -> Value {
+        let time = self.elapsed.elapsed().as_secs_f64() * 1000.0;
+        let query: Vec<Value> = Url::parse(self.url)
+            .map(|url| {
+                url.query_pairs()
+                    .map(|(name, value)| json!({ "name": name, "value": value }))
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut request = json!({
+            "method": self.method.as_str(),
+            "url": self.url,
+            "httpVersion": "HTTP/1.1",
+            "cookies": [],
+            "headers": name_values(self.headers),
+            "queryString": query,
+            "headersSize": -1,
+            "bodySize": self.body.len(),
+        });
+        if !self.body.is_empty() {
+            request["postData"] = json!({
+                "mimeType": mime_type(self.headers),
+                "text": String::from_utf8_lossy(self.body),
+            });
+        }
+
+        let response = match self.response {
+            Some((status, headers, body)) => {
+                let mut content = json!({ "size": body.len(), "mimeType": mime_type(headers) });
+                match std::str::from_utf8(body) {
+                    Ok(text) => content["text"] = Value::from(text),
+                    Err(_) => {
+                        content["text"] = Value::from(STANDARD.encode(body));
+                        content["encoding"] = Value::from("base64");
+                    }
+                }
+                json!({
+                    "status": status.as_u16(),
+                    "statusText": status.canonical_reason().unwrap_or_default(),
+                    "httpVersion": "HTTP/1.1",
+                    "cookies": [],
+                    "headers": name_values(headers),
+                    "content": content,
+                    "redirectURL": headers
+                        .get(header::LOCATION)
+                        .and_then(|location| location.to_str().ok())
+                        .unwrap_or_default(),
+                    "headersSize": -1,
+                    "bodySize": body.len(),
+                })
+            }
+            // How HAR describes requests that failed
+            None => json!({
+                "status": 0,
+                "statusText": "",
+                "httpVersion": "",
+                "cookies": [],
+                "headers": [],
+                "content": { "size": 0, "mimeType": "" },
+                "redirectURL": "",
+                "headersSize": -1,
+                "bodySize": -1,
+            }),
+        };
+
+        json!({
+            "startedDateTime": humantime::format_rfc3339_millis(self.started).to_string(),
+            "time": time,
+            "request": request,
+            "response": response,
+            "cache": {},
+            "timings": { "send": 0, "wait": time, "receive": 0 },
+        })
+    }
+}
+
+fn name_values(headers: &HeaderMap) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, value)| {
+            json!({ "name": name.as_str(), "value": String::from_utf8_lossy(value.as_bytes()) })
+        })
+        .collect()
+}
+
+fn mime_type(headers: &HeaderMap) -> &str {
+    headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default()
+}
+
+/// Forwards a request of the browser and records it, whereas HTTPS is tunnelled and only recorded as such
+async fn forward(State(tap): State<Arc<Tap>>, request: Request) -> Response {
+    if request.method() == Method::CONNECT {
+        return tunnel(tap, request).await;
+    }
+    let started = SystemTime::now();
+    let elapsed = Instant::now();
+    if request.uri().scheme().is_none() {
+        return (StatusCode::BAD_REQUEST, "Not a proxy request").into_response();
+    }
+
+    let (parts, body) = request.into_parts();
+    let url = parts.uri.to_string();
+    let body = match to_bytes(body, usize::MAX).await {
+        Ok(body) => body,
+        Err(e) => return internal_server_error(e),
+    };
+    let mut headers = parts.headers.clone();
+    for name in HOP_BY_HOP.iter().chain(&["proxy-connection", "host"]) {
+        headers.remove(*name);
+    }
+
+    let mut exchange = Exchange {
+        started,
+        elapsed,
+        method: &parts.method,
+        url: &url,
+        headers: &parts.headers,
+        body: &body,
+        response: None,
+    };
+    let response = tap
+        .http
+        .request(parts.method.clone(), &url)
+        .headers(headers)
+        .body(body.clone())
+        .send()
+        .await;
+    let response = match response {
+        Ok(response) => response,
+        Err(e) => {
+            tap.entries.lock().await.push(exchange.entry()).await;
+            return (StatusCode::BAD_GATEWAY, e.to_string()).into_response();
+        }
+    };
+
+    let status = response.status();
+    let response_headers = response.headers().clone();
+    let response_body: Bytes = match response.bytes().await {
+        Ok(body) => body,
+        Err(e) => {
+            tap.entries.lock().await.push(exchange.entry()).await;
+            return (StatusCode::BAD_GATEWAY, e.to_string()).into_response();
+        }
+    };
+    exchange.response = Some((status, &response_headers, &response_body));
+    tap.entries.lock().await.push(exchange.entry()).await;
+
+    let mut forwarded = Response::builder().status(status);
+    for (name, value) in &response_headers {
+        if !HOP_BY_HOP.contains(&name.as_str()) && name != header::CONTENT_LENGTH {
+            forwarded = forwarded.header(name, value);
+        }
+    }
+    forwarded
+        .body(Body::from(response_body))
+        .unwrap_or_else(internal_server_error)
+}
+
+/// Connects the browser to the host of a `CONNECT` request, whose encrypted traffic is passed on as is
+async fn tunnel(tap: Arc<Tap>, mut request: Request) -> Response {
+    let started = SystemTime::now();
+    let elapsed = Instant::now();
+    let Some(authority) = request.uri().authority().map(|a| a.to_string()) else {
+        return (StatusCode::BAD_REQUEST, "Missing host").into_response();
+    };
+    let url = format!("https://{}", authority);
+
+    let connected = TcpStream::connect(&authority).await;
+    let status = match connected {
+        Ok(_) => StatusCode::OK,
+        Err(_) => StatusCode::BAD_GATEWAY,
+    };
+    let empty = HeaderMap::new();
+    let exchange = Exchange {
+        started,
+        elapsed,
+        method: &Method::CONNECT,
+        url: &url,
+        headers: request.headers(),
+        body: &[],
+        response: connected.as_ref().ok().map(|_| (status, &empty, &[][..])),
+    };
+    tap.entries.lock().await.push(exchange.entry()).await;
+    let mut upstream = match connected {
+        Ok(upstream) => upstream,
+        Err(e) => return (status, e.to_string()).into_response(),
+    };
+
+    tokio::spawn(async move {
+        match hyper::upgrade::on(&mut request).await {
+            Ok(upgraded) => {
+                let mut client = TokioIo::new(upgraded);
+                if let Err(e) = tokio::io::copy_bidirectional(&mut client, &mut upstream).await {
+                    debug!("Tunnel to {} closed: {}", authority, e);
+                }
+            }
+            Err(e) => debug!("Unable to tunnel to {}: {}", authority, e),
+        }
+    });
+
+    status.into_response()
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/sessiondriver/har/{id}", get(download).delete(remove))
+}
+
+async fn download(
+    State(browsers): State<Browsers>,
+    State(webdriver_meta): State<Arc<WebDriverMeta>>,
+    tenant: Option<Extension<Arc<Tenant>>>,
+    Path(id): Path<Uuid>,
+) -> Result<Response, Response> {
+    let tenant = tenant.as_ref().map(|Extension(tenant)| tenant.as_ref());
+    let directory = webdriver_meta.har.as_ref().map(|a| a.directory.as_path());
+    HAR.download(&browsers, directory, tenant, id).await
+}
+
+async fn remove(
+    State(browsers): State<Browsers>,
+    State(webdriver_meta): State<Arc<WebDriverMeta>>,
+    tenant: Option<Extension<Arc<Tenant>>>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, Response> {
+    let tenant = tenant.as_ref().map(|Extension(tenant)| tenant.as_ref());
+    let directory = webdriver_meta.har.as_ref().map(|a| a.directory.as_path());
+    HAR.remove(&browsers, directory, tenant, id).await
+}
@@ -0,0 +1,50 @@
+use crate::capacity::Capacity;
+use crate::{Backend, Browsers, WebDriverMeta, driver_usable};
+use axum::Router;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::get;
+use std::sync::Arc;
+
+/// What readiness depends on
+pub struct Health {
+    pub capacity: Arc<Capacity>,
+    pub browsers: Browsers,
+    /// Unless this instance is a hub
+    pub webdriver: Option<Arc<WebDriverMeta>>,
+}
+
+/// Probes for orchestrators and load balancers, answered without authentication
+pub fn router<S: Clone + Send + Sync + 'static>(health: Health) -> Router<S> {
+    Router::new()
+        .route("/healthz", get(live))
+        .route("/readyz", get(ready))
+        .with_state(Arc::new(health))
+}
+
+/// Answered as long as the process serves requests at all
+async fn live() -> &'static str {
+    "OK"
+}
+
+/// Whether new sessions should be sent here, i.e. the WebDriver executable is usable and neither are sessions at their
+/// limit nor is this instance shutting down
+async fn ready(State(health): State<Arc<Health>>) -> Response {
+    let (mut ready, mut message) = health.capacity.status(health.browsers.len().await);
+    if ready
+        && let Some(webdriver) = &health.webdriver
+        && let Backend::Process(path) = &webdriver.backend
+        && let Err(e) = driver_usable(&path.read().await).await
+    {
+        ready = false;
+        message = e;
+    }
+
+    let status = match ready {
+        true => StatusCode::OK,
+        false => StatusCode::SERVICE_UNAVAILABLE,
+    };
+    let body = serde_json::json!({ "value": { "ready": ready, "message": message } });
+    (status, Json(body)).into_response()
+}
@@ -0,0 +1,665 @@
+use crate::auth::{self, Quota};
+use crate::capacity::{Capacity, Permit, Reservation};
+use crate::forwarded::ClientAddress;
+use crate::logging::Upstream;
+use crate::metrics::Metrics;
+use crate::ratelimit::RateLimit;
+use crate::tenant::{self, Tenant};
+use crate::upstream::UpstreamHeaders;
+use crate::{
+    AppState, copy_headers, internal_server_error, proxy_request, unknown_command, unknown_session,
+    w3c,
+};
+use async_lock::RwLock;
+use axum::Router;
+use axum::body::{Body, to_bytes};
+use axum::extract::{Request, State};
+use axum::http::{Method, StatusCode, header};
+use axum::middleware::{self, Next};
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::{get, post};
+use log::{debug, info, warn};
+use reqwest::{Client, Url};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use uuid::Uuid;
+
+/// Interval at which nodes register themselves again
+pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Time after which a node that stopped registering is no longer routed to
+const NODE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Routes sessions to the nodes registered with it instead of spawning WebDrivers itself
+pub struct Hub {
+    pub http: Client,
+    pub metrics: Arc<Metrics>,
+    pub protocol: String,
+    pub capacity: Arc<Capacity>,
+    pub headers: UpstreamHeaders,
+    pub rate_limit: RateLimit,
+    nodes: RwLock<HashMap<SocketAddr, Node>>,
+    sessions: RwLock<HashMap<Uuid, Routed>>,
+}
+
+struct Node {
+    capabilities: Map<String, Value>,
+    max_sessions: Option<usize>,
+    sessions: usize,
+    seen: Instant,
+}
+
+struct Routed {
+    node: SocketAddr,
+    /// When the node answered, having created the session by then
+    at: Instant,
+    tenant: Option<Arc<Tenant>>,
+    _permit: Permit,
+    _quota: Option<Permit>,
+}
+
+/// What a node reports about itself on every heartbeat
+#[derive(Debug, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Registration {
+    /// Address the hub reaches the node at
+    pub address: SocketAddr,
+    /// Capabilities sessions have to be compatible with to be routed to the node
+    pub capabilities: Map<String, Value>,
+    pub max_sessions: Option<usize>,
+    pub sessions: Vec<Uuid>,
+}
+
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct NodeSnapshot {
+    pub address: SocketAddr,
+    pub capabilities: Map<String, Value>,
+    pub max_sessions: Option<usize>,
+    pub sessions: usize,
+    pub seen_ms_ago: u128,
+}
+
+impl Hub {
+    pub fn new(
+        http: Client,
+        metrics: Arc<Metrics>,
+        protocol: String,
+        capacity: Arc<Capacity>,
+        headers: UpstreamHeaders,
+        rate_limit: RateLimit,
+    ) -> Self {
+        Self {
+            http,
+            metrics,
+            protocol,
+            capacity,
+            headers,
+            rate_limit,
+            nodes: RwLock::new(HashMap::new()),
+            sessions: RwLock::new(HashMap::new()),
+        }
+    }
+
+    async fn register(&self, registration: Registration) {
+        let node = Node {
+            capabilities: registration.capabilities,
+            max_sessions: registration.max_sessions,
+            sessions: registration.sessions.len(),
+            seen: Instant::now(),
+        };
+        let previous = self.nodes.write().await.insert(registration.address, node);
+        let Some(previous) = previous else {
+            info!("Registered node {}", registration.address);
+            return;
+        };
+
+        // Nodes only list their sessions once their previous heartbeat has been answered, so sessions routed before it
+        // arrived which the node no longer knows about have been deleted or expired there. Those routed since may not
+        // have been listed yet.
+        self.sessions.write().await.retain(|id, routed| {
+            routed.node != registration.address
+                || routed.at > previous.seen
+                || registration.sessions.contains(id)
+        });
+    }
+
+    /// Picks the live node with the fewest sessions among those with capacity left that can satisfy `requested`
+    async fn select(&self, requested: &Value) -> Option<SocketAddr> {
+        let mut nodes = self.nodes.write().await;
+        let before = nodes.len();
+        nodes.retain(|address, node| {
+            let live = node.seen.elapsed() < NODE_TIMEOUT;
+            if !live {
+                warn!("Dropped node {} (No heartbeat)", address);
+            }
+            live
+        });
+        // Sessions of dropped nodes are gone along with them, releasing their capacity
+        if nodes.len() < before {
+            self.sessions
+                .write()
+                .await
+                .retain(|_, routed| nodes.contains_key(&routed.node));
+        }
+
+        let (address, node) = nodes
+            .iter_mut()
+            .filter(|(_, node)| node.sessions < node.max_sessions.unwrap_or(usize::MAX))
+            .filter(|(_, node)| satisfies(&node.capabilities, requested))
+            .min_by_key(|(_, node)| node.sessions)?;
+        // Counted until the next heartbeat reports the actual number
+        node.sessions += 1;
+
+        Some(*address)
+    }
+
+    async fn status(&self) -> (bool, String) {
+        if self.capacity.is_draining() {
+            return (false, String::from("Shutting down"));
+        }
+        let available = self
+            .nodes
+            .read()
+            .await
+            .values()
+            .filter(|node| node.seen.elapsed() < NODE_TIMEOUT)
+            .any(|node| node.sessions < node.max_sessions.unwrap_or(usize::MAX));
+        match available {
+            true => (true, String::from("Ready")),
+            false => (false, String::from("No node available")),
+        }
+    }
+}
+
+/// Whether a node declaring `capabilities` can serve a new session request's `{ "capabilities": ... }`
+///
+/// Only capabilities the node declares are compared, anything else is left to its WebDriver.
+fn satisfies(capabilities: &Map<String, Value>, requested: &Value) -> bool {
+    let always = requested["capabilities"]["alwaysMatch"].as_object();
+    let first = match requested["capabilities"]["firstMatch"].as_array() {
+        Some(first) if !first.is_empty() => first.iter().collect(),
+        _ => vec![&Value::Null],
+    };
+
+    first.into_iter().any(|first| {
+        always
+            .into_iter()
+            .flatten()
+            .chain(first.as_object().into_iter().flatten())
+            .all(|(key, value)| {
+                capabilities
+                    .get(key)
+                    .is_none_or(|declared| declared == value)
+            })
+    })
+}
+
+/// Parses `--node-capabilities`
+pub fn parse_capabilities(s: &str) -> Result<Map<String, Value>, String> {
+    serde_json::from_str(s).map_err(|e| e.to_string())
+}
+
+/// Routes of a hub, taking the place of [`crate::proxy`]
+pub fn router() -> Router<Arc<Hub>> {
+    Router::new()
+        .route("/sessiondriver/nodes", get(nodes))
+        .fallback(proxy)
+}
+
+/// Route nodes register at, guarded by the `--hub-token` shared with them rather than by the tokens of clients
+pub fn registration(token: String) -> Router<Arc<Hub>> {
+    Router::new()
+        .route("/sessiondriver/nodes", post(register))
+        .route_layer(middleware::from_fn_with_state(
+            Arc::<str>::from(token),
+            authenticate_node,
+        ))
+}
+
+async fn authenticate_node(
+    State(token): State<Arc<str>>,
+    request: Request,
+    next: Next,
+) -> Result<Response, Response> {
+    if !auth::bearer(request.headers(), &token) {
+        warn!("Rejected registration of a node without --hub-token");
+        return Err(auth::unauthorized("Missing or unknown --hub-token"));
+    }
+    Ok(next.run(request).await)
+}
+
+async fn register(
+    State(hub): State<Arc<Hub>>,
+    Json(registration): Json<Registration>,
+) -> StatusCode {
+    hub.register(registration).await;
+    StatusCode::NO_CONTENT
+}
+
+async fn nodes(State(hub): State<Arc<Hub>>) -> Json<Vec<NodeSnapshot>> {
+    let nodes = hub.nodes.read().await;
+    Json(
+        nodes
+            .iter()
+            .map(|(address, node)| NodeSnapshot {
+                address: *address,
+                capabilities: node.capabilities.clone(),
+                max_sessions: node.max_sessions,
+                sessions: node.sessions,
+                seen_ms_ago: node.seen.elapsed().as_millis(),
+            })
+            .collect(),
+    )
+}
+
+async fn proxy(State(hub): State<Arc<Hub>>, mut request: Request) -> Result<Response, Response> {
+    let path = request.uri().path().trim_end_matches('/');
+
+    if (request.method() == Method::GET || request.method() == Method::HEAD) && path == "/status" {
+        let (ready, message) = hub.status().await;
+        let body = serde_json::json!({ "value": { "ready": ready, "message": message } });
+        return Ok(Json(body).into_response());
+    }
+
+    if request.method() == Method::POST && path == "/session" {
+        let client = request
+            .extensions()
+            .get::<ClientAddress>()
+            .map(|ClientAddress(address)| *address);
+        if let Err(wait) = hub.rate_limit.check(client) {
+            info!("Rejected session (Rate limited)");
+            let retry_after = wait.as_secs_f64().ceil() as u64;
+            return Err((
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                w3c::error(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    w3c::SESSION_NOT_CREATED,
+                    "Sessions are created too quickly",
+                ),
+            )
+                .into_response());
+        }
+
+        let permit = match hub.capacity.reserve() {
+            Reservation::Granted(permit) => permit,
+            Reservation::Exhausted => {
+                info!("Rejected session (At capacity)");
+                return Err(w3c::error(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    w3c::SESSION_NOT_CREATED,
+                    "Maximum number of sessions reached",
+                ));
+            }
+            Reservation::Draining => {
+                info!("Rejected session (Draining)");
+                return Err(w3c::error(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    w3c::SESSION_NOT_CREATED,
+                    "Shutting down",
+                ));
+            }
+        };
+        let quota = match request.extensions().get::<Quota>().map(Quota::reserve) {
+            Some(Some(quota)) => Some(quota),
+            Some(None) => {
+                info!("Rejected session (Token at quota)");
+                return Err(w3c::error(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    w3c::SESSION_NOT_CREATED,
+                    "Maximum number of sessions per token reached",
+                ));
+            }
+            None => None,
+        };
+        let tenant = request.extensions().get::<Arc<Tenant>>().cloned();
+
+        let (mut parts, body) = request.into_parts();
+        hub.headers.apply(&mut parts.headers);
+        let body = to_bytes(body, usize::MAX)
+            .await
+            .map_err(internal_server_error)?;
+        let requested: Value = serde_json::from_slice(&body).unwrap_or_default();
+        let Some(node) = hub.select(&requested).await else {
+            info!("Rejected session (No node available)");
+            return Err(w3c::error(
+                StatusCode::SERVICE_UNAVAILABLE,
+                w3c::SESSION_NOT_CREATED,
+                "No node available",
+            ));
+        };
+        debug!("Routing new session to {}", node);
+
+        let driver_response = proxy_request(
+            hub.http.clone(),
+            &hub.metrics,
+            None,
+            None,
+            &format!("{}{}", hub.protocol, node),
+            Request::from_parts(parts, Body::from(body)),
+            false,
+        )
+        .await?;
+        let response = Response::builder().status(driver_response.status().as_u16());
+        let mut response = copy_headers(response, driver_response.headers(), false);
+        let body = driver_response
+            .bytes()
+            .await
+            .map_err(internal_server_error)?;
+
+        let created: Value = serde_json::from_slice(&body).unwrap_or_default();
+        if let Some(session) = created["value"]["sessionId"]
+            .as_str()
+            .and_then(|id| id.parse::<Uuid>().ok())
+        {
+            info!("Routed {:?} to {}", session, node);
+            hub.sessions.write().await.insert(
+                session,
+                Routed {
+                    node,
+                    at: Instant::now(),
+                    tenant,
+                    _permit: permit,
+                    _quota: quota,
+                },
+            );
+            response = response.extension(Upstream {
+                session,
+                address: node,
+            });
+        }
+
+        return response
+            .body(Body::from(body))
+            .map_err(internal_server_error);
+    }
+
+    let Some(mut uuid) = path.strip_prefix("/session/") else {
+        return Err(unknown_command(request.method(), path));
+    };
+    if let Some(i) = uuid.find('/') {
+        uuid = &uuid[..i];
+    }
+    let Ok(uuid) = uuid.parse::<Uuid>() else {
+        return Err(w3c::error(
+            StatusCode::NOT_FOUND,
+            w3c::INVALID_SESSION_ID,
+            format!("No active session with ID {}", uuid),
+        ));
+    };
+    let tenant = request.extensions().get::<Arc<Tenant>>().cloned();
+    let node = match hub.sessions.read().await.get(&uuid) {
+        Some(routed) if tenant::same(tenant.as_deref(), routed.tenant.as_deref()) => routed.node,
+        _ => {
+            debug!("{:?} not found", uuid);
+            return Err(unknown_session(uuid));
+        }
+    };
+    if request.method() == Method::DELETE && path == format!("/session/{}", uuid) {
+        hub.sessions.write().await.remove(&uuid);
+        info!("Removed {:?}", uuid);
+    }
+    hub.headers.apply(request.headers_mut());
+
+    let driver_response = proxy_request(
+        hub.http.clone(),
+        &hub.metrics,
+        None,
+        None,
+        &format!("{}{}", hub.protocol, node),
+        request,
+        false,
+    )
+    .await?;
+    let mut response = Response::builder()
+        .extension(Upstream {
+            session: uuid,
+            address: node,
+        })
+        .status(driver_response.status().as_u16());
+    response = copy_headers(response, driver_response.headers(), false);
+
+    response
+        .body(Body::from_stream(driver_response.bytes_stream()))
+        .map_err(internal_server_error)
+}
+
+/// Registers this instance with a hub every [`HEARTBEAT_INTERVAL`] for as long as it runs
+pub fn join(
+    hub: Url,
+    token: Option<String>,
+    address: SocketAddr,
+    capabilities: Map<String, Value>,
+    state: AppState,
+) {
+    tokio::spawn(async move {
+        let url = match hub.join("sessiondriver/nodes") {
+            Ok(url) => url,
+            Err(e) => {
+                warn!("Unable to register with {}: {}", hub, e);
+                return;
+            }
+        };
+        info!("Registering with {} as {}", hub, address);
+
+        let mut ticker = tokio::time::interval(HEARTBEAT_INTERVAL);
+        loop {
+            ticker.tick().await;
+
+            let registration = Registration {
+                address,
+                capabilities: capabilities.clone(),
+                max_sessions: match state.capacity.is_draining() {
+                    true => Some(0),
+                    false => state.capacity.max_sessions(),
+                },
+                sessions: state.browsers.ids().await,
+            };
+            let mut request = state
+                .http
+                .post(url.clone())
+                .json(&registration)
+                .timeout(HEARTBEAT_INTERVAL);
+            if let Some(token) = &token {
+                request = request.bearer_auth(token);
+            }
+            match request.send().await.and_then(|r| r.error_for_status()) {
+                Ok(_) => debug!("Registered with {}", hub),
+                Err(e) => warn!("Unable to register with {}: {}", hub, e),
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn declared(value: Value) -> Map<String, Value> {
+        value.as_object().unwrap().clone()
+    }
+
+    #[test]
+    fn compares_declared_capabilities() {
+        let firefox = declared(json!({ "browserName": "firefox", "platformName": "linux" }));
+        let request = |capabilities| json!({ "capabilities": capabilities });
+
+        assert!(satisfies(&firefox, &request(json!({}))));
+        assert!(satisfies(
+            &firefox,
+            &request(
+                json!({ "alwaysMatch": { "browserName": "firefox", "acceptInsecureCerts": true } })
+            )
+        ));
+        assert!(!satisfies(
+            &firefox,
+            &request(json!({ "alwaysMatch": { "browserName": "chrome" } }))
+        ));
+        assert!(satisfies(
+            &firefox,
+            &request(
+                json!({ "firstMatch": [{ "browserName": "chrome" }, { "browserName": "firefox" }] })
+            )
+        ));
+        assert!(!satisfies(
+            &firefox,
+            &request(json!({
+                "alwaysMatch": { "platformName": "windows" },
+                "firstMatch": [{ "browserName": "firefox" }]
+            }))
+        ));
+    }
+
+    #[tokio::test]
+    async fn selects_least_loaded_node() {
+        let hub = Hub::new(
+            Client::new(),
+            Arc::new(Metrics::new().unwrap()),
+            String::from("http://"),
+            Arc::new(Capacity::new(None)),
+            UpstreamHeaders::default(),
+            RateLimit::new(None, None, 1),
+        );
+        let node = |port: u16, max_sessions, sessions: usize| Registration {
+            address: SocketAddr::from(([127, 0, 0, 1], port)),
+            capabilities: Map::new(),
+            max_sessions,
+            sessions: (0..sessions).map(|_| Uuid::new_v4()).collect(),
+        };
+
+        hub.register(node(4445, None, 3)).await;
+        hub.register(node(4446, Some(2), 1)).await;
+
+        let request = json!({ "capabilities": {} });
+        assert_eq!(hub.select(&request).await.unwrap().port(), 4446);
+        // 4446 is full now
+        assert_eq!(hub.select(&request).await.unwrap().port(), 4445);
+    }
+
+    #[tokio::test]
+    async fn keeps_sessions_routed_since_the_last_heartbeat() {
+        let hub = Hub::new(
+            Client::new(),
+            Arc::new(Metrics::new().unwrap()),
+            String::from("http://"),
+            Arc::new(Capacity::new(None)),
+            UpstreamHeaders::default(),
+            RateLimit::new(None, None, 1),
+        );
+        let node = SocketAddr::from(([127, 0, 0, 1], 4445));
+        let heartbeat = || Registration {
+            address: node,
+            capabilities: Map::new(),
+            max_sessions: None,
+            sessions: Vec::new(),
+        };
+        hub.register(heartbeat()).await;
+        let Reservation::Granted(permit) = hub.capacity.reserve() else {
+            panic!("No capacity");
+        };
+        let session = Uuid::new_v4();
+        hub.sessions.write().await.insert(
+            session,
+            Routed {
+                node,
+                at: Instant::now(),
+                tenant: None,
+                _permit: permit,
+                _quota: None,
+            },
+        );
+
+        // Listed its sessions before the session was routed
+        hub.register(heartbeat()).await;
+        assert!(hub.sessions.read().await.contains_key(&session));
+        hub.register(heartbeat()).await;
+        assert!(hub.sessions.read().await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn registers_nodes_presenting_the_hub_token() {
+        let hub = Arc::new(Hub::new(
+            Client::new(),
+            Arc::new(Metrics::new().unwrap()),
+            String::from("http://"),
+            Arc::new(Capacity::new(None)),
+            UpstreamHeaders::default(),
+            RateLimit::new(None, None, 1),
+        ));
+        let app = registration(String::from("secret")).with_state(hub.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!(
+            "http://{}/sessiondriver/nodes",
+            listener.local_addr().unwrap()
+        );
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let registration = Registration {
+            address: SocketAddr::from(([127, 0, 0, 1], 4445)),
+            capabilities: Map::new(),
+            max_sessions: None,
+            sessions: Vec::new(),
+        };
+        let register = |token: &str| {
+            Client::new()
+                .post(&url)
+                .bearer_auth(token)
+                .json(&registration)
+                .send()
+        };
+        assert_eq!(
+            register("client").await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+        assert!(hub.nodes.read().await.is_empty());
+        assert_eq!(
+            register("secret").await.unwrap().status(),
+            StatusCode::NO_CONTENT
+        );
+        assert_eq!(hub.nodes.read().await.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn releases_sessions_of_dropped_nodes() {
+        let hub = Hub::new(
+            Client::new(),
+            Arc::new(Metrics::new().unwrap()),
+            String::from("http://"),
+            Arc::new(Capacity::new(Some(1))),
+            UpstreamHeaders::default(),
+            RateLimit::new(None, None, 1),
+        );
+        let node = SocketAddr::from(([127, 0, 0, 1], 4445));
+        let session = Uuid::new_v4();
+        hub.register(Registration {
+            address: node,
+            capabilities: Map::new(),
+            max_sessions: None,
+            sessions: vec![session],
+        })
+        .await;
+        let Reservation::Granted(permit) = hub.capacity.reserve() else {
+            panic!("No capacity");
+        };
+        hub.sessions.write().await.insert(
+            session,
+            Routed {
+                node,
+                at: Instant::now(),
+                tenant: None,
+                _permit: permit,
+                _quota: None,
+            },
+        );
+        hub.nodes.write().await.get_mut(&node).unwrap().seen -= NODE_TIMEOUT;
+
+        assert!(hub.select(&json!({ "capabilities": {} })).await.is_none());
+        assert!(hub.sessions.read().await.is_empty());
+        assert!(matches!(hub.capacity.reserve(), Reservation::Granted(_)));
+    }
+}
@@ -0,0 +1,39 @@
+use std::ffi::c_void;
+use std::ptr;
+use windows_sys::Win32::System::JobObjects::{
+    AssignProcessToJobObject, CreateJobObjectW, JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE,
+    JOBOBJECT_EXTENDED_LIMIT_INFORMATION, JobObjectExtendedLimitInformation,
+    SetInformationJobObject,
+};
+use windows_sys::Win32::System::Threading::GetCurrentProcess;
+
+/// Places this process in a job object which terminates every process spawned from it (WebDrivers, their browsers
+/// and ffmpeg) once this process exits, as Windows has no equivalent of `kill_on_drop` for crashes or closed consoles
+pub fn kill_children_on_exit() -> std::io::Result<()> {
+    // SAFETY: The handle is checked before use and deliberately never closed, keeping the job alive for as long as
+    // this process is
+    unsafe {
+        let job = CreateJobObjectW(ptr::null(), ptr::null());
+        if job.is_null() {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        let mut information = JOBOBJECT_EXTENDED_LIMIT_INFORMATION::default();
+        information.BasicLimitInformation.LimitFlags = JOB_OBJECT_LIMIT_KILL_ON_JOB_CLOSE;
+        if SetInformationJobObject(
+            job,
+            JobObjectExtendedLimitInformation,
+            &information as *const JOBOBJECT_EXTENDED_LIMIT_INFORMATION as *const c_void,
+            size_of::<JOBOBJECT_EXTENDED_LIMIT_INFORMATION>() as u32,
+        ) == 0
+        {
+            return Err(std::io::Error::last_os_error());
+        }
+
+        if AssignProcessToJobObject(job, GetCurrentProcess()) == 0 {
+            return Err(std::io::Error::last_os_error());
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,168 @@
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::io::{Error, ErrorKind};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, Command};
+use uuid::Uuid;
+
+/// Time a pod is given to be scheduled and report ready
+const READY_TIMEOUT: &str = "--timeout=300s";
+
+/// Label attached to every pod created, e.g. to find leftovers with `kubectl get pods -l app.kubernetes.io/managed-by`
+const LABEL: &str = "app.kubernetes.io/managed-by";
+
+/// Creates a pod per session from a template using kubectl
+pub struct Kubernetes {
+    pub kubectl: PathBuf,
+    pub namespace: Option<String>,
+    /// Pod manifest converted to JSON
+    pub template: Value,
+    /// Port the WebDriver listens on within pods
+    pub port: u16,
+}
+
+/// A pod created for a session, deleted through [`Pod::remove`]
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct Pod {
+    kubectl: PathBuf,
+    namespace: Option<String>,
+    pub name: String,
+}
+
+impl Kubernetes {
+    /// Reads and validates a pod manifest (YAML or JSON)
+    pub async fn load(
+        kubectl: PathBuf,
+        namespace: Option<String>,
+        template: &Path,
+        port: u16,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let output = Command::new(&kubectl)
+            .args(["create", "--dry-run=client", "--output=json", "--filename"])
+            .arg(template)
+            .output()
+            .await
+            .map_err(|e| format!("Unable to run {:?}: {}", kubectl, e))?;
+        if !output.status.success() {
+            return Err(format!(
+                "Invalid pod template {:?}: {}",
+                template,
+                String::from_utf8_lossy(&output.stderr).trim()
+            )
+            .into());
+        }
+
+        let manifest: Value = serde_json::from_slice(&output.stdout)?;
+        if manifest["kind"] != "Pod" {
+            return Err(format!("{:?} does not describe a Pod", template).into());
+        }
+
+        Ok(Self {
+            kubectl,
+            namespace,
+            template: manifest,
+            port,
+        })
+    }
+
+    /// Creates a pod and waits until it is ready, returning a process following its logs
+    pub async fn start(&self) -> std::io::Result<(Child, IpAddr, Pod)> {
+        let pod = Pod {
+            kubectl: self.kubectl.clone(),
+            namespace: self.namespace.clone(),
+            name: format!("sessiondriver-{}", Uuid::new_v4()),
+        };
+
+        let mut manifest = self.template.clone();
+        if let Some(metadata) = manifest["metadata"].as_object_mut() {
+            metadata.remove("generateName");
+        }
+        manifest["metadata"]["name"] = Value::from(pod.name.as_str());
+        manifest["metadata"]["labels"][LABEL] = Value::from("sessiondriver");
+
+        let mut create = pod.kubectl(["create", "--filename=-"]);
+        create.stdin(Stdio::piped()).stdout(Stdio::null());
+        let mut create = create.spawn()?;
+        if let Some(mut stdin) = create.stdin.take() {
+            stdin.write_all(manifest.to_string().as_bytes()).await?;
+        }
+        let created = create.wait_with_output().await?;
+        if !created.status.success() {
+            return Err(Error::other(format!(
+                "Unable to create pod: {}",
+                String::from_utf8_lossy(&created.stderr).trim()
+            )));
+        }
+        info!("Created pod {}", pod.name);
+
+        match pod.address().await {
+            Ok(address) => {
+                let mut logs = pod.kubectl(["logs", "--follow"]);
+                logs.arg(format!("pod/{}", pod.name))
+                    .stdout(Stdio::piped())
+                    .stderr(Stdio::piped())
+                    .kill_on_drop(true);
+                let logs = logs.spawn()?;
+                Ok((logs, address, pod))
+            }
+            Err(e) => {
+                pod.remove().await;
+                Err(e)
+            }
+        }
+    }
+}
+
+impl Pod {
+    fn kubectl<const N: usize>(&self, args: [&str; N]) -> Command {
+        let mut command = Command::new(&self.kubectl);
+        if let Some(namespace) = &self.namespace {
+            command.arg(format!("--namespace={}", namespace));
+        }
+        command.args(args).stderr(Stdio::piped());
+        command
+    }
+
+    async fn address(&self) -> std::io::Result<IpAddr> {
+        let mut wait = self.kubectl(["wait", "--for=condition=Ready", READY_TIMEOUT]);
+        let waited = wait
+            .arg(format!("pod/{}", self.name))
+            .stdout(Stdio::null())
+            .output()
+            .await?;
+        if !waited.status.success() {
+            return Err(Error::other(format!(
+                "Pod {} did not become ready: {}",
+                self.name,
+                String::from_utf8_lossy(&waited.stderr).trim()
+            )));
+        }
+
+        let mut get = self.kubectl(["get", "--output=jsonpath={.status.podIP}"]);
+        let ip = get.arg(format!("pod/{}", self.name)).output().await?;
+        let ip = String::from_utf8_lossy(&ip.stdout);
+        debug!("Pod {} is reachable at {}", self.name, ip);
+        ip.trim()
+            .parse()
+            .map_err(|e| Error::new(ErrorKind::InvalidData, format!("Pod IP {:?}: {}", ip, e)))
+    }
+
+    pub async fn remove(self) {
+        let mut delete = self.kubectl(["delete", "--wait=false"]);
+        delete.stderr(Stdio::null());
+        let deleted = delete
+            .arg(format!("pod/{}", self.name))
+            .stdout(Stdio::null())
+            .status()
+            .await;
+        match deleted {
+            Ok(status) if status.success() => info!("Deleted pod {}", self.name),
+            Ok(status) => warn!("Unable to delete pod {} ({})", self.name, status),
+            Err(e) => warn!("Unable to delete pod {}: {}", self.name, e),
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Labels a session is created with, e.g. `"sessiondriver:labels": { "team": "checkout" }`
+pub const CAPABILITY: &str = "sessiondriver:labels";
+
+pub type Labels = BTreeMap<String, String>;
+
+/// Takes the labels out of new-session capabilities (`{ "capabilities": ... }`), the first of `firstMatch` if several
+///
+/// They are removed from every set, being this instance's rather than the WebDriver's.
+pub fn take(requested: &mut Value) -> Result<Option<Labels>, String> {
+    let capabilities = &mut requested["capabilities"];
+    let mut sets: Vec<&mut Value> = Vec::new();
+    if let Some(capabilities) = capabilities.as_object_mut() {
+        for (key, value) in capabilities.iter_mut() {
+            match (key.as_str(), value) {
+                ("alwaysMatch", set) => sets.push(set),
+                ("firstMatch", Value::Array(entries)) => sets.extend(entries.iter_mut()),
+                _ => {}
+            }
+        }
+    }
+    let removed: Vec<Value> = sets
+        .into_iter()
+        .filter_map(|set| set.as_object_mut()?.remove(CAPABILITY))
+        .collect();
+    let value = removed.into_iter().next();
+    let Some(value) = value else {
+        return Ok(None);
+    };
+
+    let labels =
+        Labels::deserialize(value).map_err(|e| format!("Invalid {}: {}", CAPABILITY, e))?;
+    // To be selected by, see [`Selector`]
+    if labels
+        .iter()
+        .any(|(key, value)| key.is_empty() || key.contains(['=', ',']) || value.contains(','))
+    {
+        return Err(format!(
+            "Invalid {}: Keys are to be non-empty and neither keys nor values may contain ',' (nor '=' for keys)",
+            CAPABILITY
+        ));
+    }
+
+    Ok(Some(labels))
+}
+
+/// Sessions listed, e.g. `?labels=team=checkout,env=ci` for those having all of the given labels
+#[derive(Debug, Default, Deserialize)]
+#[serde(try_from = "Filter")]
+pub struct Selector(Labels);
+
+#[derive(Deserialize)]
+struct Filter {
+    #[serde(default)]
+    labels: String,
+}
+
+impl TryFrom<Filter> for Selector {
+    type Error = String;
+
+    fn try_from(filter: Filter) -> Result<Self, Self::Error> {
+        filter
+            .labels
+            .split(',')
+            .filter(|label| !label.is_empty())
+            .map(|label| match label.split_once('=') {
+                Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+                _ => Err(format!("Expected labels as key=value, got {:?}", label)),
+            })
+            .collect::<Result<_, _>>()
+            .map(Self)
+    }
+}
+
+impl Selector {
+    pub fn matches(&self, labels: &Labels) -> bool {
+        self.0
+            .iter()
+            .all(|(key, value)| labels.get(key) == Some(value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn takes_labels_to_select_by() {
+        let mut requested = json!({
+            "capabilities": {
+                "alwaysMatch": { "browserName": "firefox", CAPABILITY: { "team": "checkout", "env": "ci" } },
+                "firstMatch": [{ CAPABILITY: { "team": "search" } }, {}]
+            }
+        });
+        let labels = take(&mut requested).unwrap().unwrap();
+        assert_eq!(labels["team"], "checkout");
+        assert_eq!(
+            requested,
+            json!({ "capabilities": { "alwaysMatch": { "browserName": "firefox" }, "firstMatch": [{}, {}] } })
+        );
+
+        let selector = |labels: &str| {
+            Selector::try_from(Filter {
+                labels: labels.to_owned(),
+            })
+        };
+        assert!(selector("team=checkout").unwrap().matches(&labels));
+        assert!(selector("").unwrap().matches(&labels));
+        assert!(!selector("team=checkout,env=prod").unwrap().matches(&labels));
+        assert!(selector("team").is_err());
+
+        let mut invalid =
+            json!({ "capabilities": { "alwaysMatch": { CAPABILITY: { "team": 1 } } } });
+        assert!(take(&mut invalid).is_err());
+    }
+}
@@ -0,0 +1,130 @@
+use serde::Serialize;
+use std::collections::{BTreeMap, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+/// Latencies kept per class to compute percentiles from, the oldest being dropped first
+const SAMPLES: usize = 1000;
+
+/// Kinds of commands whose latencies are told apart
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Class {
+    Navigation,
+    Element,
+    Script,
+    Other,
+}
+
+impl Class {
+    /// Classifies a command by its path below the session, e.g. `/url` or `/element/{id}/click`
+    pub fn of(command: &str) -> Self {
+        let first = command.trim_start_matches('/').split('/').next();
+        match first.unwrap_or_default() {
+            "url" | "back" | "forward" | "refresh" => Class::Navigation,
+            "element" | "elements" => Class::Element,
+            "execute" | "execute_async" => Class::Script,
+            _ => Class::Other,
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+const CLASSES: [Class; 4] = [
+    Class::Navigation,
+    Class::Element,
+    Class::Script,
+    Class::Other,
+];
+
+/// Counts and percentiles of a class of commands, in milliseconds
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct Summary {
+    pub count: u64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub max: f64,
+}
+
+#[derive(Default)]
+struct Samples {
+    count: u64,
+    recent: VecDeque<Duration>,
+}
+
+/// Time the WebDriver of a session took to answer its commands, see [`Class`]
+#[derive(Default)]
+pub struct Latencies(Mutex<[Samples; CLASSES.len()]>);
+
+impl Latencies {
+    pub fn record(&self, class: Class, latency: Duration) {
+        let mut classes = self.0.lock().expect("Latencies lock poisoned");
+        let samples = &mut classes[class.index()];
+        samples.count += 1;
+        if samples.recent.len() == SAMPLES {
+            samples.recent.pop_front();
+        }
+        samples.recent.push_back(latency);
+    }
+
+    /// Summaries of the classes of which commands have been answered
+    pub fn summary(&self) -> BTreeMap<Class, Summary> {
+        let classes = self.0.lock().expect("Latencies lock poisoned");
+        CLASSES
+            .into_iter()
+            .filter(|class| classes[class.index()].count > 0)
+            .map(|class| {
+                let samples = &classes[class.index()];
+                let mut sorted: Vec<Duration> = samples.recent.iter().copied().collect();
+                sorted.sort_unstable();
+                // Nearest rank
+                let percentile = |percent: usize| {
+                    let rank = (percent * sorted.len()).div_ceil(100).max(1);
+                    sorted[rank - 1].as_micros() as f64 / 1000.0
+                };
+                let summary = Summary {
+                    count: samples.count,
+                    p50: percentile(50),
+                    p90: percentile(90),
+                    p99: percentile(99),
+                    max: percentile(100),
+                };
+                (class, summary)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn summarises_per_class() {
+        assert_eq!(Class::of("/url"), Class::Navigation);
+        assert_eq!(Class::of("/element/abc/click"), Class::Element);
+        assert_eq!(Class::of("/execute/sync"), Class::Script);
+        assert_eq!(Class::of(""), Class::Other);
+
+        let latencies = Latencies::default();
+        for ms in 1..=100 {
+            latencies.record(Class::Element, Duration::from_millis(ms));
+        }
+        let summary = latencies.summary();
+        assert_eq!(summary.len(), 1);
+        assert_eq!(
+            summary[&Class::Element],
+            Summary {
+                count: 100,
+                p50: 50.0,
+                p90: 90.0,
+                p99: 99.0,
+                max: 100.0,
+            }
+        );
+    }
+}
@@ -1,13 +1,39 @@
+use async_lock::Mutex;
 use deadpool::managed;
 use fantoccini::error::{CmdError, ErrorStatus, NewSessionError};
 use fantoccini::wd::Capabilities;
 use fantoccini::{Client, ClientBuilder};
-use serde::Deserialize;
+use local::LocalDriver;
+use std::collections::BTreeMap;
 use std::fmt::{Display, Formatter};
-use std::sync::OnceLock;
+use std::future::Future;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, OnceLock};
+use std::time::Duration;
+
+mod acquire;
+mod detach;
+mod diagnostics;
+mod endpoints;
+pub mod local;
+mod preset;
+mod profiles;
+#[cfg(feature = "testing")]
+pub mod testing;
+
+pub use acquire::{get_cancellable, get_until};
+pub use detach::detach;
+pub use endpoints::{DriverStatus, SessionDriverExt, SessionInfo};
+pub use preset::{PresetSession, SessionPreset, get_with_preset};
+pub use profiles::{ProfilePool, ProfileSession};
 
 pub type Pool = managed::Pool<Manager>;
 
+/// Called with a session which failed to be recycled before it is closed, see [`Config::on_recycle_failure`]
+pub type RecycleHook =
+    Arc<dyn Fn(Client) -> Pin<Box<dyn Future<Output = ()> + Send>> + Send + Sync>;
+
 #[derive(Debug)]
 pub enum Error {
     CmdError(CmdError),
@@ -16,6 +42,10 @@ pub enum Error {
     Other(std::io::Error),
     ProxyError(reqwest::Error),
     Stateless,
+    /// Acquisition was cancelled, see [`get_until`]
+    Cancelled,
+    /// No profile of the name has been registered, see [`ProfilePool::get_for`]
+    UnknownProfile(String),
 }
 
 impl Display for Error {
@@ -27,6 +57,8 @@ impl Display for Error {
             Error::Other(error) => Display::fmt(error, f),
             Error::Stateless => f.write_str("Client must create a session"),
             Error::ProxyError(error) => Display::fmt(error, f),
+            Error::Cancelled => f.write_str("Acquisition was cancelled"),
+            Error::UnknownProfile(name) => write!(f, "Unknown profile {:?}", name),
         }
     }
 }
@@ -63,29 +95,101 @@ impl From<reqwest::Error> for Error {
     }
 }
 
+/// A driver the [`Manager`] starts itself, see [`Config::local`]
+#[derive(Clone)]
+pub struct Local {
+    pub program: PathBuf,
+    /// Passed besides `--port`
+    pub args: Vec<String>,
+}
+
+#[derive(Clone)]
 pub struct Config {
+    /// URL of the driver, unless it is `local`
     pub webdriver: String,
+    pub local: Option<Local>,
     pub capabilities: Option<Capabilities>,
     pub disable_ring_provider_init: bool,
+    /// Time after being handed out within which a session is taken back without asking SessionDriver about it
+    pub health_check_interval: Option<Duration>,
+    pub on_recycle_failure: Option<RecycleHook>,
+    /// Where diagnostics of sessions failing to be recycled are written to
+    pub diagnostics_dir: Option<PathBuf>,
+    /// Capability sets by name, each having a pool of its own within a [`ProfilePool`]
+    pub profiles: BTreeMap<String, Capabilities>,
 }
 
 impl Config {
     pub fn new<S: Into<String>>(webdriver: S, capabilities: Option<Capabilities>) -> Self {
         Self {
             webdriver: webdriver.into(),
+            local: None,
             capabilities,
             disable_ring_provider_init: false,
+            health_check_interval: None,
+            on_recycle_failure: None,
+            diagnostics_dir: None,
+            profiles: BTreeMap::new(),
         }
     }
 
+    /// Has the [`Manager`] start `program` on an ephemeral port itself, restarting it if it exits and stopping it once
+    /// the pool is dropped
+    ///
+    /// The driver has to serve sessions concurrently, e.g. chromedriver or `sessiondriver --webdriver=geckodriver`.
+    /// Sessions are checked for being healthy by asking for their URL, as plain WebDrivers lack SessionDriver's own
+    /// endpoints, which is why [`SessionDriverExt`] only works with the latter.
+    pub fn local<P, I, S>(program: P, args: I, capabilities: Option<Capabilities>) -> Self
+    where
+        P: Into<PathBuf>,
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut config = Self::new(String::new(), capabilities);
+        config.local = Some(Local {
+            program: program.into(),
+            args: args.into_iter().map(Into::into).collect(),
+        });
+        config
+    }
+
     pub fn disable_ring_provider_init(&mut self) {
         self.disable_ring_provider_init = true;
     }
+
+    /// Skips checking the health of sessions handed out within `interval`, saving a request per hand-out under high
+    /// churn
+    pub fn health_check_interval(&mut self, interval: Duration) {
+        self.health_check_interval = Some(interval);
+    }
+
+    /// Has `hook` look at sessions deemed unhealthy before they are closed, e.g. to take a screenshot of them
+    pub fn on_recycle_failure<F, Fut>(&mut self, hook: F)
+    where
+        F: Fn(Client) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        self.on_recycle_failure = Some(Arc::new(move |client| Box::pin(hook(client))));
+    }
+
+    /// Collects the current URL, page source and a screenshot of sessions deemed unhealthy into `directory` before
+    /// they are closed, named after the session and the time, to look into recurring failures after the fact
+    pub fn diagnostics_dir<P: Into<PathBuf>>(&mut self, directory: P) {
+        self.diagnostics_dir = Some(directory.into());
+    }
+
+    /// Registers a set of capabilities under `name`, e.g. `mobile-firefox`, to take sessions of with
+    /// [`ProfilePool::get_for`]
+    pub fn profile<S: Into<String>>(&mut self, name: S, capabilities: Capabilities) {
+        self.profiles.insert(name.into(), capabilities);
+    }
 }
 
 pub struct Manager {
     pub config: Config,
     pub http: reqwest::Client,
+    /// Started on demand if the driver is local
+    driver: Mutex<Option<LocalDriver>>,
 }
 
 impl Manager {
@@ -93,7 +197,42 @@ impl Manager {
         Self {
             config,
             http: reqwest::Client::new(),
+            driver: Mutex::new(None),
+        }
+    }
+
+    /// URL of the driver, starting it first if it is local and not running
+    pub(crate) async fn webdriver(&self) -> Result<String, Error> {
+        let Some(local) = &self.config.local else {
+            return Ok(self.config.webdriver.clone());
+        };
+
+        let mut driver = self.driver.lock().await;
+        if let Some(running) = driver.as_mut()
+            && running.running()
+        {
+            return Ok(running.url());
         }
+        let started = LocalDriver::start(&local.program, &local.args).await?;
+        let url = started.url();
+        *driver = Some(started);
+        Ok(url)
+    }
+
+    /// Asks SessionDriver whether the session's WebDriver is ready, or a local driver, which may not be SessionDriver,
+    /// for the session's URL
+    async fn check(&self, client: &Client) -> managed::RecycleResult<Error> {
+        if self.config.local.is_some() {
+            client.current_url().await.map_err(Error::from)?;
+            return Ok(());
+        }
+
+        let status = self.driver_status(client).await?;
+        if !status.ready {
+            return Err(Error::ErrorStatus(ErrorStatus::UnknownError).into());
+        }
+
+        Ok(())
     }
 }
 
@@ -119,7 +258,7 @@ impl managed::Manager for Manager {
             builder.capabilities(capabilities.clone());
         }
 
-        let client = builder.connect(&self.config.webdriver).await?;
+        let client = builder.connect(&self.webdriver().await?).await?;
 
         Ok(client)
     }
@@ -127,36 +266,28 @@ impl managed::Manager for Manager {
     async fn recycle(
         &self,
         client: &mut Client,
-        _: &managed::Metrics,
+        metrics: &managed::Metrics,
     ) -> managed::RecycleResult<Error> {
-        let session = match client.session_id().await.map_err(Error::from)? {
-            Some(session) => session,
-            None => return Err(Error::Stateless.into()),
-        };
-
-        #[derive(Deserialize)]
-        struct Value {
-            value: Status,
-        }
-
-        #[derive(Deserialize)]
-        struct Status {
-            ready: bool,
-            message: String,
+        // Sessions handed out (or created) recently are assumed to still be fine
+        if self
+            .config
+            .health_check_interval
+            .is_some_and(|interval| metrics.last_used() < interval)
+        {
+            return Ok(());
         }
 
-        let request = self
-            .http
-            .get(format!("/session/driver/{}/status", session))
-            .send()
-            .await
-            .map_err(Error::from)?;
-        let status: Value = request.json().await.map_err(Error::from)?;
-        if !status.value.ready {
-            return Err(Error::ErrorStatus(ErrorStatus::UnknownError).into());
+        let healthy = self.check(client).await;
+        if healthy.is_err() {
+            if let Some(directory) = &self.config.diagnostics_dir {
+                diagnostics::capture(client, directory).await;
+            }
+            if let Some(hook) = &self.config.on_recycle_failure {
+                hook(client.clone()).await;
+            }
         }
 
-        Ok(())
+        healthy
     }
 }
 
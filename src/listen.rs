@@ -0,0 +1,49 @@
+use socket2::{Domain, Protocol, Socket, Type};
+use std::io;
+use std::net::SocketAddr;
+
+/// Connections waiting to be accepted before further ones are refused
+const BACKLOG: i32 = 1024;
+
+/// Binds `address`, on the unspecified IPv6 address (`::`) accepting IPv4 connections as well regardless of the system's
+/// default (e.g. `net.ipv6.bindv6only`)
+pub fn bind(address: SocketAddr) -> io::Result<std::net::TcpListener> {
+    let socket = Socket::new(
+        Domain::for_address(address),
+        Type::STREAM,
+        Some(Protocol::TCP),
+    )?;
+    if address.is_ipv6() {
+        socket.set_only_v6(!address.ip().is_unspecified())?;
+    }
+    // As Tokio does, so restarts are not kept from binding by connections in TIME_WAIT
+    #[cfg(unix)]
+    socket.set_reuse_address(true)?;
+    socket.bind(&address.into())?;
+    socket.listen(BACKLOG)?;
+    socket.set_nonblocking(true)?;
+
+    Ok(socket.into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{Ipv4Addr, Ipv6Addr, TcpStream};
+
+    #[test]
+    fn accepts_ipv4_and_ipv6() {
+        let listener = bind(SocketAddr::new(Ipv6Addr::UNSPECIFIED.into(), 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        TcpStream::connect((Ipv4Addr::LOCALHOST, port)).unwrap();
+        TcpStream::connect((Ipv6Addr::LOCALHOST, port)).unwrap();
+
+        let listener = bind(SocketAddr::new(Ipv6Addr::LOCALHOST.into(), 0)).unwrap();
+        let address = listener.local_addr().unwrap();
+        assert_eq!(
+            format!("http://{}", address),
+            format!("http://[::1]:{}", address.port())
+        );
+        TcpStream::connect(address).unwrap();
+    }
+}
@@ -0,0 +1,115 @@
+use std::ffi::OsStr;
+use std::io::{Error, ErrorKind};
+use std::net::{Ipv4Addr, TcpListener};
+use std::process::{Child, Command, Stdio};
+use std::time::{Duration, Instant};
+
+/// Time a driver is given to report ready
+const READINESS_TIMEOUT: Duration = Duration::from_secs(30);
+/// Time a driver is given to exit, e.g. for SessionDriver to end its sessions, before it is killed
+const EXIT_GRACE: Duration = Duration::from_secs(10);
+
+/// A driver listening on an ephemeral port of `127.0.0.1`, which is stopped once dropped
+pub struct LocalDriver {
+    process: Child,
+    port: u16,
+}
+
+impl LocalDriver {
+    /// Starts `program` with `--port` and `args`, waiting until `/status` reports it ready
+    pub async fn start<P, I, S>(program: P, args: I) -> std::io::Result<Self>
+    where
+        P: AsRef<OsStr>,
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        // Released right before the program binds it
+        let port = TcpListener::bind((Ipv4Addr::LOCALHOST, 0))?
+            .local_addr()?
+            .port();
+        let mut command = Command::new(program);
+        command
+            .arg(format!("--port={}", port))
+            .args(args)
+            .stdout(Stdio::null())
+            .stderr(Stdio::null());
+        // So that whatever it started is stopped along with it
+        #[cfg(unix)]
+        std::os::unix::process::CommandExt::process_group(&mut command, 0);
+
+        let mut driver = Self {
+            process: command.spawn()?,
+            port,
+        };
+        driver.ready().await?;
+        Ok(driver)
+    }
+
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+
+    pub fn url(&self) -> String {
+        format!("http://127.0.0.1:{}", self.port)
+    }
+
+    /// Whether the driver has not exited
+    pub fn running(&mut self) -> bool {
+        matches!(self.process.try_wait(), Ok(None))
+    }
+
+    async fn ready(&mut self) -> std::io::Result<()> {
+        let http = reqwest::Client::new();
+        let started = Instant::now();
+        loop {
+            if let Some(status) = self.process.try_wait()? {
+                return Err(Error::other(format!("Exited with {}", status)));
+            }
+            let ready = match http.get(format!("{}/status", self.url())).send().await {
+                Ok(response) => response
+                    .json::<serde_json::Value>()
+                    .await
+                    .is_ok_and(|status| status["value"]["ready"] == true),
+                Err(_) => false,
+            };
+            if ready {
+                return Ok(());
+            }
+            if started.elapsed() > READINESS_TIMEOUT {
+                return Err(Error::new(
+                    ErrorKind::TimedOut,
+                    format!("Not ready after {:?}", READINESS_TIMEOUT),
+                ));
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+    }
+
+    fn signal(&self, signal: &str) {
+        #[cfg(unix)]
+        let _ = Command::new("kill")
+            .args(["-s", signal, "--", &format!("-{}", self.process.id())])
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status();
+        #[cfg(not(unix))]
+        let _ = signal;
+    }
+}
+
+impl Drop for LocalDriver {
+    fn drop(&mut self) {
+        self.signal("TERM");
+        let asked = Instant::now();
+        while asked.elapsed() < EXIT_GRACE {
+            if !self.running() {
+                break;
+            }
+            std::thread::sleep(Duration::from_millis(50));
+        }
+        let _ = self.process.kill();
+        let _ = self.process.wait();
+        // Browsers may outlive the WebDriver which started them
+        self.signal("KILL");
+    }
+}
@@ -0,0 +1,143 @@
+use crate::forwarded::ClientAddress;
+use axum::extract::Request;
+use axum::http::{HeaderName, HeaderValue};
+use axum::middleware::Next;
+use axum::response::Response;
+use clap::ValueEnum;
+use log::Record;
+use log::info;
+use log::kv::{self, Key, Value, VisitSource, VisitValue};
+use serde_json::Map;
+use std::io::Write;
+use std::net::SocketAddr;
+use std::time::Instant;
+use uuid::Uuid;
+
+pub const REQUEST_ID: HeaderName = HeaderName::from_static("x-request-id");
+
+#[derive(ValueEnum, Debug, Clone, Copy, Default)]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+pub fn init(format: LogFormat) {
+    let mut builder = env_logger::Builder::from_default_env();
+    if let LogFormat::Json = format {
+        builder.format(json);
+    }
+    builder.init();
+}
+
+fn json(buf: &mut env_logger::fmt::Formatter, record: &Record) -> std::io::Result<()> {
+    let mut entry = Map::new();
+    entry.insert("timestamp".into(), buf.timestamp().to_string().into());
+    entry.insert("level".into(), record.level().as_str().into());
+    entry.insert("target".into(), record.target().into());
+    entry.insert("message".into(), record.args().to_string().into());
+    let _ = record.key_values().visit(&mut Fields(&mut entry));
+
+    serde_json::to_writer(&mut *buf, &entry)?;
+    writeln!(buf)
+}
+
+struct Fields<'a>(&'a mut Map<String, serde_json::Value>);
+
+impl<'kvs> VisitSource<'kvs> for Fields<'_> {
+    fn visit_pair(&mut self, key: Key<'kvs>, value: Value<'kvs>) -> Result<(), kv::Error> {
+        let mut field = Field(serde_json::Value::Null);
+        value.visit(&mut field)?;
+        self.0.insert(key.as_str().to_owned(), field.0);
+
+        Ok(())
+    }
+}
+
+struct Field(serde_json::Value);
+
+impl<'v> VisitValue<'v> for Field {
+    fn visit_any(&mut self, value: Value) -> Result<(), kv::Error> {
+        self.0 = value.to_string().into();
+        Ok(())
+    }
+
+    fn visit_null(&mut self) -> Result<(), kv::Error> {
+        self.0 = serde_json::Value::Null;
+        Ok(())
+    }
+
+    fn visit_u64(&mut self, value: u64) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_i64(&mut self, value: i64) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_f64(&mut self, value: f64) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_bool(&mut self, value: bool) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+
+    fn visit_str(&mut self, value: &str) -> Result<(), kv::Error> {
+        self.0 = value.into();
+        Ok(())
+    }
+}
+
+/// Attached to proxied responses to tell the access log which session and driver served them
+#[derive(Debug, Clone, Copy)]
+pub struct Upstream {
+    pub session: Uuid,
+    pub address: SocketAddr,
+}
+
+/// Logs every request once it has been answered, tagged with a request id
+pub async fn access(request: Request, next: Next) -> Response {
+    let client = request
+        .extensions()
+        .get::<ClientAddress>()
+        .map(|ClientAddress(address)| address.to_string());
+    let request_id = request
+        .headers()
+        .get(&REQUEST_ID)
+        .and_then(|value| value.to_str().ok())
+        .map(String::from)
+        .unwrap_or_else(|| Uuid::new_v4().to_string());
+    let method = request.method().clone();
+    let path = request.uri().path().to_owned();
+    let started = Instant::now();
+
+    let mut response = next.run(request).await;
+
+    let upstream = response.extensions().get::<Upstream>().copied();
+    let session = upstream.map(|upstream| upstream.session.to_string());
+    let address = upstream.map(|upstream| upstream.address.to_string());
+    let status = response.status().as_u16();
+    info!(
+        target: "sessiondriver::access",
+        request_id = request_id.as_str(),
+        client = client.as_deref(),
+        method = method.as_str(),
+        path = path.as_str(),
+        session = session.as_deref(),
+        upstream = address.as_deref(),
+        status = status,
+        latency_ms = started.elapsed().as_millis() as u64;
+        "{} {} {}", method, path, status
+    );
+
+    if let Ok(value) = HeaderValue::from_str(&request_id) {
+        response.headers_mut().insert(REQUEST_ID, value);
+    }
+
+    response
+}
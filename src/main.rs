@@ -1,30 +1,169 @@
 use async_lock::{Mutex, RwLock};
 use axum::body::{Body, to_bytes};
 use axum::extract::{FromRef, Request, State};
-use axum::http::{Method, StatusCode};
-use axum::response::{IntoResponse, Response};
-use axum::{Router, ServiceExt};
+use axum::http::{HeaderName, HeaderValue, Method, StatusCode, header};
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::get;
+use axum::{Router, ServiceExt, middleware};
+use axum_server::Handle;
+use axum_server::tls_rustls::RustlsConfig;
 use clap::Parser;
-use log::{debug, error, info};
+use ipnet::IpNet;
+use log::{debug, error, info, warn};
+use probe::Probe;
 use reqwest::{Client, Url};
+use scaling::Watermarks;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
+use shards::Shards;
+use spawning::SpawnQueue;
+use std::collections::HashSet;
+use std::mem;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
-use std::path::Path;
-use std::process::{Stdio, exit};
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::process::{ExitStatus, Stdio};
 use std::str::FromStr;
 use std::sync::Arc;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::time::{Duration, Instant, SystemTime};
 use tokio::net::TcpListener;
 use tokio::process::{Child, Command};
 use tokio::signal;
-use tokio::task::JoinHandle;
+use tokio::task::JoinSet;
 use tokio::time::sleep;
+use tower_http::compression::CompressionLayer;
+use tower_http::compression::predicate::{DefaultPredicate, Predicate, SizeAbove};
+use tracing::{Span, instrument};
 use uuid::Uuid;
 
+mod admin;
+mod affinity;
+mod allowlist;
+mod artifacts;
+mod audit;
+mod auth;
+mod branding;
+mod capacity;
+mod cassette;
+mod chaos;
+mod config;
+mod cors;
+mod crash;
+mod docker;
+mod downloads;
+mod dump;
+mod expiry;
+mod forwarded;
+mod har;
+mod health;
+mod hub;
+#[cfg(windows)]
+mod job;
+mod kubernetes;
+mod labels;
+mod latency;
+mod listen;
+mod logging;
+mod metrics;
+mod output;
+mod overload;
+mod persist;
+mod policy;
+mod ports;
+mod probe;
+mod ratelimit;
+mod recording;
+mod recovery;
+mod remote;
+mod replay;
+mod safety;
+mod scaling;
+mod screenshot;
+mod shards;
+mod spawning;
+mod stop;
+mod systemd;
+mod telemetry;
+mod tenant;
+mod ui;
+mod upload;
+mod upstream;
+mod usage;
+mod version;
+mod w3c;
+mod webhook;
+
+use affinity::Affinity;
+use allowlist::Allowlist;
+use audit::{AuditLog, Head, Tee};
+use auth::{Quota, Tokens};
+use branding::Branding;
+use capacity::{Capacity, Limit, Permit, Reservation};
+use cassette::{Cassette, Interaction};
+use chaos::{Chaos, Fault};
+use crash::CrashArchive;
+use docker::{Container, Docker};
+use downloads::Downloads;
+use dump::DebugDump;
+use expiry::{Expiry, InFlight};
+use forwarded::ClientAddress;
+use har::{Capture, HarArchive};
+use health::Health;
+use hub::Hub;
+use kubernetes::{Kubernetes, Pod};
+use labels::Labels;
+use latency::{Class, Latencies};
+use logging::{LogFormat, Upstream};
+use metrics::Metrics;
+use output::{DriverOutput, LogEntry};
+use overload::Limits;
+use policy::Policy;
+use ports::{Lease, Ports};
+use ratelimit::RateLimit;
+use recording::{Recorder, Recording};
+use remote::Remote;
+use replay::Replay;
+use safety::{Flavor, SafetyFlags};
+use screenshot::ScreenshotArchive;
+use stop::Driver;
+use tenant::Tenant;
+use upload::Uploads;
+use upstream::UpstreamHeaders;
+use usage::Usage;
+use webhook::{EventKind, Webhook};
+
+/// Interval at which WebDriver processes are checked for having exited
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Interval at which a session is checked for requests still being proxied to it, see [`Browser::exclusive`]
+const SHARED_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Headers only meaningful for a single connection, which are not forwarded in either direction
+const HOP_BY_HOP: [&str; 8] = [
+    "connection",
+    "keep-alive",
+    "proxy-authenticate",
+    "proxy-authorization",
+    "te",
+    "trailer",
+    "transfer-encoding",
+    "upgrade",
+];
+
+/// Responses smaller than this are not worth compressing
+const COMPRESSION_THRESHOLD: u16 = 1024;
+
+/// Largest body of a legacy log request read to find the requested type
+const LOG_REQUEST_LIMIT: usize = 64 * 1024;
+
 #[derive(Parser, Debug)]
 #[command(version, about, long_about = None)]
 struct Args {
+    /// TOML file providing defaults for any of these options (e.g. max-sessions = 4)
+    /// (Flags and environment variables take precedence)
+    #[arg(env = "SESSIONDRIVER_CONFIG", long)]
+    pub config: Option<PathBuf>,
+
     /// Name of the person to greet
     #[arg(env = "SESSIONDRIVER_PORT", long, default_value_t = 4444)]
     pub port: u16,
@@ -33,9 +172,37 @@ struct Args {
     #[arg(env = "SESSIONDRIVER_HOST", long, default_value_t = IpAddr::V4(Ipv4Addr::new(0, 0, 0, 0)))]
     pub host: IpAddr,
 
+    /// Address spawned WebDrivers listen on and are reached at
+    /// (Independent of --host, so WebDrivers are not exposed without authentication)
+    #[arg(env = "SESSIONDRIVER_DRIVER_HOST", long, default_value_t = IpAddr::V4(Ipv4Addr::LOCALHOST))]
+    pub driver_host: IpAddr,
+
+    /// Ports spawned WebDrivers (or their containers) listen on, e.g. 4445-5444
+    /// (New sessions are answered with 503 while every port is in use)
+    #[arg(env = "SESSIONDRIVER_PORT_RANGE", long, value_parser = ports::parse_range, default_value = "4445-65535")]
+    pub port_range: RangeInclusive<u16>,
+
     /// Location of WebDriver executable
-    #[arg(env = "SESSIONDRIVER_WEBDRIVER", long)]
-    pub webdriver: Box<Path>,
+    #[arg(
+        env = "SESSIONDRIVER_WEBDRIVER",
+        long,
+        required_unless_present_any = ["docker_image", "kubernetes_pod_template", "upstream", "hub", "replay_dir"]
+    )]
+    pub webdriver: Option<Box<Path>>,
+
+    /// Only checks that --webdriver is an executable file at startup, for WebDrivers not supporting --version
+    #[arg(env = "SESSIONDRIVER_SKIP_VERSION_CHECK", long)]
+    pub skip_version_check: bool,
+
+    /// The WebDriver is an Appium server, which is started with --address and --base-path=/ instead of --host
+    #[arg(env = "SESSIONDRIVER_APPIUM", long)]
+    pub appium: bool,
+
+    /// Does not restrict a detected chromedriver (--allowed-ips, --allowed-origins) or geckodriver (--allow-origins) to
+    /// requests passed on by this instance
+    /// (Flags already among --parameters are never added)
+    #[arg(env = "SESSIONDRIVER_NO_SAFETY_FLAGS", long)]
+    pub no_safety_flags: bool,
 
     /// Time after which a browser is asked to shut down
     #[arg(env = "SESSIONDRIVER_TTI", long, value_parser = parse_duration, default_value_t = WrappedDuration(Duration::from_secs(43200)))]
@@ -49,6 +216,458 @@ struct Args {
     /// Protocol used to communicate with browsers
     #[arg(env = "SESSIONDRIVER_PROTOCOL", long, default_value_t = String::from("http://"))]
     pub protocol: String,
+
+    /// Name of this instance, sent in --node-header on new sessions for sticky routing at a load balancer
+    /// (Requests naming another node are answered with 421 if their session is unknown)
+    #[arg(env = "SESSIONDRIVER_NODE", long)]
+    pub node: Option<HeaderValue>,
+
+    /// Header carrying --node
+    #[arg(
+        env = "SESSIONDRIVER_NODE_HEADER",
+        long,
+        default_value = "X-SessionDriver-Node"
+    )]
+    pub node_header: HeaderName,
+
+    /// Maximum number of concurrently managed sessions
+    /// (Unlimited if unset)
+    #[arg(env = "SESSIONDRIVER_MAX_SESSIONS", long)]
+    pub max_sessions: Option<usize>,
+
+    /// Time a WebDriver is given to exit on its own once its session has ended, before it and its browser are killed
+    #[arg(env = "SESSIONDRIVER_DRIVER_STOP_GRACE", long, value_parser = parse_duration, default_value_t = WrappedDuration(Duration::from_secs(5)))]
+    pub driver_stop_grace: WrappedDuration,
+
+    /// Time WebDrivers and their browsers are given to exit on shutdown before they are killed
+    /// (All sessions are ended at once, unless they are kept for --state-file)
+    #[arg(env = "SESSIONDRIVER_SHUTDOWN_GRACE", long, value_parser = parse_duration, default_value_t = WrappedDuration(Duration::from_secs(10)))]
+    pub shutdown_grace: WrappedDuration,
+
+    /// Replaces a WebDriver which exits on its own by a new one, on which the session is created anew with the same
+    /// capabilities while keeping its ID (Responses then carry X-SessionDriver-Recovered)
+    #[arg(env = "SESSIONDRIVER_RECOVER", long)]
+    pub recover: bool,
+
+    /// Path polled until a started WebDriver answers successfully
+    #[arg(env = "SESSIONDRIVER_PROBE_PATH", long, default_value_t = String::from("/status"))]
+    pub probe_path: String,
+
+    /// Considers started WebDrivers ready as soon as their port accepts connections, instead of polling --probe-path
+    #[arg(env = "SESSIONDRIVER_PROBE_TCP", long)]
+    pub probe_tcp: bool,
+
+    /// Time between polls of a started WebDriver
+    #[arg(env = "SESSIONDRIVER_PROBE_INTERVAL", long, value_parser = parse_duration, default_value_t = WrappedDuration(Duration::from_millis(125)))]
+    pub probe_interval: WrappedDuration,
+
+    /// Number of polls after which a WebDriver is given up on and its session request answered with 502
+    #[arg(env = "SESSIONDRIVER_PROBE_ATTEMPTS", long, default_value_t = 480)]
+    pub probe_attempts: u32,
+
+    /// Maximum number of WebDrivers starting at once, further sessions waiting for their turn
+    /// (Unlimited if unset)
+    #[arg(env = "SESSIONDRIVER_MAX_SPAWNS", long)]
+    pub max_spawns: Option<usize>,
+
+    /// Minimum time between starting two WebDrivers
+    #[arg(env = "SESSIONDRIVER_SPAWN_STAGGER", long, value_parser = parse_duration, default_value_t = WrappedDuration(Duration::ZERO))]
+    pub spawn_stagger: WrappedDuration,
+
+    /// Maximum number of concurrent sessions requesting a capability value, e.g. browserName=chrome:5
+    /// (Repeatable, requests beyond a limit are answered with 503)
+    #[arg(env = "SESSIONDRIVER_CAPABILITY_LIMIT", long, value_delimiter = ',', value_parser = capacity::parse_limit)]
+    pub capability_limit: Vec<Limit>,
+
+    /// Time a connection to a WebDriver is kept open without being used
+    #[arg(env = "SESSIONDRIVER_DRIVER_IDLE_TIMEOUT", long, value_parser = parse_duration, default_value_t = WrappedDuration(Duration::from_secs(90)))]
+    pub driver_idle_timeout: WrappedDuration,
+
+    /// Maximum number of idle connections kept open to each WebDriver
+    #[arg(env = "SESSIONDRIVER_DRIVER_CONNECTIONS", long, default_value_t = 8)]
+    pub driver_connections: usize,
+
+    /// Time between samples of the CPU and memory used by each session's WebDriver and browser
+    /// (Only on Linux, 0s disables sampling)
+    #[arg(env = "SESSIONDRIVER_USAGE_INTERVAL", long, value_parser = parse_duration, default_value_t = WrappedDuration(Duration::from_secs(5)))]
+    pub usage_interval: WrappedDuration,
+
+    /// Number of recent WebDriver output lines retained per session
+    #[arg(env = "SESSIONDRIVER_DRIVER_LOG_LINES", long, default_value_t = 1000)]
+    pub driver_log_lines: usize,
+
+    /// Directory the requests and responses of sessions requesting "sessiondriver:debugDump": true are written to
+    /// (Files are named after the session, nothing is written unless set)
+    #[arg(env = "SESSIONDRIVER_DEBUG_DUMP_DIR", long)]
+    pub debug_dump_dir: Option<PathBuf>,
+
+    /// Number of body bytes written per request and response to --debug-dump-dir
+    #[arg(
+        env = "SESSIONDRIVER_DEBUG_DUMP_BODY_LIMIT",
+        long,
+        default_value_t = 65536
+    )]
+    pub debug_dump_body_limit: usize,
+
+    /// Directory the requests and responses of sessions requesting "sessiondriver:cassette": "<name>" are recorded
+    /// to as <name>.jsonl, to be replayed with --replay-dir (Nothing is recorded unless set)
+    #[arg(env = "SESSIONDRIVER_CASSETTE_DIR", long)]
+    pub cassette_dir: Option<PathBuf>,
+
+    /// Directory the exit status and recent output of WebDrivers exiting on their own are kept in, per session
+    /// (Further requests of such sessions are answered with 410 naming the directory)
+    #[arg(env = "SESSIONDRIVER_CRASH_DIR", long)]
+    pub crash_dir: Option<PathBuf>,
+
+    /// Number of bytes of a crashed WebDriver's most recent output kept in --crash-dir
+    #[arg(
+        env = "SESSIONDRIVER_CRASH_OUTPUT_LIMIT",
+        long,
+        default_value_t = 65536
+    )]
+    pub crash_output_limit: usize,
+
+    /// Allows WebDrivers to dump core, which is moved to --crash-dir
+    /// (Requires a relative core_pattern such as core.%p on Linux)
+    #[arg(env = "SESSIONDRIVER_CRASH_CORE_DUMPS", long, requires = "crash_dir")]
+    pub crash_core_dumps: bool,
+
+    /// Directory commands proxied per session are appended to as JSON lines
+    /// (Files are named after the session, nothing is written unless set)
+    #[arg(env = "SESSIONDRIVER_AUDIT_DIR", long)]
+    pub audit_dir: Option<Box<Path>>,
+
+    /// Directory screen recordings of sessions are written to
+    /// (Recording is disabled unless set, requires ffmpeg and an X display shared by all browsers)
+    #[arg(env = "SESSIONDRIVER_RECORD_DIR", long)]
+    pub record_dir: Option<PathBuf>,
+
+    /// X display captured while recording
+    #[arg(env = "SESSIONDRIVER_RECORD_DISPLAY", long, default_value_t = String::from(":0"))]
+    pub record_display: String,
+
+    /// Location of ffmpeg executable used for recording
+    #[arg(env = "SESSIONDRIVER_FFMPEG", long, default_value = "ffmpeg")]
+    pub ffmpeg: PathBuf,
+
+    /// Directory a final screenshot of deleted and expired sessions is archived to
+    /// (Disabled unless set)
+    #[arg(env = "SESSIONDRIVER_SCREENSHOT_DIR", long)]
+    pub screenshot_dir: Option<PathBuf>,
+
+    /// Time after which archived screenshots are removed
+    #[arg(env = "SESSIONDRIVER_SCREENSHOT_RETENTION", long, value_parser = parse_duration, default_value_t = WrappedDuration(Duration::from_secs(604800)))]
+    pub screenshot_retention: WrappedDuration,
+
+    /// URL session lifecycle events are posted to as JSON
+    /// (Events are created, deleted, expired, killed, crashed and expiring)
+    #[arg(env = "SESSIONDRIVER_WEBHOOK_URL", long)]
+    pub webhook_url: Option<String>,
+
+    /// Number of sessions at or above which --watermark-command is run and --watermark-url notified
+    #[arg(env = "SESSIONDRIVER_HIGH_WATERMARK", long)]
+    pub high_watermark: Option<usize>,
+
+    /// Number of sessions at or below which --watermark-command is run and --watermark-url notified
+    #[arg(env = "SESSIONDRIVER_LOW_WATERMARK", long)]
+    pub low_watermark: Option<usize>,
+
+    /// Shell command run once a watermark is reached, with SESSIONDRIVER_WATERMARK (high or low) and
+    /// SESSIONDRIVER_SESSIONS set
+    #[arg(env = "SESSIONDRIVER_WATERMARK_COMMAND", long)]
+    pub watermark_command: Option<String>,
+
+    /// URL an event is posted to as JSON once a watermark is reached
+    #[arg(env = "SESSIONDRIVER_WATERMARK_URL", long)]
+    pub watermark_url: Option<String>,
+
+    /// Time before an idle session expires at which an expiring event is posted to --webhook-url
+    #[arg(env = "SESSIONDRIVER_EXPIRY_WARNING", long, value_parser = parse_duration, requires = "webhook_url")]
+    pub expiry_warning: Option<WrappedDuration>,
+
+    /// Before expiring an idle session, check whether it is still busy (e.g. running a long asynchronous script) and
+    /// extend it by its TTI if so
+    /// (Busy meaning a request of it is being proxied or its WebDriver does not answer within 5s)
+    #[arg(env = "SESSIONDRIVER_ACTIVITY_CHECK", long)]
+    pub activity_check: bool,
+
+    /// Image each WebDriver is started from as a container instead of running --webdriver
+    /// (The image's entrypoint must be a WebDriver accepting --port and --host)
+    #[arg(env = "SESSIONDRIVER_DOCKER_IMAGE", long)]
+    pub docker_image: Option<String>,
+
+    /// Network containers are attached to
+    #[arg(env = "SESSIONDRIVER_DOCKER_NETWORK", long)]
+    pub docker_network: Option<String>,
+
+    /// Size of /dev/shm within containers
+    #[arg(env = "SESSIONDRIVER_DOCKER_SHM_SIZE", long, default_value = "2g")]
+    pub docker_shm_size: String,
+
+    /// Port the WebDriver listens on within containers
+    #[arg(env = "SESSIONDRIVER_DOCKER_PORT", long, default_value_t = 4444)]
+    pub docker_port: u16,
+
+    /// Location of docker executable
+    #[arg(env = "SESSIONDRIVER_DOCKER", long, default_value = "docker")]
+    pub docker: PathBuf,
+
+    /// Pod manifest (YAML or JSON) each WebDriver is started from instead of running --webdriver
+    /// (Its WebDriver has to listen on --kubernetes-port, and the pod IP has to be reachable from here)
+    #[arg(
+        env = "SESSIONDRIVER_KUBERNETES_POD_TEMPLATE",
+        long,
+        conflicts_with = "docker_image"
+    )]
+    pub kubernetes_pod_template: Option<PathBuf>,
+
+    /// Namespace pods are created in
+    /// (The current context's namespace unless set)
+    #[arg(env = "SESSIONDRIVER_KUBERNETES_NAMESPACE", long)]
+    pub kubernetes_namespace: Option<String>,
+
+    /// Port the WebDriver listens on within pods
+    #[arg(env = "SESSIONDRIVER_KUBERNETES_PORT", long, default_value_t = 4444)]
+    pub kubernetes_port: u16,
+
+    /// Location of kubectl executable
+    #[arg(env = "SESSIONDRIVER_KUBECTL", long, default_value = "kubectl")]
+    pub kubectl: PathBuf,
+
+    /// WebDriver endpoint new sessions are created at instead of spawning --webdriver
+    /// (Repeatable or comma separated, e.g. https://grid.example.com/wd/hub, endpoints are used in turn)
+    #[arg(env = "SESSIONDRIVER_UPSTREAM", long, value_delimiter = ',')]
+    pub upstream: Vec<Url>,
+
+    /// Make Firefox, Chrome and Edge run headless regardless of the capabilities requested
+    #[arg(env = "SESSIONDRIVER_FORCE_HEADLESS", long)]
+    pub force_headless: bool,
+
+    /// Keep --no-sandbox and --disable-setuid-sandbox in requested Chrome and Edge arguments
+    /// (They are removed unless set)
+    #[arg(env = "SESSIONDRIVER_ALLOW_NO_SANDBOX", long)]
+    pub allow_no_sandbox: bool,
+
+    /// Directory browsers are made to download files to
+    #[arg(env = "SESSIONDRIVER_DOWNLOAD_DIR", long)]
+    pub download_dir: Option<PathBuf>,
+
+    /// Directory every session gets a download directory of its own in, listed at
+    /// /session/{uuid}/sessiondriver/downloads (Takes precedence over --download-dir)
+    #[arg(
+        env = "SESSIONDRIVER_SESSION_DOWNLOADS_DIR",
+        long,
+        conflicts_with_all = ["docker_image", "kubernetes_pod_template", "upstream", "hub"]
+    )]
+    pub session_downloads_dir: Option<PathBuf>,
+
+    /// Directory the HTTP traffic of each session's browser is written to as HAR once the session ends, served at
+    /// /sessiondriver/har/{uuid} (Browsers are pointed at a proxy of their own through the proxy capability)
+    #[arg(
+        env = "SESSIONDRIVER_HAR_DIR",
+        long,
+        conflicts_with_all = ["docker_image", "kubernetes_pod_template", "upstream", "hub"]
+    )]
+    pub har_dir: Option<PathBuf>,
+
+    /// Directory every session gets a directory of its own in, which files pushed to
+    /// /session/{uuid}/se/file are written to
+    /// (Local WebDrivers only, the browser has to be able to read them)
+    #[arg(
+        env = "SESSIONDRIVER_UPLOAD_DIR",
+        long,
+        conflicts_with_all = ["docker_image", "kubernetes_pod_template", "upstream", "hub"]
+    )]
+    pub upload_dir: Option<PathBuf>,
+
+    /// Number of bytes files pushed to --upload-dir may take up, both as encoded by clients (i.e. a base64 encoded zip
+    /// archive, about a third larger than the file unless it compresses well) and once extracted
+    #[arg(env = "SESSIONDRIVER_UPLOAD_LIMIT", long, default_value_t = 268435456)]
+    pub upload_limit: usize,
+
+    /// Capability new sessions are rejected for requesting
+    /// (Repeatable or comma separated, e.g. moz:debuggerAddress)
+    #[arg(env = "SESSIONDRIVER_DENY_CAPABILITY", long, value_delimiter = ',')]
+    pub deny_capability: Vec<String>,
+
+    /// File sessions are kept in, so that a restart adopts the WebDrivers still running instead of stopping them
+    /// (WebDriver output is discarded instead of captured while set)
+    #[arg(env = "SESSIONDRIVER_STATE_FILE", long, conflicts_with = "hub")]
+    pub state_file: Option<PathBuf>,
+
+    /// Route sessions to the nodes registered with this instance instead of spawning WebDrivers
+    #[arg(env = "SESSIONDRIVER_HUB", long, requires = "hub_token")]
+    pub hub: bool,
+
+    /// Directory of cassettes recorded with --cassette-dir, which sessions requesting "sessiondriver:cassette" are
+    /// answered from instead of spawning WebDrivers
+    #[arg(
+        env = "SESSIONDRIVER_REPLAY_DIR",
+        long,
+        conflicts_with_all = ["docker_image", "kubernetes_pod_template", "upstream", "hub", "webdriver"]
+    )]
+    pub replay_dir: Option<PathBuf>,
+
+    /// Hub this instance registers with as a node
+    /// (e.g. http://hub:4444/, requires --node-address)
+    #[arg(
+        env = "SESSIONDRIVER_HUB_URL",
+        long,
+        requires = "node_address",
+        conflicts_with = "hub"
+    )]
+    pub hub_url: Option<Url>,
+
+    /// Bearer token presented when registering with --hub-url, or which nodes have to present to register with --hub
+    #[arg(env = "SESSIONDRIVER_HUB_TOKEN", long, value_parser = clap::builder::NonEmptyStringValueParser::new())]
+    pub hub_token: Option<String>,
+
+    /// Address the hub reaches this node at
+    #[arg(env = "SESSIONDRIVER_NODE_ADDRESS", long)]
+    pub node_address: Option<SocketAddr>,
+
+    /// JSON object of capabilities new sessions routed to this node have to be compatible with
+    /// (e.g. {"browserName":"firefox"})
+    #[arg(env = "SESSIONDRIVER_NODE_CAPABILITIES", long, value_parser = hub::parse_capabilities)]
+    pub node_capabilities: Option<serde_json::Map<String, serde_json::Value>>,
+
+    /// Format of log lines written to stderr
+    #[arg(env = "SESSIONDRIVER_LOG_FORMAT", long, value_enum, default_value_t = LogFormat::Text)]
+    pub log_format: LogFormat,
+
+    /// OTLP/HTTP endpoint traces are exported to
+    /// (e.g. http://127.0.0.1:4318/v1/traces, tracing is disabled unless set)
+    #[arg(env = "SESSIONDRIVER_OTLP_ENDPOINT", long)]
+    pub otlp_endpoint: Option<String>,
+
+    /// Bearer token granting access to the administrative API
+    /// (The API is disabled unless set)
+    #[arg(env = "SESSIONDRIVER_ADMIN_TOKEN", long)]
+    pub admin_token: Option<String>,
+
+    /// Token clients have to present as `Authorization: Bearer <token>` (or as Basic password)
+    /// (Repeatable, sessions can be created by anyone unless a token is set)
+    #[arg(env = "SESSIONDRIVER_AUTH_TOKEN", long, value_delimiter = ',')]
+    pub auth_token: Vec<String>,
+
+    /// File containing additional tokens, one per line
+    #[arg(env = "SESSIONDRIVER_AUTH_TOKEN_FILE", long)]
+    pub auth_token_file: Option<PathBuf>,
+
+    /// Maximum number of concurrent sessions per token
+    /// (Unlimited unless set)
+    #[arg(env = "SESSIONDRIVER_SESSIONS_PER_TOKEN", long)]
+    pub sessions_per_token: Option<usize>,
+
+    /// TOML file with a table per tenant (`tokens`, `max-sessions` and `tti`), each of which only sees its own
+    /// sessions
+    #[arg(env = "SESSIONDRIVER_TENANTS", long)]
+    pub tenants: Option<PathBuf>,
+
+    /// Header naming the tenant of requests not carrying one of its tokens
+    /// (Only to be set behind a proxy which sets the header itself)
+    #[arg(env = "SESSIONDRIVER_TENANT_HEADER", long, requires = "tenants")]
+    pub tenant_header: Option<HeaderName>,
+
+    /// Request header not passed on to WebDrivers or nodes, such as one only meant for a proxy in front
+    /// (Repeatable, Authorization is never passed on unless --forward-authorization is set)
+    #[arg(env = "SESSIONDRIVER_STRIP_HEADER", long, value_delimiter = ',')]
+    pub strip_header: Vec<HeaderName>,
+
+    /// Header added to requests passed on to WebDrivers or nodes, e.g. "Authorization: Basic dXNlcjpwYXNz"
+    /// (Repeatable, replaces a header of the same name sent by clients)
+    #[arg(env = "SESSIONDRIVER_INJECT_HEADER", long, value_parser = upstream::parse_header)]
+    pub inject_header: Vec<(reqwest::header::HeaderName, reqwest::header::HeaderValue)>,
+
+    /// Allow sessions requesting "sessiondriver:chaos" to have faults injected into their responses
+    /// (Only for testing clients' error handling, such sessions are rejected unless set)
+    #[arg(env = "SESSIONDRIVER_ALLOW_CHAOS", long)]
+    pub allow_chaos: bool,
+
+    /// Server header of responses, e.g. "grid" (WebDrivers' own are never passed on)
+    #[arg(env = "SESSIONDRIVER_SERVER_HEADER", long)]
+    pub server_header: Option<HeaderValue>,
+
+    /// Pseudonym this instance adds itself to responses' Via header as, e.g. "sessiondriver"
+    #[arg(env = "SESSIONDRIVER_VIA", long)]
+    pub via: Option<String>,
+
+    /// Pass clients' Authorization header on to WebDrivers or nodes
+    #[arg(env = "SESSIONDRIVER_FORWARD_AUTHORIZATION", long)]
+    pub forward_authorization: bool,
+
+    /// Network (e.g. 10.0.0.0/8) or address sessions may be created and controlled from, which also applies to the
+    /// administrative API, but not to /healthz, /readyz and /metrics (Repeatable, all sources are allowed unless set)
+    #[arg(env = "SESSIONDRIVER_ALLOW_CIDR", long, value_delimiter = ',', value_parser = allowlist::parse_network)]
+    pub allow_cidr: Vec<IpNet>,
+
+    /// Network or address of a proxy whose Forwarded or X-Forwarded-For headers identify clients
+    /// (Repeatable, applies to logs, rate limits and --allow-cidr, the headers are ignored unless set)
+    #[arg(env = "SESSIONDRIVER_TRUSTED_PROXIES", long, value_delimiter = ',', value_parser = allowlist::parse_network)]
+    pub trusted_proxies: Vec<IpNet>,
+
+    /// Requests served at the same time, beyond which further ones are answered with 503 rather than queued
+    /// (Probes at /healthz, /readyz and /metrics are exempt)
+    #[arg(env = "SESSIONDRIVER_MAX_CONCURRENT_REQUESTS", long)]
+    pub max_concurrent_requests: Option<usize>,
+
+    /// New-session requests served at the same time, beyond which further ones are answered with 503
+    #[arg(env = "SESSIONDRIVER_MAX_CONCURRENT_NEW_SESSIONS", long)]
+    pub max_concurrent_new_sessions: Option<usize>,
+
+    /// Origin (e.g. https://dashboard.example.com) whose pages may make requests, or * for any
+    /// (Repeatable, cross-origin requests are not allowed unless set)
+    #[arg(env = "SESSIONDRIVER_CORS_ORIGIN", long, value_delimiter = ',')]
+    pub cors_origin: Vec<HeaderValue>,
+
+    /// Methods allowed in cross-origin requests
+    #[arg(
+        env = "SESSIONDRIVER_CORS_METHODS",
+        long,
+        value_delimiter = ',',
+        default_value = "GET,POST,DELETE"
+    )]
+    pub cors_methods: Vec<Method>,
+
+    /// Request headers allowed in cross-origin requests
+    #[arg(
+        env = "SESSIONDRIVER_CORS_HEADERS",
+        long,
+        value_delimiter = ',',
+        default_value = "authorization,content-type"
+    )]
+    pub cors_headers: Vec<HeaderName>,
+
+    /// Time browsers may cache the answer to a preflight request
+    #[arg(env = "SESSIONDRIVER_CORS_MAX_AGE", long, value_parser = parse_duration, default_value_t = WrappedDuration(Duration::from_secs(3600)))]
+    pub cors_max_age: WrappedDuration,
+
+    /// Maximum number of sessions created per minute
+    /// (Unlimited unless set)
+    #[arg(env = "SESSIONDRIVER_SESSION_RATE_LIMIT", long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub session_rate_limit: Option<u32>,
+
+    /// Maximum number of sessions created per minute and client address
+    /// (Unlimited unless set)
+    #[arg(env = "SESSIONDRIVER_SESSION_RATE_LIMIT_PER_CLIENT", long, value_parser = clap::value_parser!(u32).range(1..))]
+    pub session_rate_limit_per_client: Option<u32>,
+
+    /// Number of sessions which may be created at once despite rate limits
+    #[arg(env = "SESSIONDRIVER_SESSION_BURST", long, default_value_t = 5)]
+    pub session_burst: u32,
+
+    /// PEM encoded certificate chain the proxy serves HTTPS with
+    /// (Requires --tls-key, plain HTTP is served unless set)
+    #[arg(env = "SESSIONDRIVER_TLS_CERT", long, requires = "tls_key")]
+    pub tls_cert: Option<PathBuf>,
+
+    /// PEM encoded private key belonging to --tls-cert
+    #[arg(env = "SESSIONDRIVER_TLS_KEY", long, requires = "tls_cert")]
+    pub tls_key: Option<PathBuf>,
+
+    /// Compresses larger responses (e.g. screenshots and page sources) with gzip for clients accepting it
+    #[arg(env = "SESSIONDRIVER_COMPRESS", long)]
+    pub compress: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -56,8 +675,7 @@ pub struct WrappedDuration(Duration);
 
 impl std::fmt::Display for WrappedDuration {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        f.write_str(&self.0.as_secs().to_string())?;
-        f.write_str("s")
+        write!(f, "{}", humantime::format_duration(self.0))
     }
 }
 
@@ -69,298 +687,1644 @@ fn parse_duration(s: &str) -> Result<WrappedDuration, String> {
 
 pub struct Browser {
     pub address: SocketAddr,
-    pub process: Mutex<Child>,
-    pub cleanup: Mutex<JoinHandle<()>>,
+    /// Base URL requests of the session are proxied to
+    pub upstream: String,
+    /// WebDriver process, unless the session is hosted remotely
+    pub process: Option<Mutex<Driver>>,
+    pub created: SystemTime,
+    pub permit: Permit,
+    pub quota: Option<Permit>,
+    /// Slots of the `--capability-limit`s the session counts towards
+    pub limited: Vec<Permit>,
+    pub sandbox: Option<Sandbox>,
+    /// Reserved for the WebDriver if it was spawned on a port of `--port-range`
+    pub port: Option<Lease>,
+    pub output: Arc<DriverOutput>,
+    pub audit: AuditLog,
+    pub dump: Option<DebugDump>,
+    pub recording: Option<Recording>,
+    pub downloads: Option<Downloads>,
+    pub uploads: Option<Uploads>,
+    pub capture: Option<Capture>,
+    pub capabilities: serde_json::Value,
+    /// Body of the request the session was created with if it is to be recovered, or `null`
+    pub requested: serde_json::Value,
+    /// ID of the session at its WebDriver, which differs if it was not a unique UUID or once the session has been
+    /// recovered
+    pub driver_session: String,
+    /// Number of times the session's WebDriver has been replaced
+    pub recoveries: u32,
+    pub tenant: Option<Arc<Tenant>>,
+    /// Connections to this session's WebDriver, see [`WebDriverMeta::client`]
+    pub http: Client,
+    /// Number of requests proxied to the WebDriver
+    pub requests: AtomicU64,
+    /// Requests being proxied to the WebDriver, see [`InFlight`]
+    pub in_flight: AtomicUsize,
+    /// Since when the session has been idle, counting towards its TTI
+    pub last_used: Mutex<Instant>,
+    pub usage: Usage,
+    pub latencies: Latencies,
+    /// Faults injected into responses, see `--allow-chaos`
+    pub chaos: Option<Chaos>,
+    /// Requested as `sessiondriver:labels` for the session to be found by
+    pub labels: Labels,
+    /// Where the session's interactions are recorded to, see `--cassette-dir`
+    pub cassette: Option<Cassette>,
+}
+
+impl Browser {
+    /// URL of the session at its WebDriver
+    pub fn session_url(&self) -> String {
+        format!("{}/session/{}", self.upstream, self.driver_session)
+    }
+
+    /// Waits for the requests being proxied to a session to let go of it, as it is shared with them (see [`proxy`])
+    ///
+    /// A session's shard has to be locked for writing while waiting, so that no request gets a hold of it meanwhile.
+    pub async fn exclusive(browser: &mut Arc<Self>) -> &mut Self {
+        while Arc::strong_count(browser) > 1 {
+            sleep(SHARED_INTERVAL).await;
+        }
+        Arc::get_mut(browser).expect("Session shared while its shard is locked")
+    }
+
+    /// Waits for the requests being proxied to a session removed from [`Browsers`] to let go of it
+    async fn unshared(mut browser: Arc<Self>) -> Self {
+        loop {
+            match Arc::try_unwrap(browser) {
+                Ok(browser) => return browser,
+                Err(shared) => browser = shared,
+            }
+            sleep(SHARED_INTERVAL).await;
+        }
+    }
+
+    /// Lets go of a session whose WebDriver keeps running to be adopted after a restart
+    pub async fn detach(self: Arc<Self>) {
+        let browser = Self::unshared(self).await;
+        if let Some(recording) = browser.recording {
+            recording.finish().await;
+        }
+    }
+
+    /// Ends a session its client did not delete, which remote endpoints have to be told about
+    pub async fn end(self: Arc<Self>, uuid: Uuid, grace: Duration) {
+        if self.process.is_none() {
+            let deleted = self
+                .http
+                .delete(self.session_url())
+                .timeout(Duration::from_secs(10))
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = deleted {
+                warn!("Unable to delete {:?} at {}: {}", uuid, self.address, e);
+            }
+        }
+        self.close(grace).await;
+    }
+
+    /// Releases what a session holds on to once it has been removed from [`Browsers`], stopping its WebDriver within `grace`
+    pub async fn close(self: Arc<Self>, grace: Duration) {
+        let browser = Self::unshared(self).await;
+        if let Some(process) = browser.process {
+            stop::stop_driver(&mut process.into_inner(), grace).await;
+        }
+        if let Some(recording) = browser.recording {
+            recording.finish().await;
+        }
+        if let Some(downloads) = browser.downloads {
+            downloads.remove().await;
+        }
+        if let Some(uploads) = browser.uploads {
+            uploads.remove().await;
+        }
+        if let Some(capture) = browser.capture {
+            capture.finish().await;
+        }
+        if let Some(sandbox) = browser.sandbox {
+            sandbox.remove(grace).await;
+        }
+    }
+}
+
+/// How WebDrivers are started
+pub enum Backend {
+    /// Replaceable through the administrative API, running WebDrivers are kept until their session ends
+    Process(RwLock<Box<Path>>),
+    Docker(Docker),
+    Kubernetes(Kubernetes),
+    Remote(Remote),
+}
+
+/// Where a WebDriver runs unless it is a child process
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub enum Sandbox {
+    Container(Container),
+    Pod(Pod),
+    /// ID of a process adopted after a restart
+    Process(u32),
+}
+
+impl Sandbox {
+    /// Removes the sandbox, whereas `grace` is what an adopted WebDriver is given to exit before it is killed
+    pub async fn remove(self, grace: Duration) {
+        match self {
+            Sandbox::Container(container) => container.remove().await,
+            Sandbox::Pod(pod) => pod.remove().await,
+            Sandbox::Process(pid) => persist::terminate(pid, grace).await,
+        }
+    }
 }
 
 pub struct WebDriverMeta {
-    pub path: Box<Path>,
-    pub parameters: Option<String>,
-    pub next_port: Mutex<u16>,
-    pub tti: Duration,
+    pub backend: Backend,
+    pub parameters: RwLock<Option<String>>,
+    pub ports: Arc<Ports>,
+    pub tti: RwLock<Duration>,
     pub host: IpAddr,
     pub protocol: String,
+    pub affinity: Option<Affinity>,
+    pub log_lines: usize,
+    pub audit_dir: Option<Box<Path>>,
+    pub debug_dump_dir: Option<PathBuf>,
+    pub debug_dump_body_limit: usize,
+    pub crashes: Option<CrashArchive>,
+    pub recorder: Option<Recorder>,
+    pub screenshots: Option<ScreenshotArchive>,
+    pub webhook: Option<Webhook>,
+    /// Time before expiry at which sessions are warned about, see `--expiry-warning`
+    pub expiry_warning: Option<Duration>,
+    /// Whether sessions still busy are kept from expiring, see `--activity-check`
+    pub activity_check: bool,
+    /// Whether WebDrivers have to outlive this process, see `--state-file`
+    pub detach: bool,
+    /// Whether crashed WebDrivers are replaced, see `--recover`
+    pub recover: bool,
+    pub appium: bool,
+    /// Added to WebDrivers recognised as a [`safety::Flavor`], see `--no-safety-flags`
+    pub safety: Option<SafetyFlags>,
+    pub policy: Policy,
+    pub session_downloads: Option<PathBuf>,
+    pub har: Option<HarArchive>,
+    pub upload_dir: Option<PathBuf>,
+    pub limits: Vec<Limit>,
+    pub idle_timeout: Duration,
+    pub connections: usize,
+    pub spawns: SpawnQueue,
+    pub probe: Probe,
+    /// Whether WebDrivers are run with --version before being used
+    pub check_version: bool,
+    /// What the WebDriver executable reported on `--version`
+    pub driver_version: RwLock<Option<String>>,
+    pub stop_grace: Duration,
+    pub headers: UpstreamHeaders,
+    /// Whether sessions may request faults to be injected, see `--allow-chaos`
+    pub allow_chaos: bool,
+    /// Where sessions requesting a cassette are recorded to, see `--cassette-dir`
+    pub cassette_dir: Option<PathBuf>,
+}
+
+impl WebDriverMeta {
+    /// Client of a single WebDriver, so that its connections are kept alive apart from those of others
+    pub fn client(&self) -> reqwest::Result<Client> {
+        Client::builder()
+            .pool_idle_timeout(self.idle_timeout)
+            .pool_max_idle_per_host(self.connections)
+            .tcp_keepalive(self.idle_timeout)
+            .build()
+    }
 }
 
-type Browsers = Arc<RwLock<HashMap<Uuid, Browser>>>;
+/// Sessions, which are shared with the requests being proxied to them so that their shard is not locked meanwhile
+type Browsers = Arc<Shards<Arc<Browser>>>;
 
 #[derive(Clone, FromRef)]
 pub struct AppState {
     pub browsers: Browsers,
     pub http: Client,
     pub webdriver: Arc<WebDriverMeta>,
+    pub metrics: Arc<Metrics>,
+    pub capacity: Arc<Capacity>,
+    pub rate_limit: Arc<RateLimit>,
+    pub tokens: Arc<Tokens>,
+    pub expiry: Expiry,
 }
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
-    let args = Args::parse();
+    let args: Args = config::parse()?;
+    logging::init(args.log_format);
+    #[cfg(windows)]
+    if args.state_file.is_none() {
+        if let Err(e) = job::kill_children_on_exit() {
+            warn!("Unable to tie WebDrivers to this process: {}", e);
+        }
+    }
+    if let Some(path) = &args.config {
+        info!("Using configuration {:?}", path);
+    }
+    let tracer_provider = match &args.otlp_endpoint {
+        Some(endpoint) => Some(telemetry::init(endpoint)?),
+        None => None,
+    };
 
-    let parameters = match args.parameters {
-        Some(mut p) => {
-            if (p.starts_with("\\\"") && p.ends_with("\\\""))
-                || (p.starts_with("\\'") && p.ends_with("\\'"))
-            {
-                let tmp = &p[2..];
-                p = String::from(&tmp[..2]);
-            } else if (p.starts_with('"') && p.ends_with('"'))
-                || (p.starts_with("'") && p.ends_with("'"))
+    let tokens = Arc::new(Tokens::new(
+        accepted_tokens(&args)?,
+        args.sessions_per_token,
+    ));
+    if let Some(path) = &args.tenants {
+        tokens.replace_tenants(tenant::read(path)?, args.tenant_header.clone());
+    }
+    let headers = upstream_headers(&args);
+    let (app, capacity, browsers) = if let (true, Some(node_token)) =
+        (args.hub, args.hub_token.clone())
+    {
+        let hub = Arc::new(Hub::new(
+            Client::new(),
+            Arc::new(Metrics::new()?),
+            args.protocol,
+            Arc::new(Capacity::new(args.max_sessions)),
+            headers,
+            RateLimit::new(
+                args.session_rate_limit,
+                args.session_rate_limit_per_client,
+                args.session_burst,
+            ),
+        ));
+        let capacity = hub.capacity.clone();
+        info!("Routing sessions to registered nodes");
+
+        let sessions = hub::router()
+            .layer(middleware::from_fn_with_state(tokens, auth::authenticate))
+            .merge(hub::registration(node_token))
+            .with_state(hub);
+        let app = allowlist::restrict(sessions, args.allow_cidr).merge(health::router(Health {
+            capacity: capacity.clone(),
+            browsers: Browsers::default(),
+            webdriver: None,
+        }));
+        (app, capacity, Browsers::default())
+    } else if let Some(directory) = args.replay_dir {
+        let capacity = Arc::new(Capacity::new(args.max_sessions));
+        info!("Replaying sessions from {:?}", directory);
+
+        let sessions = replay::router()
+            .layer(middleware::from_fn_with_state(tokens, auth::authenticate))
+            .with_state(Arc::new(Replay::new(directory)));
+        let app = allowlist::restrict(sessions, args.allow_cidr).merge(health::router(Health {
+            capacity: capacity.clone(),
+            browsers: Browsers::default(),
+            webdriver: None,
+        }));
+        (app, capacity, Browsers::default())
+    } else {
+        let backend = if !args.upstream.is_empty() {
+            Backend::Remote(Remote::new(args.upstream))
+        } else {
+            match (
+                args.kubernetes_pod_template,
+                args.docker_image,
+                args.webdriver,
+            ) {
+                (Some(template), _, _) => Backend::Kubernetes(
+                    Kubernetes::load(
+                        args.kubectl,
+                        args.kubernetes_namespace,
+                        &template,
+                        args.kubernetes_port,
+                    )
+                    .await?,
+                ),
+                (None, Some(image), _) => Backend::Docker(Docker {
+                    binary: args.docker,
+                    image,
+                    network: args.docker_network,
+                    shm_size: args.docker_shm_size,
+                    port: args.docker_port,
+                }),
+                (None, None, Some(path)) => Backend::Process(RwLock::new(path)),
+                (None, None, None) => {
+                    unreachable!("--webdriver is required unless another backend is set")
+                }
+            }
+        };
+        let driver_version = match &backend {
+            Backend::Process(path) => {
+                check_driver(&path.read().await, !args.skip_version_check).await?
+            }
+            Backend::Docker(docker) => {
+                docker.prepare().await?;
+                None
+            }
+            Backend::Kubernetes(_) | Backend::Remote(_) => None,
+        };
+
+        let (expiry, changes) = Expiry::new();
+        let state = AppState {
+            browsers: Browsers::default(),
+            http: Client::new(),
+            webdriver: Arc::new(WebDriverMeta {
+                backend,
+                parameters: RwLock::new(unquote(args.parameters)),
+                tti: RwLock::new(args.tti.0),
+                ports: Arc::new(Ports::new(args.port_range)),
+                host: args.driver_host,
+                protocol: args.protocol,
+                log_lines: args.driver_log_lines,
+                audit_dir: args.audit_dir,
+                debug_dump_dir: args.debug_dump_dir,
+                debug_dump_body_limit: args.debug_dump_body_limit,
+                crashes: args.crash_dir.map(|directory| {
+                    CrashArchive::new(directory, args.crash_output_limit, args.crash_core_dumps)
+                }),
+                recorder: args.record_dir.map(|directory| Recorder {
+                    directory,
+                    ffmpeg: args.ffmpeg,
+                    display: args.record_display,
+                }),
+                screenshots: args.screenshot_dir.map(|directory| ScreenshotArchive {
+                    directory,
+                    retention: args.screenshot_retention.0,
+                }),
+                webhook: args.webhook_url.map(|url| Webhook { url }),
+                expiry_warning: args.expiry_warning.map(|warning| warning.0),
+                activity_check: args.activity_check,
+                headers,
+                allow_chaos: args.allow_chaos,
+                cassette_dir: args.cassette_dir,
+                detach: args.state_file.is_some(),
+                recover: args.recover,
+                appium: args.appium,
+                safety: (!args.no_safety_flags && !args.appium).then(|| SafetyFlags {
+                    origins: args
+                        .cors_origin
+                        .iter()
+                        .filter_map(|origin| origin.to_str().ok())
+                        .map(String::from)
+                        .collect(),
+                }),
+                policy: Policy {
+                    headless: args.force_headless,
+                    allow_no_sandbox: args.allow_no_sandbox,
+                    download_dir: args.download_dir,
+                    denied: args.deny_capability,
+                    appium: args.appium,
+                },
+                session_downloads: args.session_downloads_dir,
+                har: args.har_dir.map(|directory| HarArchive { directory }),
+                affinity: args.node.map(|node| Affinity {
+                    header: args.node_header,
+                    node,
+                }),
+                upload_dir: args.upload_dir.clone(),
+                limits: args.capability_limit,
+                idle_timeout: args.driver_idle_timeout.0,
+                connections: args.driver_connections,
+                spawns: SpawnQueue::new(args.max_spawns, args.spawn_stagger.0),
+                probe: Probe {
+                    path: (!args.probe_tcp).then_some(args.probe_path),
+                    interval: args.probe_interval.0,
+                    attempts: args.probe_attempts,
+                },
+                check_version: !args.skip_version_check,
+                driver_version: RwLock::new(driver_version),
+                stop_grace: args.driver_stop_grace.0,
+            }),
+            metrics: Arc::new(Metrics::new()?),
+            capacity: Arc::new(Capacity::new(args.max_sessions)),
+            rate_limit: Arc::new(RateLimit::new(
+                args.session_rate_limit,
+                args.session_rate_limit_per_client,
+                args.session_burst,
+            )),
+            tokens: tokens.clone(),
+            expiry,
+        };
+        tokio::spawn(expiry::run(state.clone(), changes));
+        tokio::spawn(reap(state.clone()));
+        let capacity = state.capacity.clone();
+        let browsers = state.browsers.clone();
+
+        if let Some(path) = &args.state_file {
+            if let Err(e) = persist::adopt(&state, path).await {
+                warn!("Unable to adopt sessions from {:?}: {}", path, e);
+            }
+            persist::keep(browsers.clone(), path.clone());
+        }
+        #[cfg(unix)]
+        tokio::spawn(reload_on_hangup(state.clone()));
+        if let (Some(url), Some(address)) = (args.hub_url, args.node_address) {
+            let capabilities = args.node_capabilities.unwrap_or_default();
+            hub::join(url, args.hub_token, address, capabilities, state.clone());
+        }
+        Watermarks {
+            high: args.high_watermark,
+            low: args.low_watermark,
+            command: args.watermark_command,
+            url: args.watermark_url,
+            max_sessions: args.max_sessions,
+        }
+        .watch(browsers.clone(), state.http.clone());
+        usage::sample(browsers.clone(), args.usage_interval.0);
+
+        let mut sessions = recording::router()
+            .merge(downloads::router())
+            .merge(har::router());
+        // Otherwise left to the WebDriver, as a Selenium server supports it itself
+        if args.upload_dir.is_some() {
+            sessions = sessions.merge(upload::router(args.upload_limit));
+        }
+        let mut sessions = sessions
+            .merge(tenant::router())
+            .merge(version::router())
+            .fallback(proxy)
+            .layer(middleware::from_fn_with_state(tokens, auth::authenticate));
+        if let Some(token) = args.admin_token {
+            sessions = sessions
+                .merge(admin::router(token))
+                .route("/ui", get(ui::dashboard));
+        }
+        let app = Router::default()
+            .route("/metrics", get(metrics::export))
+            .route("/openapi.json", get(ui::openapi))
+            .merge(health::router(Health {
+                capacity: capacity.clone(),
+                browsers: browsers.clone(),
+                webdriver: Some(state.webdriver.clone()),
+            }))
+            .merge(allowlist::restrict(sessions, args.allow_cidr))
+            .with_state(state);
+        (app, capacity, browsers)
+    };
+    // Selenium 3 and Appium clients tend to be pointed at /wd/hub
+    let mut app = Router::new()
+        .nest("/wd/hub", app.clone())
+        .fallback_service(app);
+    if args.compress {
+        let predicate = DefaultPredicate::new().and(SizeAbove::new(COMPRESSION_THRESHOLD));
+        app = app.layer(CompressionLayer::new().compress_when(predicate));
+    }
+    // Outside of authentication, as preflight requests carry no credentials
+    if !args.cors_origin.is_empty() {
+        app = app.layer(cors::layer(
+            args.cors_origin,
+            args.cors_methods,
+            args.cors_headers,
+            args.cors_max_age.0,
+        ));
+    }
+    let limits = Limits::new(
+        args.max_concurrent_requests,
+        args.max_concurrent_new_sessions,
+    );
+    if !limits.is_empty() {
+        app = app.layer(middleware::from_fn_with_state(
+            Arc::new(limits),
+            overload::shed,
+        ));
+    }
+    let branding = Branding {
+        server: args.server_header,
+        via: args.via,
+    };
+    if !branding.is_empty() {
+        app = app.layer(middleware::from_fn_with_state(
+            Arc::new(branding),
+            branding::brand,
+        ));
+    }
+    let trusted = Arc::new(Allowlist::new(args.trusted_proxies));
+    let app = app
+        .layer(middleware::from_fn(logging::access))
+        .layer(middleware::from_fn_with_state(trusted, forwarded::identify));
+
+    let service = ServiceExt::<Request>::into_make_service_with_connect_info::<SocketAddr>(app);
+    if let (Some(cert), Some(key)) = (args.tls_cert, args.tls_key) {
+        let _ = rustls::crypto::ring::default_provider().install_default();
+        let config = RustlsConfig::from_pem_file(cert, key).await?;
+
+        let handle = Handle::new();
+        tokio::spawn({
+            let handle = handle.clone();
+            async move {
+                graceful_shutdown(capacity).await;
+                handle.graceful_shutdown(None);
+            }
+        });
+        tokio::spawn({
+            let handle = handle.clone();
+            async move {
+                if handle.listening().await.is_some() {
+                    systemd::ready();
+                }
+            }
+        });
+        systemd::watchdog(browsers.clone());
+
+        let address = SocketAddr::new(args.host, args.port);
+        info!("Listening on {} (TLS)", address);
+        axum_server::from_tcp_rustls(listen::bind(address)?, config)?
+            .handle(handle)
+            .serve(service)
+            .await?;
+    } else {
+        let address = SocketAddr::new(args.host, args.port);
+        let listener = TcpListener::from_std(listen::bind(address)?)?;
+        info!("Listening on {}", address);
+        systemd::ready();
+        systemd::watchdog(browsers.clone());
+
+        axum::serve(listener, service)
+            .with_graceful_shutdown(graceful_shutdown(capacity))
+            .await?;
+    }
+
+    if let Some(path) = &args.state_file {
+        if let Err(e) = persist::save(&browsers, path).await {
+            warn!("Unable to persist sessions to {:?}: {}", path, e);
+        }
+        let remaining = browsers.drain().await;
+        info!("Leaving {} session(s) to be adopted", remaining.len());
+        for (_, browser) in remaining {
+            browser.detach().await;
+        }
+    } else {
+        let remaining = browsers.drain().await;
+        info!(
+            "Ending {} session(s) within {:?}",
+            remaining.len(),
+            args.shutdown_grace.0
+        );
+        let mut ending = JoinSet::new();
+        for (uuid, browser) in remaining {
+            ending.spawn(browser.end(uuid, args.shutdown_grace.0));
+        }
+        ending.join_all().await;
+    }
+
+    if let Some(provider) = tracer_provider {
+        tokio::task::spawn_blocking(move || provider.shutdown()).await??;
+    }
+
+    Ok(())
+}
+
+#[instrument(
+    name = "proxy",
+    level = "debug",
+    skip_all,
+    fields(method = %request.method(), path = request.uri().path())
+)]
+pub async fn proxy(
+    State(browsers): State<Browsers>,
+    State(http): State<Client>,
+    State(webdriver_meta): State<Arc<WebDriverMeta>>,
+    State(metrics): State<Arc<Metrics>>,
+    State(capacity): State<Arc<Capacity>>,
+    State(state): State<AppState>,
+    mut request: Request,
+) -> Result<Response, Response> {
+    telemetry::adopt(&Span::current(), request.headers());
+    let elsewhere = match &webdriver_meta.affinity {
+        Some(affinity) => affinity.take(request.headers_mut()),
+        None => None,
+    };
+    let tenant = request.extensions().get::<Arc<Tenant>>().cloned();
+    let path = request.uri().path().trim_end_matches('/').to_owned();
+
+    if (request.method() == Method::GET || request.method() == Method::HEAD) && path == "/status" {
+        let response = Response::builder()
+            .status(200)
+            .header("Content-Type", "application/json");
+        let (ready, message) = capacity.status(browsers.len().await);
+        let body = Body::from(
+            serde_json::json!({ "value": { "ready": ready, "message": message } }).to_string(),
+        );
+        return Ok(response.body(body).map_err(internal_server_error)?);
+    }
+
+    if request.method() == Method::POST && path == "/session" {
+        let client = request
+            .extensions()
+            .get::<ClientAddress>()
+            .map(|ClientAddress(address)| *address);
+        if let Err(wait) = state.rate_limit.check(client) {
+            info!("Rejected session (Rate limited)");
+            let retry_after = wait.as_secs_f64().ceil() as u64;
+            return Err((
+                [(header::RETRY_AFTER, retry_after.to_string())],
+                w3c::error(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    w3c::SESSION_NOT_CREATED,
+                    "Sessions are created too quickly",
+                ),
+            )
+                .into_response());
+        }
+
+        let downloads = webdriver_meta
+            .session_downloads
+            .as_deref()
+            .map(Downloads::new);
+        let download_dir = downloads.as_ref().map(|d| d.directory.as_path());
+        let mut capture = match &webdriver_meta.har {
+            Some(archive) => Some(
+                archive
+                    .start(webdriver_meta.host)
+                    .await
+                    .map_err(internal_server_error)?,
+            ),
+            None => None,
+        };
+        let proxy = capture.as_ref().map(|c| c.address);
+        let (mut request, mut requested) = webdriver_meta
+            .policy
+            .enforce(request, download_dir, proxy)
+            .await?;
+        let labels = labels::take(&mut requested).map_err(|message| {
+            info!("Rejected session ({})", message);
+            w3c::error(StatusCode::BAD_REQUEST, w3c::INVALID_ARGUMENT, message)
+        })?;
+        if labels.is_some() {
+            *request.body_mut() = Body::from(requested.to_string());
+        }
+        let chaos = match Chaos::requested(&requested) {
+            Ok(Some(_)) if !webdriver_meta.allow_chaos => {
+                let message = format!("{} is not allowed (see --allow-chaos)", chaos::CAPABILITY);
+                info!("Rejected session ({})", message);
+                return Err(w3c::error(
+                    StatusCode::BAD_REQUEST,
+                    w3c::INVALID_ARGUMENT,
+                    message,
+                ));
+            }
+            Ok(chaos) => chaos,
+            Err(message) => {
+                info!("Rejected session ({})", message);
+                return Err(w3c::error(
+                    StatusCode::BAD_REQUEST,
+                    w3c::INVALID_ARGUMENT,
+                    message,
+                ));
+            }
+        };
+        // Left to the WebDriver unless sessions are recorded
+        let cassette_name = match &webdriver_meta.cassette_dir {
+            Some(_) => cassette::requested(&requested).map_err(|message| {
+                info!("Rejected session ({})", message);
+                w3c::error(StatusCode::BAD_REQUEST, w3c::INVALID_ARGUMENT, message)
+            })?,
+            None => None,
+        };
+
+        let permit = match capacity.reserve() {
+            Reservation::Granted(permit) => permit,
+            Reservation::Exhausted => {
+                info!("Rejected session (At capacity)");
+                return Err(w3c::error(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    w3c::SESSION_NOT_CREATED,
+                    "Maximum number of sessions reached",
+                ));
+            }
+            Reservation::Draining => {
+                info!("Rejected session (Draining)");
+                return Err(w3c::error(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    w3c::SESSION_NOT_CREATED,
+                    "Shutting down",
+                ));
+            }
+        };
+        let quota = match request.extensions().get::<Quota>().map(Quota::reserve) {
+            Some(Some(quota)) => Some(quota),
+            Some(None) => {
+                info!("Rejected session (Token at quota)");
+                return Err(w3c::error(
+                    StatusCode::TOO_MANY_REQUESTS,
+                    w3c::SESSION_NOT_CREATED,
+                    "Maximum number of sessions per token reached",
+                ));
+            }
+            None => None,
+        };
+        let limited = match capacity::reserve_limits(&webdriver_meta.limits, &requested) {
+            Ok(limited) => limited,
+            Err(limit) => {
+                info!("Rejected session ({} at capacity)", limit);
+                return Err(w3c::error(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    w3c::SESSION_NOT_CREATED,
+                    format!("Maximum number of {} sessions reached", limit),
+                ));
+            }
+        };
+
+        let driver_http = webdriver_meta.client().map_err(internal_server_error)?;
+        let SpawnedDriver {
+            driver,
+            address: socket_address,
+            upstream,
+            output,
+        } = spawn_driver(&driver_http, &webdriver_meta, &metrics).await?;
+        let audit = AuditLog::new(webdriver_meta.audit_dir.as_deref());
+        let dump = webdriver_meta
+            .debug_dump_dir
+            .as_deref()
+            .filter(|_| DebugDump::requested(&requested))
+            .map(|directory| DebugDump::new(directory, webdriver_meta.debug_dump_body_limit));
+
+        webdriver_meta.headers.apply(request.headers_mut());
+        let driver_response = match proxy_request(
+            driver_http.clone(),
+            &metrics,
+            Some(&audit),
+            dump.as_ref(),
+            &upstream,
+            request,
+            false,
+        )
+        .await
+        {
+            Ok(driver_response) => driver_response,
+            Err(response) => {
+                driver.discard(webdriver_meta.stop_grace).await;
+                return Err(response);
+            }
+        };
+        debug!("Proxied request");
+
+        // The body is read and written anew
+        let mut response = copy_headers(Response::builder(), driver_response.headers(), true);
+        if let Some(affinity) = &webdriver_meta.affinity {
+            response = affinity.tag(response);
+        }
+
+        let status = driver_response.status();
+        response = response.status(status.as_u16());
+
+        let body = driver_response
+            .bytes()
+            .await
+            .map_err(internal_server_error)?;
+        debug!("Collected body {:?}", String::from_utf8_lossy(&body));
+        // Errors are the client's to handle
+        if !status.is_success() {
+            info!("Rejected session (WebDriver answered {})", status);
+            driver.discard(webdriver_meta.stop_grace).await;
+            return response
+                .body(Body::from(body))
+                .map_err(internal_server_error);
+        }
+        let mut body: serde_json::Value =
+            serde_json::from_slice(&body).map_err(internal_server_error)?;
+        debug!("Deserialised body");
+        let (mut session_id, driver_session, capabilities) = new_session(&mut body);
+        // e.g. a remote endpoint handing out IDs of its own
+        if browsers.contains(&session_id).await {
+            let unique = Uuid::new_v4();
+            warn!(
+                "WebDriver created session {:?} which exists already, proxying it as {:?}",
+                session_id, unique
+            );
+            session_id = unique;
+            set_session_id(&mut body, session_id);
+        }
+        debug!("Extracted session {:?}", session_id);
+        output.assign(session_id);
+        audit.attach(session_id).await;
+        if let Some(dump) = &dump {
+            dump.attach(session_id).await;
+        }
+        let recording = webdriver_meta.recorder.as_ref().and_then(|recorder| {
+            match recorder.start(session_id, tenant.as_deref()) {
+                Ok(recording) => Some(recording),
+                Err(e) => {
+                    warn!("Unable to record {:?}: {}", session_id, e);
+                    None
+                }
+            }
+        });
+        if let (Some(capture), Some(archive)) = (&mut capture, &webdriver_meta.har) {
+            capture.assign(archive, session_id, tenant.as_deref());
+        }
+        let cassette = match (&webdriver_meta.cassette_dir, cassette_name) {
+            (Some(directory), Some(name)) => match Cassette::create(directory, &name).await {
+                Ok(cassette) => {
+                    info!("Recording {:?} to cassette {:?}", session_id, name);
+                    cassette
+                        .record(&Interaction {
+                            method: Method::POST.to_string(),
+                            path: String::from("/session"),
+                            body: requested.clone(),
+                            status: status.as_u16(),
+                            response: body.clone(),
+                            fault: None,
+                        })
+                        .await;
+                    Some(cassette)
+                }
+                Err(e) => {
+                    warn!(
+                        "Unable to record {:?} to cassette {:?}: {}",
+                        session_id, name, e
+                    );
+                    None
+                }
+            },
+            _ => None,
+        };
+        // Created only now, so rejected sessions leave nothing behind
+        if let Some(downloads) = &downloads
+            && let Err(e) = downloads.create().await
+        {
+            warn!(
+                "Unable to create downloads {:?}: {}",
+                downloads.directory, e
+            );
+        }
+        response = response.extension(Upstream {
+            session: session_id,
+            address: socket_address,
+        });
+        let (child, sandbox, port) = driver.claim();
+        let tti = tenant.as_ref().and_then(|t| t.tti);
+        let inserted = browsers
+            .try_insert(
+                session_id,
+                Arc::new(Browser {
+                    address: socket_address,
+                    upstream,
+                    process: child.map(Mutex::new),
+                    created: SystemTime::now(),
+                    permit,
+                    quota,
+                    limited,
+                    sandbox,
+                    port,
+                    output,
+                    audit,
+                    dump,
+                    recording,
+                    downloads,
+                    // Written on this host, which only runs the browser if the WebDriver is a local process
+                    uploads: match webdriver_meta.backend {
+                        Backend::Process(_) => {
+                            webdriver_meta.upload_dir.as_deref().map(Uploads::new)
+                        }
+                        _ => None,
+                    },
+                    capture,
+                    capabilities: capabilities.clone(),
+                    requested: match webdriver_meta.recover {
+                        true => requested,
+                        false => serde_json::Value::Null,
+                    },
+                    driver_session,
+                    recoveries: 0,
+                    tenant,
+                    http: driver_http,
+                    requests: AtomicU64::new(0),
+                    in_flight: AtomicUsize::new(0),
+                    last_used: Mutex::new(Instant::now()),
+                    usage: Usage::default(),
+                    latencies: Latencies::default(),
+                    chaos,
+                    cassette,
+                    labels: labels.unwrap_or_default(),
+                }),
+            )
+            .await;
+        // Created by another request since it was checked for
+        if let Err(browser) = inserted {
+            warn!("Rejected session (ID {:?} taken meanwhile)", session_id);
+            browser.end(session_id, webdriver_meta.stop_grace).await;
+            return Err(w3c::error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                w3c::SESSION_NOT_CREATED,
+                format!("Session {} exists already", session_id),
+            ));
+        }
+        metrics.sessions_created.inc();
+        if let Some(webhook) = &webdriver_meta.webhook {
+            webhook.notify(&http, EventKind::Created, session_id, &capabilities);
+        }
+        state.expiry.touch(session_id, tti);
+
+        let body = Body::from(serde_json::to_string(&body).expect("String to JSON from JSON"));
+        return Ok(response.body(body).map_err(internal_server_error)?);
+    }
+
+    let Some(mut uuid) = path.strip_prefix("/session/") else {
+        return Err(unknown_command(request.method(), &path));
+    };
+    if let Some(i) = uuid.find('/') {
+        uuid = &uuid[..i];
+    }
+    let Ok(uuid) = uuid.parse::<Uuid>() else {
+        return Err(w3c::error(
+            StatusCode::NOT_FOUND,
+            w3c::INVALID_SESSION_ID,
+            format!("No active session with ID {}", uuid),
+        ));
+    };
+
+    if request.method() == Method::DELETE && path == format!("/session/{}", uuid) {
+        let removed = {
+            let mut shard = browsers.shard(&uuid).write().await;
+            match shard.get(&uuid) {
+                Some(browser) if tenant::owns(tenant.as_deref(), browser) => shard.remove(&uuid),
+                _ => None,
+            }
+        };
+        if let Some(browser) = removed {
+            info!("Removed {:?}", uuid);
+            metrics.sessions_deleted.inc();
+            state.expiry.cancel(uuid);
+            if let Some(webhook) = &webdriver_meta.webhook {
+                webhook.notify(&http, EventKind::Deleted, uuid, &browser.capabilities);
+            }
+            if let Some(screenshots) = &webdriver_meta.screenshots {
+                screenshots
+                    .capture(&browser.http, &browser.session_url(), uuid)
+                    .await;
+            }
+            if browser.driver_session != uuid.to_string() {
+                recovery::redirect(&mut request, uuid, &browser.driver_session);
+            }
+            webdriver_meta.headers.apply(request.headers_mut());
+            let deleted = async {
+                let driver_response = proxy_request(
+                    browser.http.clone(),
+                    &metrics,
+                    Some(&browser.audit),
+                    browser.dump.as_ref(),
+                    &browser.upstream,
+                    request,
+                    false,
+                )
+                .await?;
+
+                let response = Response::builder().extension(Upstream {
+                    session: uuid,
+                    address: browser.address,
+                });
+                let mut response = copy_headers(response, driver_response.headers(), false);
+                response = response.status(driver_response.status().as_u16());
+                if browser.recoveries > 0 {
+                    response = response.header(recovery::RECOVERED, browser.recoveries);
+                }
+                let status = driver_response.status().as_u16();
+                let body = driver_response
+                    .bytes()
+                    .await
+                    .map_err(internal_server_error)?;
+                if let Some(cassette) = &browser.cassette {
+                    cassette
+                        .record(&Interaction::new(&Method::DELETE, "", b"", status, &body))
+                        .await;
+                }
+                response
+                    .body(Body::from(body))
+                    .map_err(internal_server_error)
+            }
+            .await;
+            // Released even if the WebDriver could not be reached, as the session is gone in any case
+            browser.close(webdriver_meta.stop_grace).await;
+            return deleted;
+        }
+    }
+
+    let browser = browsers
+        .shard(&uuid)
+        .read()
+        .await
+        .get(&uuid)
+        .filter(|browser| tenant::owns(tenant.as_deref(), browser))
+        .cloned();
+    let browser = match browser {
+        Some(browser) => browser,
+        None => {
+            if let Some(crashes) = &webdriver_meta.crashes
+                && let Some(crash) = crashes.lookup(uuid).await
             {
-                let tmp = &p[1..];
-                p = String::from(&tmp[..1]);
+                debug!("{:?} not found (WebDriver crashed)", uuid);
+                let body = serde_json::json!({
+                    "value": {
+                        "error": w3c::INVALID_SESSION_ID,
+                        "message": format!("WebDriver exited with {}", crash.status),
+                        "stacktrace": "",
+                        "data": { "artifacts": crash.artifacts },
+                    }
+                });
+                return Err((StatusCode::GONE, Json(body)).into_response());
+            }
+            if let Some(node) = elsewhere {
+                debug!("{:?} not found (Belongs to {})", uuid, node);
+                return Err(w3c::error(
+                    StatusCode::MISDIRECTED_REQUEST,
+                    w3c::INVALID_SESSION_ID,
+                    format!("Session belongs to {}", node),
+                ));
+            }
+            debug!("{:?} not found", uuid);
+            return Err(unknown_session(uuid));
+        }
+    };
+
+    if request.method() == Method::GET
+        && path == format!("/session/{}/sessiondriver/driver-logs", uuid)
+    {
+        let body = serde_json::json!({ "value": browser.output.lines() });
+        return Ok(Json(body).into_response());
+    }
+
+    if request.method() == Method::GET && path == format!("/session/{}/sessiondriver/info", uuid) {
+        let body = serde_json::json!({ "value": session_info(&browser, &webdriver_meta).await });
+        return Ok(Json(body).into_response());
+    }
+
+    // Legacy log retrieval (Selenium's at /se/log), other types than "driver" are left to the WebDriver
+    if path == format!("/session/{}/log", uuid) || path == format!("/session/{}/se/log", uuid) {
+        let driver_log = match *request.method() {
+            Method::GET => true,
+            Method::POST => {
+                let body = mem::take(request.body_mut());
+                let bytes = to_bytes(body, LOG_REQUEST_LIMIT)
+                    .await
+                    .map_err(bad_request_error)?;
+                let requested = serde_json::from_slice::<serde_json::Value>(&bytes)
+                    .is_ok_and(|body| body["type"] == "driver");
+                *request.body_mut() = Body::from(bytes);
+                requested
             }
+            _ => false,
+        };
+        if driver_log {
+            let entries: Vec<LogEntry> = browser
+                .output
+                .lines()
+                .into_iter()
+                .map(LogEntry::from)
+                .collect();
+            return Ok(Json(serde_json::json!({ "value": entries })).into_response());
+        }
+    }
+
+    state
+        .expiry
+        .touch(uuid, browser.tenant.as_ref().and_then(|t| t.tti));
+    *browser.last_used.lock().await = Instant::now();
+    browser.requests.fetch_add(1, Ordering::Relaxed);
+
+    let status_request =
+        request.method() == Method::GET && path == format!("/session/driver/{}/status", uuid);
+
+    let mut response = Response::builder().extension(Upstream {
+        session: uuid,
+        address: browser.address,
+    });
+
+    debug!("Serving {:?}", uuid);
+    if browser.driver_session != uuid.to_string() {
+        recovery::redirect(&mut request, uuid, &browser.driver_session);
+    }
+    webdriver_meta.headers.apply(request.headers_mut());
+    let command = path
+        .strip_prefix(&format!("/session/{}", uuid))
+        .unwrap_or_default();
+    let class = Class::of(command);
+    let method = request.method().clone();
+    // Bodies are read to be recorded, see `--cassette-dir`
+    let recorded = match &browser.cassette {
+        Some(_) if !status_request => {
+            let (parts, body) = request.into_parts();
+            let body = to_bytes(body, usize::MAX)
+                .await
+                .map_err(bad_request_error)?;
+            request = Request::from_parts(parts, Body::from(body.clone()));
+            Some(body)
+        }
+        _ => None,
+    };
+    let started = Instant::now();
+    let in_flight = (!status_request).then(|| InFlight::enter(&browser.in_flight));
+    let driver_response = proxy_request(
+        browser.http.clone(),
+        &metrics,
+        Some(&browser.audit),
+        browser.dump.as_ref(),
+        &browser.upstream,
+        request,
+        status_request,
+    )
+    .await?;
+    drop(in_flight);
+    // Idle from the answer on, which may have taken longer than the TTI
+    if webdriver_meta.activity_check && !status_request {
+        state
+            .expiry
+            .touch(uuid, browser.tenant.as_ref().and_then(|t| t.tti));
+        *browser.last_used.lock().await = Instant::now();
+    }
+    if !status_request {
+        browser.latencies.record(class, started.elapsed());
+    }
+    response = copy_headers(response, driver_response.headers(), false);
+    response = response.status(driver_response.status().as_u16());
+    if browser.recoveries > 0 {
+        response = response.header(recovery::RECOVERED, browser.recoveries);
+    }
 
-            Some(p)
+    // Picked before the answer is recorded, so that the cassette tells it apart from the WebDriver's
+    let fault = match &browser.chaos {
+        Some(chaos) if !status_request => chaos.fault(),
+        _ => None,
+    };
+    let body = match (&browser.cassette, recorded) {
+        (Some(cassette), Some(requested)) => {
+            let status = driver_response.status().as_u16();
+            let body = driver_response.bytes().await.map_err(gateway_error)?;
+            let mut interaction = Interaction::new(&method, command, &requested, status, &body);
+            interaction.fault = fault.as_ref().map(|fault| fault.name().to_owned());
+            cassette.record(&interaction).await;
+            Body::from(body)
         }
-        None => None,
+        _ => Body::from_stream(driver_response.bytes_stream()),
     };
 
-    let state = AppState {
-        browsers: Arc::new(RwLock::new(HashMap::new())),
-        http: Client::new(),
-        webdriver: Arc::new(WebDriverMeta {
-            path: args.webdriver,
-            parameters,
-            tti: args.tti.0,
-            next_port: Mutex::new(4445),
-            host: args.host,
-            protocol: args.protocol,
-        }),
-    };
+    match fault {
+        Some(Fault::Delay(delay)) => {
+            debug!("Delaying response to {:?} by {:?} (Chaos)", uuid, delay);
+            sleep(delay).await;
+        }
+        Some(Fault::Drop) => {
+            debug!("Dropping response to {:?} (Chaos)", uuid);
+            return response
+                .body(Body::new(chaos::Dropped))
+                .map_err(internal_server_error);
+        }
+        Some(Fault::Error) => {
+            debug!("Failing response to {:?} (Chaos)", uuid);
+            return Err(w3c::error(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                w3c::UNKNOWN_ERROR,
+                format!("Injected by {}", chaos::CAPABILITY),
+            ));
+        }
+        None => {}
+    }
 
-    let app = Router::default().fallback(proxy).with_state(state);
+    response.body(body).map_err(internal_server_error)
+}
 
-    let listener = TcpListener::bind((args.host, args.port)).await?;
-    info!("Listening on {}:{}", args.host, args.port);
+/// What is known about a session besides its WebDriver, so clients can e.g. keep it from expiring
+async fn session_info(browser: &Browser, webdriver_meta: &WebDriverMeta) -> serde_json::Value {
+    let tti = match browser.tenant.as_ref().and_then(|t| t.tti) {
+        Some(tti) => tti,
+        None => *webdriver_meta.tti.read().await,
+    };
+    let idle = browser.last_used.lock().await.elapsed();
 
-    Ok(
-        axum::serve(listener, ServiceExt::<Request>::into_make_service(app))
-            .with_graceful_shutdown(graceful_shutdown())
-            .await?,
-    )
+    serde_json::json!({
+        "created": admin::unix_seconds(browser.created),
+        "address": browser.address.ip(),
+        "port": browser.address.port(),
+        "pid": admin::pid(browser).await,
+        "capabilities": browser.capabilities,
+        "requests": browser.requests.load(Ordering::Relaxed),
+        "idle": idle.as_secs(),
+        "remaining": tti.saturating_sub(idle).as_secs(),
+        "recoveries": browser.recoveries,
+        "usage": browser.usage.snapshot(),
+        "latency": browser.latencies.summary(),
+        "labels": browser.labels,
+    })
 }
 
-pub async fn proxy(
-    State(browsers): State<Browsers>,
-    State(http): State<Client>,
-    State(webdriver_meta): State<Arc<WebDriverMeta>>,
-    request: Request,
-) -> Result<Response, Response> {
-    let path = request.uri().path().trim_end_matches('/');
-    info!("{}", path);
+/// The session ID, the WebDriver's own ID of the session and the capabilities of a new session's response
+///
+/// A WebDriver's ID which is not a UUID, or left out, is replaced by a new one in the response, while requests are
+/// passed on with the WebDriver's (see [`recovery::redirect`]). Besides W3C responses, this accepts those of the JSON
+/// Wire Protocol (e.g. older Appium servers), whose `sessionId` is not nested in `value` and whose `value` holds the
+/// capabilities.
+fn new_session(body: &mut serde_json::Value) -> (Uuid, String, serde_json::Value) {
+    let legacy = body.get("sessionId").is_some_and(|id| id.is_string());
+    let driver_session = match legacy {
+        true => body.get("sessionId"),
+        false => body.pointer("/value/sessionId"),
+    }
+    .and_then(|id| id.as_str())
+    .map(String::from);
 
-    if (request.method() == Method::GET || request.method() == Method::HEAD) && path == "/status" {
-        let response = Response::builder()
-            .status(200)
-            .header("Content-Type", "application/json");
-        let body = Body::from(r#"{ "value": { "ready": true, "message": "" } }"#);
-        return Ok(response.body(body).map_err(internal_server_error)?);
+    let (session_id, driver_session) = match driver_session {
+        Some(id) => match id.parse() {
+            Ok(session_id) => (session_id, id),
+            Err(_) => (Uuid::new_v4(), id),
+        },
+        None => {
+            let session_id = Uuid::new_v4();
+            (session_id, session_id.to_string())
+        }
+    };
+    if driver_session != session_id.to_string() {
+        warn!(
+            "WebDriver created session {:?}, proxying it as {:?}",
+            driver_session, session_id
+        );
     }
+    set_session_id(body, session_id);
 
-    if request.method() == Method::POST && path == "/session" {
-        let port = loop {
-            let mut port = webdriver_meta.next_port.lock().await;
-            if let Err(_) = TcpListener::bind((webdriver_meta.host, *port)).await {
-                *port = *port + 1;
-                continue;
-            }
-            let usable_port = *port;
-            *port = *port + 1;
+    let capabilities = match legacy {
+        true => body["value"].clone(),
+        false => body
+            .pointer("/value/capabilities")
+            .cloned()
+            .unwrap_or_default(),
+    };
 
-            break usable_port;
-        };
+    (session_id, driver_session, capabilities)
+}
 
-        let mut command = Command::new(webdriver_meta.path.as_ref());
-        command.arg(&format!("--port={}", port));
-        command.arg(&format!("--host={}", webdriver_meta.host));
+/// Has a new session's response carry `session_id`, in the place its protocol expects it
+fn set_session_id(body: &mut serde_json::Value, session_id: Uuid) {
+    if let Some(legacy) = body.get_mut("sessionId").filter(|id| id.is_string()) {
+        *legacy = session_id.to_string().into();
+    } else if let Some(value) = body.get_mut("value").and_then(|v| v.as_object_mut()) {
+        value.insert(String::from("sessionId"), session_id.to_string().into());
+    }
+}
 
-        if let Some(parameters) = webdriver_meta.parameters.as_ref() {
-            for parameter in parameters.split(' ') {
-                command.arg(parameter);
+/// Spawns a WebDriver (on the next free port unless it runs in a pod) and waits until it reports ready
+#[instrument(level = "debug", skip_all)]
+pub async fn spawn_driver(
+    http: &Client,
+    webdriver_meta: &WebDriverMeta,
+    metrics: &Metrics,
+) -> Result<SpawnedDriver, Response> {
+    // Held until the WebDriver is ready, remote endpoints are not started by this instance
+    let _slot = match &webdriver_meta.backend {
+        Backend::Remote(_) => None,
+        _ => webdriver_meta.spawns.enter().await,
+    };
+    let spawned = Instant::now();
+    let (mut child, socket_address, sandbox, port) = match &webdriver_meta.backend {
+        Backend::Process(path) => {
+            let lease = next_port(webdriver_meta).await.ok_or_else(no_free_port)?;
+            let port = lease.port();
+            let mut command = Command::new(path.read().await.as_ref());
+            command.arg(&format!("--port={}", port));
+            match webdriver_meta.appium {
+                true => command
+                    .arg(format!("--address={}", webdriver_meta.host))
+                    .arg("--base-path=/"),
+                false => command.arg(format!("--host={}", webdriver_meta.host)),
+            };
+            if let Some(safety) = &webdriver_meta.safety {
+                let driver_version = webdriver_meta.driver_version.read().await;
+                if let Some(flavor) = Flavor::detect(&path.read().await, driver_version.as_deref())
+                {
+                    let parameters = webdriver_meta.parameters.read().await;
+                    command.args(safety.flags(flavor, webdriver_meta.host, parameters.as_deref()));
+                }
             }
+            let child = spawn_command(command, webdriver_meta).await?;
+            let address = SocketAddr::new(webdriver_meta.host, port);
+            (child, address, None, Some(lease))
+        }
+        Backend::Docker(docker) => {
+            let lease = next_port(webdriver_meta).await.ok_or_else(no_free_port)?;
+            let (command, container) = docker.command(webdriver_meta.host, lease.port());
+            let child = spawn_command(command, webdriver_meta).await?;
+            let address = SocketAddr::new(webdriver_meta.host, lease.port());
+            (
+                child,
+                address,
+                Some(Sandbox::Container(container)),
+                Some(lease),
+            )
+        }
+        Backend::Kubernetes(kubernetes) => {
+            let (child, ip, pod) = kubernetes.start().await.map_err(internal_server_error)?;
+            let address = SocketAddr::new(ip, kubernetes.port);
+            (child, address, Some(Sandbox::Pod(pod)), None)
         }
+        Backend::Remote(remote) => {
+            let upstream = remote.next();
+            let address = remote::address(&upstream).await.map_err(gateway_error)?;
+            return Ok(SpawnedDriver {
+                driver: Unclaimed::default(),
+                address,
+                upstream,
+                output: DriverOutput::new(address, webdriver_meta.log_lines),
+            });
+        }
+    };
+    info!("Browser spawned");
 
-        command.stdout(Stdio::piped());
-        command.stderr(Stdio::piped());
+    let upstream = format!("{}{}", webdriver_meta.protocol, socket_address);
+    let output = DriverOutput::capture(&mut child, socket_address, webdriver_meta.log_lines);
+    // Stopped if the client disconnects while waiting
+    let driver = Unclaimed {
+        process: Some(Driver::from(child)),
+        sandbox,
+        port,
+    };
 
-        command.kill_on_drop(true);
-        debug!("Spawning browser using {:?}", command);
+    if !webdriver_meta
+        .probe
+        .wait(http, &upstream, socket_address)
+        .await
+    {
+        error!(
+            "WebDriver at {} did not become ready (Please check your configuration)",
+            socket_address
+        );
+        driver.discard(Duration::ZERO).await;
+        return Err(w3c::error(
+            StatusCode::BAD_GATEWAY,
+            w3c::SESSION_NOT_CREATED,
+            "WebDriver did not become ready",
+        ));
+    }
+    debug!("Browser started");
+    metrics
+        .spawn_latency
+        .observe(spawned.elapsed().as_secs_f64());
 
-        let child = command.spawn().map_err(internal_server_error)?;
-        info!("Browser spawned");
+    Ok(SpawnedDriver {
+        driver,
+        address: socket_address,
+        upstream,
+        output,
+    })
+}
 
-        let socket_address = SocketAddr::new(webdriver_meta.host, port);
+/// WebDriver whose session has not been created yet, which is stopped if the request is abandoned (e.g. the client
+/// disconnected) before it is claimed
+#[derive(Default)]
+pub struct Unclaimed {
+    process: Option<Driver>,
+    sandbox: Option<Sandbox>,
+    /// Released once dropped, after the WebDriver has been stopped
+    port: Option<Lease>,
+}
 
-        let mut i = 0;
-        loop {
-            if let Ok(response) = http
-                .get(format!(
-                    "{}{}/status",
-                    webdriver_meta.protocol, socket_address
-                ))
-                .send()
-                .await
-            {
-                if response.status().is_success() {
-                    debug!("Browser started");
-                    break;
-                }
-            }
-            i = i + 1;
-            sleep(Duration::from_millis(125)).await;
+impl Unclaimed {
+    /// Hands the WebDriver over to its session
+    pub fn claim(mut self) -> (Option<Driver>, Option<Sandbox>, Option<Lease>) {
+        (self.process.take(), self.sandbox.take(), self.port.take())
+    }
 
-            if i == 40 || i == 80 || i == 120 || i == 480 {
-                eprintln!(
-                    "There might be an issue with the WebDriver (Please check your configuration)"
-                );
+    /// Stops the WebDriver, e.g. as its session was rejected
+    pub async fn discard(mut self, grace: Duration) {
+        discard(self.process.take(), self.sandbox.take(), grace).await;
+    }
+}
 
-                if i == 480 {
-                    exit(1);
-                }
-            }
+impl Drop for Unclaimed {
+    fn drop(&mut self) {
+        if self.process.is_none() && self.sandbox.is_none() {
+            return;
         }
+        warn!("Stopping WebDriver of an abandoned session request");
+        let (process, sandbox, port) = (self.process.take(), self.sandbox.take(), self.port.take());
+        tokio::spawn(async move {
+            discard(process, sandbox, Duration::ZERO).await;
+            drop(port);
+        });
+    }
+}
 
-        let driver_response = proxy_request(
-            http,
-            &webdriver_meta.protocol,
-            socket_address,
-            request,
-            false,
-        )
-        .await?;
-        debug!("Proxied request");
+async fn discard(process: Option<Driver>, sandbox: Option<Sandbox>, grace: Duration) {
+    if let Some(mut process) = process {
+        stop::stop_driver(&mut process, grace).await;
+    }
+    if let Some(sandbox) = sandbox {
+        sandbox.remove(grace).await;
+    }
+}
 
-        let mut response = Response::builder();
+pub struct SpawnedDriver {
+    pub driver: Unclaimed,
+    pub address: SocketAddr,
+    pub upstream: String,
+    pub output: Arc<DriverOutput>,
+}
 
-        for (key, value) in driver_response.headers() {
-            response = response.header(key.as_str(), value.as_ref());
-        }
+/// Reserves the next port of `--port-range` nothing is listening on yet
+async fn next_port(webdriver_meta: &WebDriverMeta) -> Option<Lease> {
+    let (ports, host) = (webdriver_meta.ports.clone(), webdriver_meta.host);
+    tokio::task::spawn_blocking(move || ports.lease(host))
+        .await
+        .ok()
+        .flatten()
+}
 
-        response = response.status(driver_response.status().as_u16());
+fn no_free_port() -> Response {
+    warn!("Rejected session (No free port within --port-range)");
+    w3c::error(
+        StatusCode::SERVICE_UNAVAILABLE,
+        w3c::SESSION_NOT_CREATED,
+        "No free port to start a WebDriver on",
+    )
+}
 
-        #[derive(Debug, Deserialize, Serialize)]
-        struct Value {
-            pub value: Session,
+/// Starts a local process with --parameters appended and its output piped
+async fn spawn_command(
+    mut command: Command,
+    webdriver_meta: &WebDriverMeta,
+) -> Result<Child, Response> {
+    if let Some(parameters) = webdriver_meta.parameters.read().await.as_ref() {
+        for parameter in parameters.split(' ') {
+            command.arg(parameter);
         }
+    }
 
-        #[derive(Debug, Deserialize, Serialize)]
-        #[serde(rename_all = "camelCase")]
-        struct Session {
-            pub session_id: Option<Uuid>,
-            pub capabilities: serde_json::Value,
+    if webdriver_meta.detach {
+        // A pipe would break once this process exits, taking the WebDriver with it
+        command.stdout(Stdio::null());
+        command.stderr(Stdio::null());
+        #[cfg(unix)]
+        command.process_group(0);
+    } else {
+        command.stdout(Stdio::piped());
+        command.stderr(Stdio::piped());
+        command.kill_on_drop(true);
+        // So that browsers are stopped along with their WebDriver
+        #[cfg(unix)]
+        command.process_group(0);
+    }
+    #[cfg(unix)]
+    if webdriver_meta
+        .crashes
+        .as_ref()
+        .is_some_and(|crashes| crashes.core_dumps)
+    {
+        let unlimited = libc::rlimit {
+            rlim_cur: libc::RLIM_INFINITY,
+            rlim_max: libc::RLIM_INFINITY,
+        };
+        // SAFETY: Only async-signal-safe setrlimit is called between fork and exec
+        unsafe {
+            command.pre_exec(move || {
+                if libc::setrlimit(libc::RLIMIT_CORE, &unlimited) != 0 {
+                    return Err(std::io::Error::last_os_error());
+                }
+                Ok(())
+            });
         }
+    }
+    debug!("Spawning browser using {:?}", command);
 
-        let body = driver_response
-            .bytes()
-            .await
-            .map_err(internal_server_error)?;
-        debug!("Collected body {:?}", String::from_utf8_lossy(&body));
-        let mut body: Value = serde_json::from_slice(&body).map_err(internal_server_error)?;
-        debug!("Deserialised body");
-        let session_id = body.value.session_id.unwrap_or(Uuid::default());
-        body.value.session_id = Some(session_id);
-        debug!("Extracted session {:?}", session_id);
-        let _browsers = browsers.clone();
-        browsers.write().await.insert(
-            session_id,
-            Browser {
-                address: socket_address,
-                process: Mutex::new(child),
-                cleanup: Mutex::new(tokio::spawn(async move {
-                    sleep(webdriver_meta.tti).await;
-                    _browsers.write().await.remove(&session_id);
-                    info!("Removed {:?}", session_id);
-                })),
-            },
-        );
+    command.spawn().map_err(internal_server_error)
+}
 
-        let body = Body::from(serde_json::to_string(&body).expect("String to JSON from JSON"));
-        return Ok(response.body(body).map_err(internal_server_error)?);
+/// Ensures the WebDriver executable can be run before it is used, returning the version it reports if `version`
+pub async fn check_driver(
+    path: &Path,
+    version: bool,
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    driver_usable(path).await?;
+    if !version {
+        info!("Using {:?}", path);
+        return Ok(None);
     }
 
-    let mut uuid = path.trim_start_matches("/session/");
-    if let Some(i) = uuid.find('/') {
-        uuid = &uuid[..i];
+    let output = tokio::time::timeout(
+        Duration::from_secs(10),
+        Command::new(path).arg("--version").output(),
+    )
+    .await
+    .map_err(|_| format!("{:?} --version did not finish in time", path))?
+    .map_err(|e| format!("Unable to run {:?}: {}", path, e))?;
+    if !output.status.success() {
+        return Err(format!("{:?} --version exited with {}", path, output.status).into());
     }
-    let uuid = uuid.parse::<Uuid>().map_err(bad_request_error)?;
 
-    if request.method() == Method::DELETE && path == format!("/session/{}", uuid) {
-        if let Some(browser) = browsers.write().await.remove(&uuid) {
-            info!("Removed {:?}", uuid);
-            browser.cleanup.lock().await.abort();
-            let driver_response = proxy_request(
-                http,
-                &webdriver_meta.protocol,
-                browser.address,
-                request,
-                false,
-            )
-            .await?;
+    let version = String::from_utf8_lossy(&output.stdout);
+    let version = version.lines().next().unwrap_or_default().trim().to_owned();
+    info!("Using {} ({:?})", version, path);
+    Ok(Some(version))
+}
 
-            let mut response = Response::builder();
-            for (key, value) in driver_response.headers() {
-                response = response.header(key.as_str(), value.as_ref());
-            }
-            response = response.status(driver_response.status().as_u16());
-            let body = Body::from(
-                driver_response
-                    .bytes()
-                    .await
-                    .map_err(internal_server_error)?,
-            );
-            return Ok(response.body(body).map_err(internal_server_error)?);
+/// Ensures the WebDriver executable is an executable file, without running it
+pub async fn driver_usable(path: &Path) -> Result<(), String> {
+    let metadata = tokio::fs::metadata(path)
+        .await
+        .map_err(|e| format!("Unable to find WebDriver {:?}: {}", path, e))?;
+    if !metadata.is_file() {
+        return Err(format!("WebDriver {:?} is not a file", path));
+    }
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        if metadata.permissions().mode() & 0o111 == 0 {
+            return Err(format!("WebDriver {:?} is not executable", path));
         }
     }
+    Ok(())
+}
 
-    let _browsers = browsers.clone();
-    let browsers = browsers.read().await;
-    let browser = match browsers.get(&uuid) {
-        Some(browser) => browser,
-        None => {
-            debug!("{:?} not found", uuid);
-            return Err((StatusCode::NOT_FOUND, Body::empty()).into_response());
+/// Removes sessions once their WebDriver has exited on its own
+///
+/// A single task looks at the WebDriver processes of all sessions each [`WATCH_INTERVAL`], rather than each session
+/// having a task polling its own.
+pub async fn reap(state: AppState) {
+    // Sessions whose process could not be looked at, which are not tried again
+    let mut unwatchable = HashSet::new();
+
+    loop {
+        sleep(WATCH_INTERVAL).await;
+
+        let mut watched = Vec::new();
+        for shard in state.browsers.shards() {
+            watched.extend(
+                shard
+                    .read()
+                    .await
+                    .iter()
+                    .filter(|(_, browser)| browser.process.is_some())
+                    .map(|(uuid, browser)| (*uuid, browser.clone())),
+            );
         }
-    };
+        unwatchable.retain(|uuid| watched.iter().any(|(watched, _)| watched == uuid));
+        watched.retain(|(uuid, _)| !unwatchable.contains(uuid));
 
-    {
-        let mut cleanup = browser.cleanup.lock().await;
-        cleanup.abort();
-        let tti = webdriver_meta.tti;
-        *cleanup = tokio::spawn(async move {
-            sleep(tti).await;
-            _browsers.write().await.remove(&uuid);
-            info!("Removed {:?}", uuid);
-        });
+        let mut exited = JoinSet::new();
+        for (uuid, browser) in watched {
+            let Some(process) = &browser.process else {
+                continue;
+            };
+            let (pid, status) = {
+                let mut driver = process.lock().await;
+                (driver.pid, driver.process.try_wait())
+            };
+            match status {
+                Ok(Some(status)) => {
+                    exited.spawn(handle_exit(state.clone(), uuid, pid, status));
+                }
+                Ok(None) => {}
+                Err(e) => {
+                    warn!("Unable to watch {:?}: {}", uuid, e);
+                    unwatchable.insert(uuid);
+                }
+            }
+        }
+        // Handled before looking again, as the processes still count as exited until replaced or removed
+        exited.join_all().await;
     }
+}
 
-    let status_request =
-        request.method() == Method::GET && path == format!("/session/driver/{}/status", uuid);
-
-    let mut response = Response::builder();
+/// Recovers or removes a session whose WebDriver has exited
+async fn handle_exit(state: AppState, uuid: Uuid, pid: Option<u32>, status: ExitStatus) {
+    if let Some(crashes) = &state.webdriver.crashes {
+        let crashed = state
+            .browsers
+            .shard(&uuid)
+            .read()
+            .await
+            .get(&uuid)
+            .map(|b| (b.output.clone(), b.capabilities.clone()));
+        if let Some((output, capabilities)) = crashed {
+            crashes
+                .collect(uuid, status, pid, &output, &capabilities)
+                .await;
+        }
+    }
 
-    debug!("Serving {:?}", uuid);
-    let driver_response = proxy_request(
-        http,
-        &webdriver_meta.protocol,
-        browser.address,
-        request,
-        status_request,
-    )
-    .await?;
-    for (key, value) in driver_response.headers() {
-        response = response.header(key.as_str(), value.as_ref());
+    if state.webdriver.recover {
+        warn!("WebDriver of {:?} exited with {}, recovering", uuid, status);
+        match recovery::recover(&state, uuid).await {
+            Ok(()) => {
+                state.metrics.sessions_recovered.inc();
+                return;
+            }
+            Err(e) => warn!("Unable to recover {:?}: {}", uuid, e),
+        }
     }
-    response = response.status(driver_response.status().as_u16());
 
-    Ok(response
-        .body(Body::from_stream(driver_response.bytes_stream()))
-        .map_err(internal_server_error)?)
+    let removed = state.browsers.remove(&uuid).await;
+    if let Some(browser) = removed {
+        state.expiry.cancel(uuid);
+        state.metrics.sessions_crashed.inc();
+        warn!("WebDriver of {:?} exited with {}", uuid, status);
+        if let Some(webhook) = &state.webdriver.webhook {
+            webhook.notify(&state.http, EventKind::Crashed, uuid, &browser.capabilities);
+        }
+        browser.close(state.webdriver.stop_grace).await;
+    }
 }
 
-pub async fn proxy_request<S: AsRef<str>>(
+#[instrument(level = "debug", skip_all, fields(upstream = %upstream))]
+pub async fn proxy_request(
     http: Client,
-    protocol: S,
-    address: SocketAddr,
+    metrics: &Metrics,
+    audit: Option<&AuditLog>,
+    dump: Option<&DebugDump>,
+    upstream: &str,
     request: Request,
     status_request: bool,
 ) -> Result<reqwest::Response, Response> {
@@ -368,9 +2332,16 @@ pub async fn proxy_request<S: AsRef<str>>(
         &Method::POST => reqwest::Method::POST,
         &Method::GET => reqwest::Method::GET,
         &Method::DELETE => reqwest::Method::DELETE,
-        _ => unreachable!(), // According to specification
+        method => {
+            return Err(w3c::error(
+                StatusCode::METHOD_NOT_ALLOWED,
+                w3c::UNKNOWN_METHOD,
+                format!("{} is not a method of WebDriver", method),
+            ));
+        }
     };
 
+    let endpoint = request.uri().path().to_owned();
     let mut path = if status_request {
         String::from("/status")
     } else {
@@ -381,11 +2352,17 @@ pub async fn proxy_request<S: AsRef<str>>(
         path.push_str(query);
     }
 
-    let url = Url::from_str(&format!("{}{}{}", protocol.as_ref(), address, path))
-        .map_err(internal_server_error)?;
+    let url = Url::from_str(&format!("{}{}", upstream, path)).map_err(internal_server_error)?;
 
     let mut header_map = reqwest::header::HeaderMap::new();
     for (key, value) in request.headers() {
+        // WebDrivers are to answer uncompressed, as bodies are read and rewritten (compressed again by --compress)
+        if HOP_BY_HOP.contains(&key.as_str())
+            || key == header::ACCEPT_ENCODING
+            || key == header::CONTENT_LENGTH
+        {
+            continue;
+        }
         let key =
             reqwest::header::HeaderName::from_bytes(key.as_ref()).map_err(bad_request_error)?;
         let value =
@@ -393,12 +2370,96 @@ pub async fn proxy_request<S: AsRef<str>>(
         header_map.insert(key, value);
     }
     header_map.remove(reqwest::header::HOST);
+    telemetry::propagate(&mut header_map);
 
-    let bytes = to_bytes(request.into_body(), usize::MAX)
-        .await
-        .map_err(internal_server_error)?;
-    let request = http.request(method, url).headers(header_map).body(bytes);
-    Ok(request.send().await.map_err(gateway_error)?)
+    // Streamed as it arrives, with bounded buffering, unless dumped
+    let (body, head) = match dump {
+        Some(dump) => {
+            let bytes = to_bytes(request.into_body(), usize::MAX)
+                .await
+                .map_err(internal_server_error)?;
+            dump.request(&method, &path, &header_map, &bytes).await;
+            let head = Head::of(&bytes);
+            (reqwest::Body::from(bytes), head)
+        }
+        None => {
+            // Not all WebDrivers accept chunked requests, so known lengths are passed on
+            if let Some(length) = hyper::body::Body::size_hint(request.body()).exact() {
+                header_map.insert(reqwest::header::CONTENT_LENGTH, length.into());
+            }
+            let (tee, head) = Tee::new(request.into_body());
+            let stream = Body::new(tee).into_data_stream();
+            (reqwest::Body::wrap_stream(stream), head)
+        }
+    };
+    let latency = metrics
+        .request_latency
+        .with_label_values(&[method.as_str()])
+        .start_timer();
+    let started = Instant::now();
+    let request = http
+        .request(method.clone(), url)
+        .headers(header_map)
+        .body(body);
+    let response = request.send().await;
+    if let Some(audit) = audit {
+        let status = response.as_ref().ok().map(|r| r.status().as_u16());
+        audit
+            .record(&method, &endpoint, &head.bytes(), status, started.elapsed())
+            .await;
+    }
+    let response = response.map_err(|e| {
+        metrics.upstream_errors.inc();
+        gateway_error(e)
+    })?;
+    latency.observe_duration();
+
+    match dump {
+        Some(dump) => {
+            let status = response.status();
+            let headers = response.headers().clone();
+            let body = response.bytes().await.map_err(gateway_error)?;
+            dump.response(status, &headers, &body, started.elapsed())
+                .await;
+            // Passed on as if it had not been read
+            let mut response = axum::http::Response::new(body);
+            *response.status_mut() = status;
+            *response.headers_mut() = headers;
+            Ok(reqwest::Response::from(response))
+        }
+        None => Ok(response),
+    }
+}
+
+/// Copies the headers of a WebDriver's response, leaving out those describing the body if it has been `rewritten`
+///
+/// Hop-by-hop headers, including those named in `Connection`, and headers identifying the WebDriver are left out.
+pub fn copy_headers(
+    mut response: axum::http::response::Builder,
+    headers: &reqwest::header::HeaderMap,
+    rewritten: bool,
+) -> axum::http::response::Builder {
+    let connection: Vec<String> = headers
+        .get_all(reqwest::header::CONNECTION)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .map(|name| name.trim().to_ascii_lowercase())
+        .collect();
+    for (key, value) in headers {
+        let describes_body =
+            key == reqwest::header::CONTENT_LENGTH || key == reqwest::header::CONTENT_ENCODING;
+        if HOP_BY_HOP.contains(&key.as_str())
+            || connection.iter().any(|name| name == key.as_str())
+            || branding::IDENTIFYING.contains(&key.as_str())
+            || (rewritten && describes_body)
+        {
+            continue;
+        }
+        response = response.header(key.as_str(), value.as_ref());
+    }
+
+    response
 }
 
 pub fn gateway_error<E>(e: E) -> Response
@@ -406,7 +2467,7 @@ where
     E: std::error::Error,
 {
     error!("Gateway Error: {e}");
-    (StatusCode::BAD_GATEWAY, e.to_string()).into_response()
+    w3c::error(StatusCode::BAD_GATEWAY, w3c::UNKNOWN_ERROR, e.to_string())
 }
 
 pub fn internal_server_error<E>(e: E) -> Response
@@ -414,7 +2475,11 @@ where
     E: std::error::Error,
 {
     error!("Internal Server Error: {e}");
-    (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()).into_response()
+    w3c::error(
+        StatusCode::INTERNAL_SERVER_ERROR,
+        w3c::UNKNOWN_ERROR,
+        e.to_string(),
+    )
 }
 
 pub fn bad_request_error<E>(e: E) -> Response
@@ -422,10 +2487,110 @@ where
     E: std::error::Error,
 {
     error!("Bad Request: {e}");
-    (StatusCode::BAD_REQUEST, e.to_string()).into_response()
+    w3c::error(
+        StatusCode::BAD_REQUEST,
+        w3c::INVALID_ARGUMENT,
+        e.to_string(),
+    )
+}
+
+pub fn unknown_command(method: &Method, path: &str) -> Response {
+    w3c::error(
+        StatusCode::NOT_FOUND,
+        w3c::UNKNOWN_COMMAND,
+        format!("{} {} is not a known command", method, path),
+    )
+}
+
+pub fn unknown_session(uuid: Uuid) -> Response {
+    w3c::error(
+        StatusCode::NOT_FOUND,
+        w3c::INVALID_SESSION_ID,
+        format!("No active session with ID {}", uuid),
+    )
+}
+
+/// Strips quotes surrounding `--parameters`
+fn unquote(parameters: Option<String>) -> Option<String> {
+    match parameters {
+        Some(mut p) => {
+            if (p.starts_with("\\\"") && p.ends_with("\\\""))
+                || (p.starts_with("\\'") && p.ends_with("\\'"))
+            {
+                let tmp = &p[2..];
+                p = String::from(&tmp[..2]);
+            } else if (p.starts_with('"') && p.ends_with('"'))
+                || (p.starts_with("'") && p.ends_with("'"))
+            {
+                let tmp = &p[1..];
+                p = String::from(&tmp[..1]);
+            }
+
+            Some(p)
+        }
+        None => None,
+    }
+}
+
+fn accepted_tokens(args: &Args) -> std::io::Result<Vec<String>> {
+    let mut tokens = args.auth_token.clone();
+    if let Some(path) = &args.auth_token_file {
+        tokens.extend(Tokens::read(path)?);
+    }
+
+    Ok(tokens)
+}
+
+fn upstream_headers(args: &Args) -> UpstreamHeaders {
+    UpstreamHeaders::new(
+        args.strip_header.clone(),
+        args.inject_header.clone(),
+        args.forward_authorization,
+    )
+}
+
+/// Applies options which can change at runtime whenever SIGHUP is received
+#[cfg(unix)]
+async fn reload_on_hangup(state: AppState) {
+    let mut hangup = signal::unix::signal(signal::unix::SignalKind::hangup())
+        .expect("Failed to install signal handler");
+
+    while hangup.recv().await.is_some() {
+        let args = match config::reload::<Args>() {
+            Ok(args) => args,
+            Err(e) => {
+                error!("Unable to reload configuration: {}", e);
+                continue;
+            }
+        };
+        let tokens = match accepted_tokens(&args) {
+            Ok(tokens) => tokens,
+            Err(e) => {
+                error!("Unable to reload tokens: {}", e);
+                continue;
+            }
+        };
+        let tenants = match &args.tenants {
+            Some(path) => match tenant::read(path) {
+                Ok(tenants) => tenants,
+                Err(e) => {
+                    error!("Unable to reload tenants: {}", e);
+                    continue;
+                }
+            },
+            None => Vec::new(),
+        };
+
+        *state.webdriver.tti.write().await = args.tti.0;
+        *state.webdriver.parameters.write().await = unquote(args.parameters);
+        state.capacity.set_max_sessions(args.max_sessions);
+        state.tokens.replace(tokens, args.sessions_per_token);
+        state.tokens.replace_tenants(tenants, args.tenant_header);
+        info!("Reloaded configuration (tti, parameters, max-sessions, tokens and tenants)");
+    }
 }
 
-pub async fn graceful_shutdown() {
+pub async fn graceful_shutdown(capacity: Arc<Capacity>) {
     let ctrl_c = async {
         signal::ctrl_c()
             .await
@@ -440,11 +2605,128 @@ pub async fn graceful_shutdown() {
             .await;
     };
 
-    #[cfg(not(unix))]
+    // Console windows being closed and the system shutting down, whereas logging off is ignored as it is also
+    // reported to services whenever any user logs off
+    #[cfg(windows)]
+    let terminate = async {
+        let mut close = signal::windows::ctrl_close().expect("Failed to install signal handler");
+        let mut shutdown =
+            signal::windows::ctrl_shutdown().expect("Failed to install signal handler");
+        let mut ctrl_break =
+            signal::windows::ctrl_break().expect("Failed to install signal handler");
+        tokio::select! {
+            _ = close.recv() => {},
+            _ = shutdown.recv() => {},
+            _ = ctrl_break.recv() => {},
+        }
+    };
+
+    #[cfg(not(any(unix, windows)))]
     let terminate = std::future::pending::<()>();
 
     tokio::select! {
         _ = ctrl_c => {},
         _ = terminate => {},
     }
+
+    info!("Draining");
+    systemd::stopping();
+    capacity.drain();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::body::Body as _;
+    use std::future::poll_fn;
+    use std::pin::Pin;
+    use tokio::io::{AsyncWriteExt, DuplexStream, duplex};
+    use tokio::sync::Notify;
+    use tokio_util::io::ReaderStream;
+
+    /// Many chunks, whereas whether bodies are streamed is told by holding back all but the first (see [`write`])
+    const LARGE: usize = 4 * 1024 * 1024;
+    /// What a print or screenshot of a large page may take up
+    const HUGE: usize = 384 * 1024 * 1024;
+    const CHUNK: usize = 64 * 1024;
+
+    /// Writes `size` bytes, the first chunk only until `proceed` has been notified
+    async fn write(mut writer: DuplexStream, size: usize, proceed: Arc<Notify>) {
+        let chunk = vec![b'x'; CHUNK];
+        writer.write_all(&chunk).await.unwrap();
+        proceed.notified().await;
+        for _ in 1..size / CHUNK {
+            writer.write_all(&chunk).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_large_bodies() {
+        stream(LARGE, Duration::from_secs(10)).await;
+    }
+
+    /// Run with `cargo test -- --ignored streams_huge_bodies`
+    #[tokio::test]
+    #[ignore]
+    async fn streams_huge_bodies() {
+        stream(HUGE, Duration::from_secs(60)).await;
+    }
+
+    /// Proxies a request and its response of `size` bytes each, generated rather than held in memory
+    async fn stream(size: usize, within: Duration) {
+        // Each side holds back all but the first chunk until it has been passed on, which buffering would deadlock
+        let (arrived, received) = (Arc::new(Notify::new()), Arc::new(Notify::new()));
+
+        let upstream = {
+            let (arrived, received) = (arrived.clone(), received.clone());
+            Router::new().fallback(move |request: Request| async move {
+                let mut body = request.into_body();
+                let mut length = 0;
+                while let Some(frame) = poll_fn(|cx| Pin::new(&mut body).poll_frame(cx)).await {
+                    if let Some(data) = frame.unwrap().data_ref() {
+                        length += data.len();
+                        arrived.notify_one();
+                    }
+                }
+                assert_eq!(length, size);
+
+                let (writer, reader) = duplex(CHUNK);
+                tokio::spawn(write(writer, size, received));
+                Body::from_stream(ReaderStream::new(reader))
+            })
+        };
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, upstream).await });
+
+        let (writer, reader) = duplex(CHUNK);
+        tokio::spawn(write(writer, size, arrived));
+        let request = Request::post("/session/c0ffee/print")
+            .body(Body::from_stream(ReaderStream::new(reader)))
+            .unwrap();
+
+        let proxied = async {
+            let mut response = proxy_request(
+                Client::new(),
+                &Metrics::new().unwrap(),
+                None,
+                None,
+                &format!("http://{}", address),
+                request,
+                false,
+            )
+            .await
+            .unwrap();
+            let mut length = 0;
+            while let Some(chunk) = response.chunk().await.unwrap() {
+                length += chunk.len();
+                received.notify_one();
+            }
+            length
+        };
+        let length = tokio::time::timeout(within, proxied)
+            .await
+            .expect("Bodies are buffered");
+        assert_eq!(length, size);
+    }
 }
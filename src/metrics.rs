@@ -0,0 +1,112 @@
+use crate::{Browsers, internal_server_error};
+use axum::extract::State;
+use axum::response::{IntoResponse, Response};
+use prometheus::{
+    Encoder, Histogram, HistogramOpts, HistogramVec, IntCounter, IntGauge, Registry, TextEncoder,
+};
+use std::sync::Arc;
+use std::time::Instant;
+
+pub struct Metrics {
+    pub registry: Registry,
+    pub sessions_created: IntCounter,
+    pub sessions_deleted: IntCounter,
+    pub sessions_expired: IntCounter,
+    pub sessions_killed: IntCounter,
+    pub sessions_crashed: IntCounter,
+    pub sessions_recovered: IntCounter,
+    pub sessions_active: IntGauge,
+    pub spawn_latency: Histogram,
+    pub request_latency: HistogramVec,
+    pub upstream_errors: IntCounter,
+    pub started: Instant,
+}
+
+impl Metrics {
+    pub fn new() -> Result<Self, prometheus::Error> {
+        let registry = Registry::new_custom(Some(String::from("sessiondriver")), None)?;
+
+        let sessions_created =
+            IntCounter::new("sessions_created_total", "Sessions created since start")?;
+        let sessions_deleted = IntCounter::new(
+            "sessions_deleted_total",
+            "Sessions deleted by their client since start",
+        )?;
+        let sessions_expired = IntCounter::new(
+            "sessions_expired_total",
+            "Sessions removed after exceeding the TTI since start",
+        )?;
+        let sessions_killed = IntCounter::new(
+            "sessions_killed_total",
+            "Sessions forcefully removed through the administrative API since start",
+        )?;
+        let sessions_crashed = IntCounter::new(
+            "sessions_crashed_total",
+            "Sessions removed after their WebDriver exited on its own since start",
+        )?;
+        let sessions_recovered = IntCounter::new(
+            "sessions_recovered_total",
+            "Sessions moved to a new WebDriver after theirs exited on its own since start",
+        )?;
+        let sessions_active = IntGauge::new("sessions_active", "Currently managed sessions")?;
+        let spawn_latency = Histogram::with_opts(
+            HistogramOpts::new(
+                "driver_spawn_seconds",
+                "Time from spawning a WebDriver until it reports ready",
+            )
+            .buckets(vec![0.25, 0.5, 1.0, 2.5, 5.0, 10.0, 30.0, 60.0]),
+        )?;
+        let request_latency = HistogramVec::new(
+            HistogramOpts::new(
+                "proxied_request_seconds",
+                "Latency of requests proxied to a WebDriver",
+            ),
+            &["method"],
+        )?;
+        let upstream_errors = IntCounter::new(
+            "upstream_errors_total",
+            "Requests that could not be delivered to a WebDriver",
+        )?;
+
+        registry.register(Box::new(sessions_created.clone()))?;
+        registry.register(Box::new(sessions_deleted.clone()))?;
+        registry.register(Box::new(sessions_expired.clone()))?;
+        registry.register(Box::new(sessions_killed.clone()))?;
+        registry.register(Box::new(sessions_crashed.clone()))?;
+        registry.register(Box::new(sessions_recovered.clone()))?;
+        registry.register(Box::new(sessions_active.clone()))?;
+        registry.register(Box::new(spawn_latency.clone()))?;
+        registry.register(Box::new(request_latency.clone()))?;
+        registry.register(Box::new(upstream_errors.clone()))?;
+
+        Ok(Self {
+            registry,
+            sessions_created,
+            sessions_deleted,
+            sessions_expired,
+            sessions_killed,
+            sessions_crashed,
+            sessions_recovered,
+            sessions_active,
+            spawn_latency,
+            request_latency,
+            upstream_errors,
+            started: Instant::now(),
+        })
+    }
+}
+
+pub async fn export(
+    State(browsers): State<Browsers>,
+    State(metrics): State<Arc<Metrics>>,
+) -> Result<Response, Response> {
+    metrics.sessions_active.set(browsers.len().await as i64);
+
+    let encoder = TextEncoder::new();
+    let mut buffer = Vec::new();
+    encoder
+        .encode(&metrics.registry.gather(), &mut buffer)
+        .map_err(internal_server_error)?;
+
+    Ok(([("Content-Type", encoder.format_type())], buffer).into_response())
+}
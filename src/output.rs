@@ -0,0 +1,126 @@
+use log::debug;
+use serde::Serialize;
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex, OnceLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncBufReadExt, AsyncRead, BufReader};
+use tokio::process::Child;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct Line {
+    pub stream: Stream,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub message: String,
+}
+
+/// Entry of the legacy log endpoint (`POST /session/{uuid}/log`), as read by e.g. Selenium's `get_log("driver")`
+#[derive(Debug, Clone, Serialize)]
+pub struct LogEntry {
+    pub timestamp: u64,
+    pub level: &'static str,
+    pub message: String,
+}
+
+impl From<Line> for LogEntry {
+    fn from(line: Line) -> Self {
+        let level = match line.stream {
+            Stream::Stdout => "INFO",
+            Stream::Stderr => "WARNING",
+        };
+        Self {
+            timestamp: line.timestamp,
+            level,
+            message: line.message,
+        }
+    }
+}
+
+/// Retains the most recent lines a WebDriver wrote to stdout and stderr
+pub struct DriverOutput {
+    address: SocketAddr,
+    session: OnceLock<Uuid>,
+    capacity: usize,
+    lines: Mutex<VecDeque<Line>>,
+}
+
+impl DriverOutput {
+    /// Takes over the piped stdout and stderr of `child`
+    pub fn capture(child: &mut Child, address: SocketAddr, capacity: usize) -> Arc<Self> {
+        let output = Self::new(address, capacity);
+
+        if let Some(stdout) = child.stdout.take() {
+            tokio::spawn(output.clone().read(stdout, Stream::Stdout));
+        }
+        if let Some(stderr) = child.stderr.take() {
+            tokio::spawn(output.clone().read(stderr, Stream::Stderr));
+        }
+
+        output
+    }
+
+    /// Retains nothing until lines are captured, e.g. for sessions hosted remotely
+    pub fn new(address: SocketAddr, capacity: usize) -> Arc<Self> {
+        Arc::new(Self {
+            address,
+            session: OnceLock::new(),
+            capacity,
+            lines: Mutex::new(VecDeque::with_capacity(capacity.min(1024))),
+        })
+    }
+
+    /// Prefixes subsequent log lines with `session` instead of the driver's address
+    pub fn assign(&self, session: Uuid) {
+        let _ = self.session.set(session);
+    }
+
+    pub fn lines(&self) -> Vec<Line> {
+        self.lines
+            .lock()
+            .expect("Driver output lock poisoned")
+            .iter()
+            .cloned()
+            .collect()
+    }
+
+    async fn read<R: AsyncRead + Unpin>(self: Arc<Self>, reader: R, stream: Stream) {
+        let mut lines = BufReader::new(reader).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            self.push(stream, line);
+        }
+    }
+
+    fn push(&self, stream: Stream, message: String) {
+        match self.session.get() {
+            Some(session) => debug!(target: "sessiondriver::driver", "[{}] {}", session, message),
+            None => debug!(target: "sessiondriver::driver", "[{}] {}", self.address, message),
+        }
+
+        if self.capacity == 0 {
+            return;
+        }
+
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_millis() as u64)
+            .unwrap_or_default();
+        let mut lines = self.lines.lock().expect("Driver output lock poisoned");
+        if lines.len() == self.capacity {
+            lines.pop_front();
+        }
+        lines.push_back(Line {
+            stream,
+            timestamp,
+            message,
+        });
+    }
+}
@@ -0,0 +1,76 @@
+use crate::w3c;
+use axum::extract::{Request, State};
+use axum::http::{Method, StatusCode, header};
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use log::debug;
+use std::sync::Arc;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Seconds clients are asked to wait before retrying a shed request
+const RETRY_AFTER: u64 = 1;
+
+/// Probes, which are answered regardless so that an overloaded instance is not restarted
+const EXEMPT: [&str; 3] = ["/healthz", "/readyz", "/metrics"];
+
+/// Requests served at the same time, beyond which further ones are shed with `503` rather than queued
+pub struct Limits {
+    requests: Option<Arc<Semaphore>>,
+    /// Of `POST /session`, which start a WebDriver each
+    new_sessions: Option<Arc<Semaphore>>,
+}
+
+impl Limits {
+    pub fn new(requests: Option<usize>, new_sessions: Option<usize>) -> Self {
+        Self {
+            requests: requests.map(|max| Arc::new(Semaphore::new(max))),
+            new_sessions: new_sessions.map(|max| Arc::new(Semaphore::new(max))),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.requests.is_none() && self.new_sessions.is_none()
+    }
+}
+
+/// Held until the response (though not its body) has been produced
+fn enter(limit: &Option<Arc<Semaphore>>) -> Result<Option<OwnedSemaphorePermit>, ()> {
+    match limit {
+        Some(limit) => limit.clone().try_acquire_owned().map(Some).map_err(|_| ()),
+        None => Ok(None),
+    }
+}
+
+pub async fn shed(State(limits): State<Arc<Limits>>, request: Request, next: Next) -> Response {
+    let path = request.uri().path().trim_end_matches('/');
+    if EXEMPT.contains(&path) {
+        return next.run(request).await;
+    }
+
+    let Ok(_request) = enter(&limits.requests) else {
+        debug!("Shed request to {} (Too many concurrent requests)", path);
+        return overloaded("Too many concurrent requests");
+    };
+    let new_session =
+        request.method() == Method::POST && (path == "/session" || path == "/wd/hub/session");
+    let _new_session = match new_session {
+        true => match enter(&limits.new_sessions) {
+            Ok(permit) => permit,
+            Err(()) => {
+                debug!("Shed new session (Too many concurrent new sessions)");
+                return overloaded("Too many sessions being created");
+            }
+        },
+        false => None,
+    };
+
+    next.run(request).await
+}
+
+fn overloaded(message: &'static str) -> Response {
+    (
+        [(header::RETRY_AFTER, RETRY_AFTER.to_string())],
+        w3c::error(StatusCode::SERVICE_UNAVAILABLE, w3c::UNKNOWN_ERROR, message),
+    )
+        .into_response()
+}
@@ -0,0 +1,257 @@
+use crate::audit::AuditLog;
+use crate::capacity::{self, Reservation};
+use crate::labels::Labels;
+use crate::latency::Latencies;
+use crate::output::DriverOutput;
+#[cfg(unix)]
+use crate::stop;
+use crate::usage::Usage;
+use crate::{AppState, Browser, Browsers, Sandbox, WATCH_INTERVAL};
+use async_lock::Mutex;
+use log::{debug, info, warn};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+#[cfg(not(unix))]
+use std::process::Stdio;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, AtomicUsize};
+use std::time::{Duration, Instant, SystemTime};
+#[cfg(not(unix))]
+use tokio::process::Command;
+use tokio::time::sleep;
+use uuid::Uuid;
+
+/// Time an adopted WebDriver is given to answer `/status`
+const HEALTH_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// What is kept of a session to re-adopt it after a restart
+#[derive(Debug, Deserialize, Serialize)]
+pub struct Persisted {
+    pub session: Uuid,
+    pub address: SocketAddr,
+    pub upstream: String,
+    pub pid: Option<u32>,
+    pub sandbox: Option<Sandbox>,
+    pub created: SystemTime,
+    pub capabilities: serde_json::Value,
+    #[serde(default)]
+    pub tenant: Option<String>,
+    /// ID of the session at its WebDriver if it differs
+    #[serde(default)]
+    pub driver_session: Option<String>,
+    #[serde(default)]
+    pub recoveries: u32,
+    #[serde(default)]
+    pub labels: Labels,
+}
+
+/// Writes the sessions to `path` whenever what is kept of them has changed, e.g. a session's WebDriver once recovered
+pub fn keep(browsers: Browsers, path: PathBuf) {
+    tokio::spawn(async move {
+        let mut saved = None;
+        loop {
+            sleep(WATCH_INTERVAL).await;
+
+            let sessions = persisted(&browsers).await;
+            let serialised = match serde_json::to_vec(&sessions) {
+                Ok(serialised) => serialised,
+                Err(e) => {
+                    warn!("Unable to persist sessions to {:?}: {}", path, e);
+                    continue;
+                }
+            };
+            if saved.as_ref() == Some(&serialised) {
+                continue;
+            }
+            match write(&path, &serialised, sessions.len()).await {
+                Ok(()) => saved = Some(serialised),
+                Err(e) => warn!("Unable to persist sessions to {:?}: {}", path, e),
+            }
+        }
+    });
+}
+
+pub async fn save(browsers: &Browsers, path: &Path) -> std::io::Result<()> {
+    let sessions = persisted(browsers).await;
+    write(path, &serde_json::to_vec(&sessions)?, sessions.len()).await
+}
+
+async fn persisted(browsers: &Browsers) -> Vec<Persisted> {
+    let mut sessions = Vec::new();
+    for shard in browsers.shards() {
+        for (session, browser) in shard.read().await.iter() {
+            let pid = match &browser.process {
+                Some(process) => process.lock().await.pid,
+                None => None,
+            };
+            sessions.push(Persisted {
+                session: *session,
+                address: browser.address,
+                upstream: browser.upstream.clone(),
+                pid,
+                sandbox: browser.sandbox.clone(),
+                created: browser.created,
+                capabilities: browser.capabilities.clone(),
+                tenant: browser.tenant.as_ref().map(|t| t.name.clone()),
+                driver_session: (browser.driver_session != session.to_string())
+                    .then(|| browser.driver_session.clone()),
+                recoveries: browser.recoveries,
+                labels: browser.labels.clone(),
+            });
+        }
+    }
+    sessions
+}
+
+async fn write(path: &Path, serialised: &[u8], sessions: usize) -> std::io::Result<()> {
+    // Replaced at once, so a crash never leaves a truncated file behind
+    let temporary = path.with_extension("tmp");
+    tokio::fs::write(&temporary, serialised).await?;
+    tokio::fs::rename(&temporary, path).await?;
+    debug!("Persisted {} session(s)", sessions);
+
+    Ok(())
+}
+
+/// Takes over the sessions in `path` whose WebDriver still answers
+pub async fn adopt(state: &AppState, path: &Path) -> std::io::Result<()> {
+    let sessions: Vec<Persisted> = match tokio::fs::read(path).await {
+        Ok(bytes) => serde_json::from_slice(&bytes)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+
+    for persisted in sessions {
+        let session = persisted.session;
+        let http = state.webdriver.client().map_err(std::io::Error::other)?;
+        let healthy = http
+            .get(format!("{}/status", persisted.upstream))
+            .timeout(HEALTH_TIMEOUT)
+            .send()
+            .await
+            .is_ok_and(|response| response.status().is_success());
+        // Sandboxes are looked up by name, whereas a process ID may have been reused by now
+        let sandbox = match (persisted.sandbox, persisted.pid) {
+            (Some(sandbox), _) => Some(sandbox),
+            (None, Some(pid)) if healthy => Some(Sandbox::Process(pid)),
+            (None, _) => None,
+        };
+        if !healthy {
+            warn!(
+                "Dropped {:?} (WebDriver at {} is gone)",
+                session, persisted.address
+            );
+            if let Some(sandbox) = sandbox {
+                sandbox.remove(state.webdriver.stop_grace).await;
+            }
+            continue;
+        }
+
+        let permit = match state.capacity.reserve() {
+            Reservation::Granted(permit) => permit,
+            _ => {
+                warn!("Dropped {:?} (At capacity)", session);
+                if let Some(sandbox) = sandbox {
+                    sandbox.remove(state.webdriver.stop_grace).await;
+                }
+                continue;
+            }
+        };
+        // Counted where a slot remains, as the session is kept either way
+        let requested =
+            serde_json::json!({ "capabilities": { "alwaysMatch": persisted.capabilities } });
+        let limited = state
+            .webdriver
+            .limits
+            .iter()
+            .filter_map(|limit| {
+                capacity::reserve_limits(std::slice::from_ref(limit), &requested).ok()
+            })
+            .flatten()
+            .collect();
+        let output = DriverOutput::new(persisted.address, state.webdriver.log_lines);
+        output.assign(session);
+        let audit = AuditLog::new(state.webdriver.audit_dir.as_deref());
+        audit.attach(session).await;
+
+        // Sessions of tenants no longer configured are left to requests without a tenant
+        let tenant = persisted
+            .tenant
+            .and_then(|name| state.tokens.tenant_named(&name));
+        state
+            .expiry
+            .touch(session, tenant.as_ref().and_then(|t| t.tti));
+        state
+            .browsers
+            .insert(
+                session,
+                Arc::new(Browser {
+                    address: persisted.address,
+                    upstream: persisted.upstream,
+                    process: None,
+                    created: persisted.created,
+                    permit,
+                    quota: None,
+                    limited,
+                    sandbox,
+                    port: Some(persisted.address)
+                        .filter(|address| address.ip() == state.webdriver.host)
+                        .and_then(|address| state.webdriver.ports.reserve(address.port())),
+                    output,
+                    audit,
+                    dump: None,
+                    recording: None,
+                    downloads: None,
+                    uploads: None,
+                    capture: None,
+                    capabilities: persisted.capabilities,
+                    requested: serde_json::Value::Null,
+                    driver_session: persisted
+                        .driver_session
+                        .unwrap_or_else(|| session.to_string()),
+                    recoveries: persisted.recoveries,
+                    tenant,
+                    http,
+                    requests: AtomicU64::new(0),
+                    in_flight: AtomicUsize::new(0),
+                    last_used: Mutex::new(Instant::now()),
+                    usage: Usage::default(),
+                    latencies: Latencies::default(),
+                    chaos: None,
+                    cassette: None,
+                    labels: persisted.labels,
+                }),
+            )
+            .await;
+        info!("Adopted {:?} at {}", session, persisted.address);
+    }
+
+    Ok(())
+}
+
+/// Stops a WebDriver which is not a child process of this one, along with what it started (i.e. browsers), killing it
+/// if it is still running after `grace`
+///
+/// Adopted WebDrivers were started in a process group of their own on Unix, which is signalled as a whole.
+#[cfg(unix)]
+pub async fn terminate(pid: u32, grace: Duration) {
+    stop::stop_group(pid, grace).await;
+}
+
+/// Stops a WebDriver which is not a child process of this one, along with its process tree
+#[cfg(not(unix))]
+pub async fn terminate(pid: u32, _: Duration) {
+    let status = Command::new("taskkill")
+        .args(["/F", "/T", "/PID"])
+        .arg(pid.to_string())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await;
+    match status {
+        Ok(status) if status.success() => info!("Terminated process {}", pid),
+        Ok(status) => warn!("Unable to terminate process {} ({})", pid, status),
+        Err(e) => warn!("Unable to terminate process {}: {}", pid, e),
+    }
+}
@@ -0,0 +1,339 @@
+use crate::{bad_request_error, internal_server_error, w3c};
+use axum::body::{Body, to_bytes};
+use axum::extract::Request;
+use axum::http::{StatusCode, header};
+use axum::response::Response;
+use log::info;
+use serde_json::{Map, Value, json};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+
+/// Vendor options and the argument that makes their browser headless
+const HEADLESS: [(&str, &str); 3] = [
+    ("moz:firefoxOptions", "-headless"),
+    ("goog:chromeOptions", "--headless=new"),
+    ("ms:edgeOptions", "--headless=new"),
+];
+
+/// Chromium arguments removed unless `allow_no_sandbox` is set
+const NO_SANDBOX: [&str; 2] = ["--no-sandbox", "--disable-setuid-sandbox"];
+
+/// Rules new-session capabilities are rewritten or rejected by
+#[derive(Debug, Default)]
+pub struct Policy {
+    pub headless: bool,
+    pub allow_no_sandbox: bool,
+    pub download_dir: Option<PathBuf>,
+    /// Capabilities a new session must not request
+    pub denied: Vec<String>,
+    /// Whether the WebDriver is an Appium server, whose sessions requesting `appium:` capabilities are not made headless
+    /// or pointed at a download directory or proxy
+    pub appium: bool,
+}
+
+impl Policy {
+    /// Applies the rules to the body of `POST /session`, returning the rewritten request along with its body
+    ///
+    /// `download_dir` takes precedence over [`Policy::download_dir`]. Browsers are pointed at `proxy` unless the
+    /// session requests a proxy itself.
+    pub async fn enforce(
+        &self,
+        request: Request,
+        download_dir: Option<&Path>,
+        proxy: Option<SocketAddr>,
+    ) -> Result<(Request, Value), Response> {
+        let (mut parts, body) = request.into_parts();
+        let body = to_bytes(body, usize::MAX)
+            .await
+            .map_err(internal_server_error)?;
+        let mut body: Value = serde_json::from_slice(&body).map_err(bad_request_error)?;
+
+        if let Err(message) = self.apply(&mut body, download_dir, proxy) {
+            info!("Rejected session ({})", message);
+            return Err(w3c::error(
+                StatusCode::BAD_REQUEST,
+                w3c::INVALID_ARGUMENT,
+                message,
+            ));
+        }
+
+        parts.headers.remove(header::CONTENT_LENGTH);
+        let request = Request::from_parts(parts, Body::from(body.to_string()));
+        Ok((request, body))
+    }
+
+    fn apply(
+        &self,
+        body: &mut Value,
+        download_dir: Option<&Path>,
+        proxy: Option<SocketAddr>,
+    ) -> Result<(), String> {
+        let download_dir = download_dir.or(self.download_dir.as_deref());
+        // Left to the WebDriver to reject
+        if !body.is_object() {
+            return Ok(());
+        }
+
+        if let Some(capabilities) = body.get_mut("capabilities") {
+            for (_, set) in sets(capabilities) {
+                if let Some(key) = self
+                    .denied
+                    .iter()
+                    .find(|key| set.contains_key(key.as_str()))
+                {
+                    return Err(format!("Capability {:?} is not allowed", key));
+                }
+            }
+
+            if !self.allow_no_sandbox {
+                for key in ["goog:chromeOptions", "ms:edgeOptions"] {
+                    for (_, set) in sets(capabilities) {
+                        if let Some(Value::Array(args)) =
+                            set.get_mut(key).and_then(|o| o.get_mut("args"))
+                        {
+                            args.retain(|arg| !NO_SANDBOX.iter().any(|flag| arg == flag));
+                        }
+                    }
+                }
+            }
+
+            // Appium capabilities describe devices rather than desktop browsers
+            if self.appium
+                && sets(capabilities)
+                    .iter()
+                    .any(|(_, set)| set.keys().any(|key| key.starts_with("appium:")))
+            {
+                return Ok(());
+            }
+        }
+
+        if self.headless || download_dir.is_some() || proxy.is_some() {
+            if !body["capabilities"].is_object() {
+                body["capabilities"] = json!({});
+            }
+            if !body["capabilities"]["alwaysMatch"].is_object() {
+                body["capabilities"]["alwaysMatch"] = json!({});
+            }
+        }
+        let Some(capabilities) = body.get_mut("capabilities") else {
+            return Ok(());
+        };
+
+        if self.headless {
+            for (key, argument) in HEADLESS {
+                for options in options(capabilities, key) {
+                    let args = arguments(options);
+                    if !args.iter().any(|arg| arg == argument) {
+                        args.push(Value::from(argument));
+                    }
+                }
+            }
+        }
+
+        if let Some(proxy) = proxy {
+            if sets(capabilities)
+                .iter()
+                .any(|(_, set)| set.contains_key("proxy"))
+            {
+                info!("Not capturing traffic (Proxy requested)");
+            } else if let Some(always) = capabilities["alwaysMatch"].as_object_mut() {
+                always.insert(
+                    String::from("proxy"),
+                    json!({
+                        "proxyType": "manual",
+                        "httpProxy": proxy.to_string(),
+                        "sslProxy": proxy.to_string(),
+                    }),
+                );
+            }
+        }
+
+        if let Some(directory) = download_dir {
+            let directory = directory.to_string_lossy();
+            for options in options(capabilities, "moz:firefoxOptions") {
+                let prefs = object(options, "prefs");
+                prefs.insert(
+                    String::from("browser.download.dir"),
+                    Value::from(directory.as_ref()),
+                );
+                prefs.insert(String::from("browser.download.folderList"), Value::from(2));
+            }
+            for key in ["goog:chromeOptions", "ms:edgeOptions"] {
+                for options in options(capabilities, key) {
+                    let prefs = object(options, "prefs");
+                    prefs.insert(
+                        String::from("download.default_directory"),
+                        Value::from(directory.as_ref()),
+                    );
+                    prefs.insert(
+                        String::from("download.prompt_for_download"),
+                        Value::from(false),
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+/// `alwaysMatch` (flagged `true`) and every entry of `firstMatch`
+fn sets(capabilities: &mut Value) -> Vec<(bool, &mut Map<String, Value>)> {
+    let mut sets = Vec::new();
+    let Some(capabilities) = capabilities.as_object_mut() else {
+        return sets;
+    };
+    for (key, value) in capabilities.iter_mut() {
+        match (key.as_str(), value) {
+            ("alwaysMatch", Value::Object(set)) => sets.push((true, set)),
+            ("firstMatch", Value::Array(entries)) => sets.extend(
+                entries
+                    .iter_mut()
+                    .filter_map(Value::as_object_mut)
+                    .map(|set| (false, set)),
+            ),
+            _ => {}
+        }
+    }
+
+    sets
+}
+
+/// The vendor options `key` of every `firstMatch` entry setting them, otherwise those of `alwaysMatch`
+///
+/// A capability must not appear in both, so options are only added to `alwaysMatch` if no `firstMatch` entry has them.
+fn options<'a>(capabilities: &'a mut Value, key: &str) -> Vec<&'a mut Map<String, Value>> {
+    let (always, first): (Vec<_>, Vec<_>) = sets(capabilities)
+        .into_iter()
+        .partition(|(always, _)| *always);
+    let first: Vec<_> = first
+        .into_iter()
+        .map(|(_, set)| set)
+        .filter(|set| set.contains_key(key))
+        .collect();
+    let sets = match first.is_empty() {
+        true => always.into_iter().map(|(_, set)| set).collect(),
+        false => first,
+    };
+
+    sets.into_iter().map(|set| object(set, key)).collect()
+}
+
+fn object<'a>(map: &'a mut Map<String, Value>, key: &str) -> &'a mut Map<String, Value> {
+    let value = map.entry(key).or_insert_with(|| json!({}));
+    if !value.is_object() {
+        *value = json!({});
+    }
+    value.as_object_mut().expect("Replaced by an object")
+}
+
+fn arguments(options: &mut Map<String, Value>) -> &mut Vec<Value> {
+    let value = options.entry("args").or_insert_with(|| json!([]));
+    if !value.is_array() {
+        *value = json!([]);
+    }
+    value.as_array_mut().expect("Replaced by an array")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rewrites_vendor_options() {
+        let policy = Policy {
+            headless: true,
+            download_dir: Some(PathBuf::from("/downloads")),
+            ..Policy::default()
+        };
+        let mut body = json!({ "capabilities": {
+            "alwaysMatch": { "browserName": "chrome" },
+            "firstMatch": [{ "goog:chromeOptions": { "args": ["--no-sandbox", "--window-size=800,600"] } }]
+        }});
+        policy.apply(&mut body, None, None).unwrap();
+
+        let capabilities = &body["capabilities"];
+        assert_eq!(
+            capabilities["firstMatch"][0]["goog:chromeOptions"]["args"],
+            json!(["--window-size=800,600", "--headless=new"])
+        );
+        assert_eq!(
+            capabilities["firstMatch"][0]["goog:chromeOptions"]["prefs"]["download.default_directory"],
+            "/downloads"
+        );
+        assert!(
+            capabilities["alwaysMatch"]
+                .get("goog:chromeOptions")
+                .is_none()
+        );
+        assert_eq!(
+            capabilities["alwaysMatch"]["moz:firefoxOptions"]["args"],
+            json!(["-headless"])
+        );
+    }
+
+    #[test]
+    fn rejects_denied_capabilities() {
+        let policy = Policy {
+            denied: vec![String::from("moz:debuggerAddress")],
+            ..Policy::default()
+        };
+
+        let mut body =
+            json!({ "capabilities": { "firstMatch": [{}, { "moz:debuggerAddress": true }] } });
+        assert!(policy.apply(&mut body, None, None).is_err());
+        let request = json!({ "capabilities": { "alwaysMatch": { "browserName": "firefox" } } });
+        let mut body = request.clone();
+        assert!(policy.apply(&mut body, None, None).is_ok());
+        assert_eq!(body, request);
+    }
+
+    #[test]
+    fn points_browsers_at_proxy() {
+        let proxy = Some(SocketAddr::from(([127, 0, 0, 1], 4000)));
+
+        let mut body = json!({ "capabilities": {} });
+        Policy::default().apply(&mut body, None, proxy).unwrap();
+        assert_eq!(
+            body["capabilities"]["alwaysMatch"]["proxy"]["httpProxy"],
+            "127.0.0.1:4000"
+        );
+
+        let request =
+            json!({ "capabilities": { "firstMatch": [{ "proxy": { "proxyType": "system" } }] } });
+        let mut body = request.clone();
+        Policy::default().apply(&mut body, None, proxy).unwrap();
+        assert!(body["capabilities"]["alwaysMatch"].get("proxy").is_none());
+    }
+
+    #[test]
+    fn leaves_appium_sessions_headed() {
+        let request = json!({ "capabilities": { "alwaysMatch": {
+            "platformName": "Android",
+            "appium:automationName": "UiAutomator2",
+            "goog:chromeOptions": { "args": ["--no-sandbox", "--lang=de"] }
+        }}});
+        let stripped = json!({ "capabilities": { "alwaysMatch": {
+            "platformName": "Android",
+            "appium:automationName": "UiAutomator2",
+            "goog:chromeOptions": { "args": ["--lang=de"] }
+        }}});
+        let policy = |appium| Policy {
+            headless: true,
+            appium,
+            ..Policy::default()
+        };
+
+        let mut body = request.clone();
+        policy(true).apply(&mut body, None, None).unwrap();
+        assert_eq!(body, stripped);
+
+        // Only an Appium server's sessions are, not any requesting an appium: capability
+        let mut body = request.clone();
+        policy(false).apply(&mut body, None, None).unwrap();
+        assert_eq!(
+            body["capabilities"]["alwaysMatch"]["goog:chromeOptions"]["args"],
+            json!(["--lang=de", "--headless=new"])
+        );
+    }
+}
@@ -0,0 +1,147 @@
+use std::collections::HashSet;
+use std::net::{IpAddr, TcpListener};
+use std::num::ParseIntError;
+use std::ops::RangeInclusive;
+use std::sync::{Arc, Mutex};
+
+/// Ports local WebDrivers are started on, see `--port-range`
+///
+/// Ports are handed out in turn, skipping those in use by a session or by anything else, and released once the
+/// [`Lease`] is dropped along with the session (or the WebDriver of a rejected one).
+pub struct Ports {
+    range: RangeInclusive<u16>,
+    state: Mutex<State>,
+}
+
+struct State {
+    next: u16,
+    leased: HashSet<u16>,
+}
+
+/// A port reserved for a WebDriver until dropped
+pub struct Lease {
+    ports: Arc<Ports>,
+    port: u16,
+}
+
+impl Lease {
+    pub fn port(&self) -> u16 {
+        self.port
+    }
+}
+
+impl Drop for Lease {
+    fn drop(&mut self) {
+        self.ports.lock().leased.remove(&self.port);
+    }
+}
+
+impl Ports {
+    pub fn new(range: RangeInclusive<u16>) -> Self {
+        Self {
+            state: Mutex::new(State {
+                next: *range.start(),
+                leased: HashSet::new(),
+            }),
+            range,
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, State> {
+        self.state.lock().expect("Ports lock poisoned")
+    }
+
+    /// Reserves the next port of the range nothing listens on at `host`, trying each port at most once
+    ///
+    /// This blocks while ports are bound to check whether they are free, see [`tokio::task::spawn_blocking`].
+    pub fn lease(self: &Arc<Self>, host: IpAddr) -> Option<Lease> {
+        for _ in self.range.clone() {
+            let lease = self.next()?;
+            // Checked without holding the lock, the port being leased meanwhile (and released again if in use)
+            if TcpListener::bind((host, lease.port)).is_ok() {
+                return Some(lease);
+            }
+        }
+
+        None
+    }
+
+    /// Leases the next port of the range not leased yet
+    fn next(self: &Arc<Self>) -> Option<Lease> {
+        let mut state = self.lock();
+        let (start, end) = (*self.range.start(), *self.range.end());
+        for _ in self.range.clone() {
+            let port = state.next;
+            state.next = if port >= end { start } else { port + 1 };
+            if state.leased.insert(port) {
+                return Some(Lease {
+                    ports: self.clone(),
+                    port,
+                });
+            }
+        }
+
+        None
+    }
+
+    /// Marks a port in use by an adopted session, if it is within the range
+    pub fn reserve(self: &Arc<Self>, port: u16) -> Option<Lease> {
+        if !self.range.contains(&port) || !self.lock().leased.insert(port) {
+            return None;
+        }
+        Some(Lease {
+            ports: self.clone(),
+            port,
+        })
+    }
+}
+
+pub fn parse_range(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| String::from("Expected a range such as 4445-5444"))?;
+    let start: u16 = start
+        .trim()
+        .parse()
+        .map_err(|e: ParseIntError| e.to_string())?;
+    let end: u16 = end
+        .trim()
+        .parse()
+        .map_err(|e: ParseIntError| e.to_string())?;
+    if start > end {
+        return Err(String::from("The range is empty"));
+    }
+    Ok(start..=end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn leases_ports_within_range() {
+        let host = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        // Two adjacent ports, the second of which is released again to be leased
+        let (blocker, taken) = loop {
+            let blocker = TcpListener::bind((host, 0)).unwrap();
+            let taken = blocker.local_addr().unwrap().port();
+            if let Some(next) = taken.checked_add(1)
+                && TcpListener::bind((host, next)).is_ok()
+            {
+                break (blocker, taken);
+            }
+        };
+        let ports = Arc::new(Ports::new(taken..=taken + 1));
+
+        // The first port is in use by something else
+        let lease = ports.lease(host).unwrap();
+        assert_eq!(lease.port(), taken + 1);
+        assert!(ports.lease(host).is_none());
+
+        drop(lease);
+        assert_eq!(ports.lease(host).unwrap().port(), taken + 1);
+        drop(blocker);
+        assert!(parse_range("5444-4445").is_err());
+    }
+}
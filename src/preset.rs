@@ -0,0 +1,159 @@
+use crate::{Error, Manager, Pool};
+use deadpool::managed::{Object, PoolError};
+use fantoccini::Client;
+use log::warn;
+use serde_json::json;
+use std::ops::Deref;
+use tokio::runtime::Handle;
+
+/// Device emulation applied to a session for as long as it is handed out, see [`get_with_preset`]
+///
+/// The user agent and locale are overridden through the Chrome DevTools Protocol, which only Chromium-based browsers
+/// (i.e. chromedriver) support.
+#[derive(Debug, Clone, Default)]
+pub struct SessionPreset {
+    pub user_agent: Option<String>,
+    /// Width and height of the window in pixels
+    pub window_size: Option<(u32, u32)>,
+    /// e.g. `de-DE`
+    pub locale: Option<String>,
+}
+
+/// A pooled session the preset is applied to, which is reverted before the session returns to its pool
+pub struct PresetSession {
+    object: Option<Object<Manager>>,
+    preset: SessionPreset,
+    /// What is reverted to
+    window_size: Option<(u64, u64)>,
+    user_agent: Option<String>,
+}
+
+impl Deref for PresetSession {
+    type Target = Object<Manager>;
+
+    fn deref(&self) -> &Self::Target {
+        self.object.as_ref().expect("Session taken before drop")
+    }
+}
+
+impl Drop for PresetSession {
+    fn drop(&mut self) {
+        let Some(object) = self.object.take() else {
+            return;
+        };
+        let (preset, window_size, user_agent) = (
+            self.preset.clone(),
+            self.window_size,
+            self.user_agent.take(),
+        );
+        let Ok(runtime) = Handle::try_current() else {
+            warn!("Unable to revert session preset (Dropped outside of a runtime)");
+            // Rather than handing out a session emulating another device, left to expire as it cannot be closed either
+            let _ = Object::take(object);
+            return;
+        };
+        // Returned to the pool once dropped in turn
+        runtime.spawn(async move {
+            if let Err(e) = revert(&object, &preset, window_size, user_agent).await {
+                warn!("Unable to revert session preset: {}", e);
+                // Rather than handing out a session emulating another device
+                if let Err(e) = Object::take(object).close().await {
+                    warn!(
+                        "Unable to close session whose preset was not reverted: {}",
+                        e
+                    );
+                }
+            }
+        });
+    }
+}
+
+/// Takes a session from `pool` with `preset` applied
+pub async fn get_with_preset(
+    pool: &Pool,
+    preset: SessionPreset,
+) -> Result<PresetSession, PoolError<Error>> {
+    let object = pool.get().await?;
+    let window_size = match preset.window_size {
+        Some(_) => Some(object.get_window_size().await.map_err(Error::from)?),
+        None => None,
+    };
+    let user_agent = match preset.user_agent {
+        Some(_) => object
+            .execute("return navigator.userAgent", Vec::new())
+            .await
+            .map_err(Error::from)?
+            .as_str()
+            .map(String::from),
+        None => None,
+    };
+    let session = PresetSession {
+        object: Some(object),
+        preset,
+        window_size,
+        user_agent,
+    };
+
+    // Reverted by dropping the session if any of these fail
+    if let Some((width, height)) = session.preset.window_size {
+        session
+            .set_window_size(width, height)
+            .await
+            .map_err(Error::from)?;
+    }
+    if let Some(user_agent) = &session.preset.user_agent {
+        let params = json!({ "userAgent": user_agent });
+        cdp(&session, "Network.setUserAgentOverride", params).await?;
+    }
+    if let Some(locale) = &session.preset.locale {
+        let params = json!({ "locale": locale });
+        cdp(&session, "Emulation.setLocaleOverride", params).await?;
+    }
+
+    Ok(session)
+}
+
+async fn revert(
+    object: &Object<Manager>,
+    preset: &SessionPreset,
+    window_size: Option<(u64, u64)>,
+    user_agent: Option<String>,
+) -> Result<(), Error> {
+    if let Some((width, height)) = window_size {
+        object.set_window_size(width as u32, height as u32).await?;
+    }
+    if preset.user_agent.is_some()
+        && let Some(user_agent) = user_agent
+    {
+        let params = json!({ "userAgent": user_agent });
+        cdp(object, "Network.setUserAgentOverride", params).await?;
+    }
+    if preset.locale.is_some() {
+        // Without a locale, the override is cleared
+        cdp(object, "Emulation.setLocaleOverride", json!({})).await?;
+    }
+    Ok(())
+}
+
+/// Runs a Chrome DevTools Protocol command through chromedriver's vendor endpoint
+async fn cdp(
+    object: &Object<Manager>,
+    command: &str,
+    params: serde_json::Value,
+) -> Result<(), Error> {
+    let pool = Object::pool(object).ok_or(Error::Stateless)?;
+    let client: &Client = object;
+    let session = client.session_id().await?.ok_or(Error::Stateless)?;
+    pool.manager()
+        .http
+        .post(format!(
+            "{}/session/{}/goog/cdp/execute",
+            pool.manager().webdriver().await?,
+            session
+        ))
+        .json(&json!({ "cmd": command, "params": params }))
+        .send()
+        .await?
+        .error_for_status()?;
+    Ok(())
+}
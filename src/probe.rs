@@ -0,0 +1,40 @@
+use log::debug;
+use reqwest::Client;
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::TcpStream;
+use tokio::time::sleep;
+
+/// How a freshly started WebDriver is found to be ready for a session
+pub struct Probe {
+    /// Requested until it answers successfully, or `None` to only wait for the port to accept connections
+    pub path: Option<String>,
+    pub interval: Duration,
+    pub attempts: u32,
+}
+
+impl Probe {
+    /// Polls the WebDriver at `address` (`upstream`) until it is ready, false once all attempts failed
+    pub async fn wait(&self, http: &Client, upstream: &str, address: SocketAddr) -> bool {
+        for attempt in 1..=self.attempts {
+            let ready = match &self.path {
+                Some(path) => http
+                    .get(format!("{}{}", upstream, path))
+                    .send()
+                    .await
+                    .is_ok_and(|response| response.status().is_success()),
+                None => TcpStream::connect(address).await.is_ok(),
+            };
+            if ready {
+                return true;
+            }
+            debug!(
+                "WebDriver at {} is not ready (Attempt {})",
+                address, attempt
+            );
+            sleep(self.interval).await;
+        }
+
+        false
+    }
+}
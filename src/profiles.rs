@@ -0,0 +1,211 @@
+use crate::{Config, Error, Manager, Pool};
+use async_lock::{Mutex, Semaphore, SemaphoreGuardArc};
+use deadpool::managed::{BuildError, Object, PoolError};
+use fantoccini::Client;
+use log::{debug, warn};
+use std::collections::BTreeMap;
+use std::ops::Deref;
+use std::sync::Arc;
+
+/// Pools of sessions of the capability sets registered with [`Config::profile`], holding at most `max_size` sessions
+/// together
+///
+/// Each profile has a pool of its own, whose sessions are created with its capabilities and otherwise the same
+/// configuration (a local driver being started per profile). Idle sessions of other profiles are closed to make room
+/// for those of a profile asked for.
+pub struct ProfilePool {
+    pools: BTreeMap<String, Pool>,
+    max_size: usize,
+    /// Sessions handed out of any of the pools
+    handed_out: Arc<Semaphore>,
+    /// Held while making room for a session
+    room: Mutex<()>,
+}
+
+/// A session of a profile, counting towards the shared limit until dropped, see [`ProfilePool::get_for`]
+pub struct ProfileSession {
+    object: Object<Manager>,
+    _permit: SemaphoreGuardArc,
+}
+
+impl Deref for ProfileSession {
+    type Target = Object<Manager>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.object
+    }
+}
+
+impl ProfileSession {
+    /// Takes the session out of its profile's pool for good, no longer counting towards the shared limit, see
+    /// [`crate::detach`]
+    pub fn detach(self) -> Client {
+        crate::detach(self.object)
+    }
+}
+
+impl ProfilePool {
+    pub fn new(config: Config, max_size: usize) -> Result<Self, BuildError> {
+        let pools = config
+            .profiles
+            .iter()
+            .map(|(name, capabilities)| {
+                let mut profile = config.clone();
+                profile.capabilities = Some(capabilities.clone());
+                profile.profiles.clear();
+                let pool = Pool::builder(Manager::new(profile))
+                    .max_size(max_size)
+                    .build()?;
+                Ok((name.clone(), pool))
+            })
+            .collect::<Result<_, BuildError>>()?;
+
+        Ok(Self {
+            pools,
+            max_size,
+            handed_out: Arc::new(Semaphore::new(max_size)),
+            room: Mutex::new(()),
+        })
+    }
+
+    /// The pool of a profile, e.g. to look at its status
+    pub fn profile(&self, name: &str) -> Option<&Pool> {
+        self.pools.get(name)
+    }
+
+    /// Takes a session of the profile `name` from its pool, waiting while `max_size` sessions are handed out
+    pub async fn get_for(&self, name: &str) -> Result<ProfileSession, PoolError<Error>> {
+        let pool = self
+            .pools
+            .get(name)
+            .ok_or_else(|| PoolError::Backend(Error::UnknownProfile(name.to_owned())))?;
+        let permit = self.handed_out.acquire_arc().await;
+
+        {
+            let _room = self.room.lock().await;
+            let size: usize = self.pools.values().map(|pool| pool.status().size).sum();
+            if pool.status().available == 0 && size >= self.max_size {
+                self.evict(name);
+            }
+        }
+
+        let object = pool.get().await?;
+        Ok(ProfileSession {
+            object,
+            _permit: permit,
+        })
+    }
+
+    /// Closes an idle session of a profile other than `name`
+    fn evict(&self, name: &str) {
+        for (profile, pool) in self.pools.iter().filter(|(profile, _)| *profile != name) {
+            // Only the first idle session is removed
+            let mut evicted = false;
+            let retained = pool.retain(|_, _| std::mem::replace(&mut evicted, true));
+            let Some(client) = retained.removed.into_iter().next() else {
+                continue;
+            };
+            debug!(
+                "Closing idle session of {:?} to make room for {:?}",
+                profile, name
+            );
+            tokio::spawn(async move {
+                if let Err(e) = client.close().await {
+                    warn!("Unable to close idle session: {}", e);
+                }
+            });
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::Json;
+    use axum::Router;
+    use axum::body::Bytes;
+    use axum::routing::{delete, post};
+    use fantoccini::wd::Capabilities;
+    use serde_json::{Value, json};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use tokio::net::TcpListener;
+
+    /// Answers new sessions with an ID naming the browser they requested
+    async fn webdriver() -> String {
+        let created = Arc::new(AtomicUsize::new(0));
+        let app = Router::new()
+            .route(
+                "/session",
+                // Not every client sends a Content-Type
+                post(|body: Bytes| async move {
+                    let requested: Value = serde_json::from_slice(&body).unwrap_or_default();
+                    let browser = requested
+                        .pointer("/capabilities/alwaysMatch/browserName")
+                        .or_else(|| requested.pointer("/desiredCapabilities/browserName"))
+                        .and_then(Value::as_str)
+                        .unwrap_or_default()
+                        .to_owned();
+                    let id = format!("{}-{}", browser, created.fetch_add(1, Ordering::Relaxed));
+                    Json(json!({ "value": { "sessionId": id, "capabilities": {} } }))
+                }),
+            )
+            .route(
+                "/session/{id}",
+                delete(|| async { Json(json!({ "value": null })) }),
+            );
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        format!("http://{}", address)
+    }
+
+    fn browser(name: &str) -> Capabilities {
+        let mut capabilities = Capabilities::new();
+        capabilities.insert(String::from("browserName"), json!(name));
+        capabilities
+    }
+
+    #[tokio::test]
+    async fn hands_out_sessions_of_the_profile_asked_for() {
+        let mut config = Config::new(webdriver().await, None);
+        config.profile("desktop-firefox", browser("firefox"));
+        config.profile("mobile-chrome", browser("chrome"));
+        let pool = ProfilePool::new(config, 2).unwrap();
+
+        let firefox = pool.get_for("desktop-firefox").await.unwrap();
+        let chrome = pool.get_for("mobile-chrome").await.unwrap();
+        assert!(
+            firefox
+                .session_id()
+                .await
+                .unwrap()
+                .unwrap()
+                .starts_with("firefox-")
+        );
+        assert!(
+            chrome
+                .session_id()
+                .await
+                .unwrap()
+                .unwrap()
+                .starts_with("chrome-")
+        );
+        for name in ["desktop-firefox", "mobile-chrome"] {
+            assert_eq!(pool.profile(name).unwrap().status().size, 1);
+        }
+    }
+
+    #[tokio::test]
+    async fn rejects_unknown_profiles() {
+        let mut config = Config::new("http://127.0.0.1:4444", None);
+        config.profile("desktop-chrome", Capabilities::new());
+        let pool = ProfilePool::new(config, 2).unwrap();
+
+        assert!(pool.profile("desktop-chrome").is_some());
+        assert!(matches!(
+            pool.get_for("mobile-firefox").await,
+            Err(PoolError::Backend(Error::UnknownProfile(_)))
+        ));
+    }
+}
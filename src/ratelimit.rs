@@ -0,0 +1,164 @@
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+/// Number of tracked clients above which idle buckets are forgotten
+const TRACKED_CLIENTS: usize = 4096;
+
+/// Token buckets limiting how fast sessions are created, globally and per client
+pub struct RateLimit {
+    global: Option<Limit>,
+    per_client: Option<Limit>,
+    burst: f64,
+    global_bucket: Mutex<Bucket>,
+    client_buckets: Mutex<HashMap<IpAddr, Bucket>>,
+}
+
+#[derive(Clone, Copy)]
+struct Limit {
+    /// Tokens regained per second
+    rate: f64,
+}
+
+#[derive(Clone, Copy)]
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn full(burst: f64, now: Instant) -> Self {
+        Self {
+            tokens: burst,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, limit: Limit, burst: f64, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * limit.rate).min(burst);
+        self.updated = now;
+    }
+
+    /// Time until a token is available, which is never at a rate of 0
+    fn wait(&self, limit: Limit) -> Duration {
+        Duration::try_from_secs_f64(((1.0 - self.tokens) / limit.rate).max(0.0))
+            .unwrap_or(Duration::MAX)
+    }
+}
+
+impl RateLimit {
+    /// Limits are given in sessions per minute, `burst` sessions may be created at once
+    pub fn new(global: Option<u32>, per_client: Option<u32>, burst: u32) -> Self {
+        let limit = |per_minute: u32| Limit {
+            rate: per_minute as f64 / 60.0,
+        };
+        let burst = burst.max(1) as f64;
+        let now = Instant::now();
+
+        Self {
+            global: global.map(limit),
+            per_client: per_client.map(limit),
+            burst,
+            global_bucket: Mutex::new(Bucket::full(burst, now)),
+            client_buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Takes a token for `client`, or returns the time after which a retry could succeed
+    pub fn check(&self, client: Option<IpAddr>) -> Result<(), Duration> {
+        self.check_at(client, Instant::now())
+    }
+
+    fn check_at(&self, client: Option<IpAddr>, now: Instant) -> Result<(), Duration> {
+        let mut clients = lock(&self.client_buckets);
+        let mut global = lock(&self.global_bucket);
+
+        if clients.len() > TRACKED_CLIENTS {
+            let (burst, per_client) = (self.burst, self.per_client);
+            clients.retain(|_, bucket| {
+                per_client.is_some_and(|limit| {
+                    bucket.refill(limit, burst, now);
+                    bucket.tokens < burst
+                })
+            });
+        }
+
+        let mut wait = Duration::ZERO;
+        let client = match (self.per_client, client) {
+            (Some(limit), Some(client)) => {
+                let bucket = clients
+                    .entry(client)
+                    .or_insert_with(|| Bucket::full(self.burst, now));
+                bucket.refill(limit, self.burst, now);
+                wait = wait.max(bucket.wait(limit));
+                Some(bucket)
+            }
+            _ => None,
+        };
+        if let Some(limit) = self.global {
+            global.refill(limit, self.burst, now);
+            wait = wait.max(global.wait(limit));
+        }
+
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+        if let Some(bucket) = client {
+            bucket.tokens -= 1.0;
+        }
+        if self.global.is_some() {
+            global.tokens -= 1.0;
+        }
+
+        Ok(())
+    }
+}
+
+/// Buckets are kept consistent at any point, so they remain usable even if a panic occurred while they were locked
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn limits_clients_independently() {
+        let limit = RateLimit::new(None, Some(60), 2);
+        let (a, b) = ("10.0.0.1".parse().ok(), "10.0.0.2".parse().ok());
+        let now = Instant::now();
+
+        assert!(limit.check_at(a, now).is_ok());
+        assert!(limit.check_at(a, now).is_ok());
+        assert_eq!(limit.check_at(a, now), Err(Duration::from_secs(1)));
+        assert!(limit.check_at(b, now).is_ok());
+        assert!(limit.check_at(a, now + Duration::from_secs(1)).is_ok());
+    }
+
+    #[test]
+    fn limits_globally_without_consuming_client_tokens() {
+        let limit = RateLimit::new(Some(30), Some(60), 1);
+        let (a, b) = ("10.0.0.1".parse().ok(), "10.0.0.2".parse().ok());
+        let now = Instant::now();
+
+        assert!(limit.check_at(a, now).is_ok());
+        assert_eq!(limit.check_at(b, now), Err(Duration::from_secs(2)));
+        assert!(limit.check_at(b, now + Duration::from_secs(2)).is_ok());
+    }
+
+    #[test]
+    fn rejects_sessions_for_good_at_a_rate_of_zero() {
+        let limit = RateLimit::new(Some(0), None, 1);
+        let now = Instant::now();
+
+        assert!(limit.check_at(None, now).is_ok());
+        assert_eq!(limit.check_at(None, now), Err(Duration::MAX));
+        assert_eq!(
+            limit.check_at(None, now + Duration::from_secs(3600)),
+            Err(Duration::MAX)
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use crate::artifacts::Artifact;
+use crate::tenant::Tenant;
+use crate::{AppState, Browsers, WebDriverMeta};
+use axum::Router;
+use axum::extract::{Extension, Path, State};
+use axum::http::StatusCode;
+use axum::response::Response;
+use axum::routing::get;
+use log::{debug, info, warn};
+use std::path::PathBuf;
+use std::process::Stdio;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::process::{Child, Command};
+use tokio::time::timeout;
+use uuid::Uuid;
+
+const RECORDING: Artifact = Artifact {
+    name: "recording",
+    extension: "mp4",
+    content_type: "video/mp4",
+};
+
+/// Time ffmpeg is given to finalise a recording before it is killed
+const FINALISE_TIMEOUT: Duration = Duration::from_secs(15);
+
+/// Records the X display browsers are rendered to using ffmpeg
+pub struct Recorder {
+    pub directory: PathBuf,
+    pub ffmpeg: PathBuf,
+    pub display: String,
+}
+
+impl Recorder {
+    pub fn start(&self, session: Uuid, tenant: Option<&Tenant>) -> std::io::Result<Recording> {
+        let path = RECORDING.path(&self.directory, tenant, session);
+        if let Some(directory) = path.parent() {
+            std::fs::create_dir_all(directory)?;
+        }
+
+        let mut command = Command::new(&self.ffmpeg);
+        command
+            .args(["-y", "-loglevel", "error"])
+            .args(["-f", "x11grab", "-framerate", "15", "-i"])
+            .arg(&self.display)
+            .args(["-c:v", "libx264", "-preset", "ultrafast"])
+            .args(["-pix_fmt", "yuv420p"])
+            .arg(&path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .kill_on_drop(true);
+        debug!("Starting recording using {:?}", command);
+
+        let process = command.spawn()?;
+        info!("Recording {:?} to {:?}", session, path);
+
+        Ok(Recording { process, path })
+    }
+}
+
+pub struct Recording {
+    process: Child,
+    path: PathBuf,
+}
+
+impl Recording {
+    /// Asks ffmpeg to stop and waits for the file to be written
+    pub async fn finish(mut self) {
+        if let Some(mut stdin) = self.process.stdin.take() {
+            let _ = stdin.write_all(b"q").await;
+        }
+
+        match timeout(FINALISE_TIMEOUT, self.process.wait()).await {
+            Ok(Ok(status)) if status.success() => info!("Finished recording {:?}", self.path),
+            Ok(Ok(status)) => warn!("Recording {:?} ended with {}", self.path, status),
+            Ok(Err(e)) => warn!("Unable to finish recording {:?}: {}", self.path, e),
+            Err(_) => {
+                warn!("Recording {:?} did not finish in time", self.path);
+                let _ = self.process.kill().await;
+            }
+        }
+    }
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route(
+        "/sessiondriver/recordings/{id}",
+        get(download).delete(remove),
+    )
+}
+
+async fn download(
+    State(browsers): State<Browsers>,
+    State(webdriver_meta): State<Arc<WebDriverMeta>>,
+    tenant: Option<Extension<Arc<Tenant>>>,
+    Path(id): Path<Uuid>,
+) -> Result<Response, Response> {
+    let tenant = tenant.as_ref().map(|Extension(tenant)| tenant.as_ref());
+    let directory = webdriver_meta
+        .recorder
+        .as_ref()
+        .map(|r| r.directory.as_path());
+    RECORDING.download(&browsers, directory, tenant, id).await
+}
+
+async fn remove(
+    State(browsers): State<Browsers>,
+    State(webdriver_meta): State<Arc<WebDriverMeta>>,
+    tenant: Option<Extension<Arc<Tenant>>>,
+    Path(id): Path<Uuid>,
+) -> Result<StatusCode, Response> {
+    let tenant = tenant.as_ref().map(|Extension(tenant)| tenant.as_ref());
+    let directory = webdriver_meta
+        .recorder
+        .as_ref()
+        .map(|r| r.directory.as_path());
+    RECORDING.remove(&browsers, directory, tenant, id).await
+}
@@ -0,0 +1,165 @@
+use crate::{AppState, Browser, SpawnedDriver, new_session, spawn_driver, stop};
+use async_lock::Mutex;
+use axum::extract::Request;
+use axum::http::{HeaderName, Uri};
+use log::info;
+use std::mem;
+use std::time::Duration;
+use uuid::Uuid;
+
+/// Answers requests of recovered sessions with the number of times their WebDriver has been replaced
+pub const RECOVERED: HeaderName = HeaderName::from_static("x-sessiondriver-recovered");
+
+/// Replaces the exited WebDriver of `uuid` by a new one, on which a session is created with the original request
+///
+/// The session keeps its ID, while requests are passed on to the new WebDriver's session (see [`redirect`]).
+pub async fn recover(state: &AppState, uuid: Uuid) -> Result<(), String> {
+    let requested = match state.browsers.shard(&uuid).read().await.get(&uuid) {
+        Some(browser) if !browser.requested.is_null() => browser.requested.clone(),
+        Some(_) => return Err(String::from("Not created by this instance")),
+        None => return Err(String::from("Session has ended")),
+    };
+
+    let http = state.webdriver.client().map_err(|e| e.to_string())?;
+    let SpawnedDriver {
+        driver,
+        address,
+        upstream,
+        output,
+    } = spawn_driver(&http, &state.webdriver, &state.metrics)
+        .await
+        .map_err(|response| format!("WebDriver did not start ({})", response.status()))?;
+
+    let created = async {
+        let response = http
+            .post(format!("{}/session", upstream))
+            .json(&requested)
+            .send()
+            .await
+            .map_err(|e| e.to_string())?;
+        if !response.status().is_success() {
+            return Err(format!("WebDriver answered {}", response.status()));
+        }
+        let mut body: serde_json::Value = response.json().await.map_err(|e| e.to_string())?;
+        Ok(new_session(&mut body).1)
+    };
+    let driver_session = match created.await {
+        Ok(driver_session) => driver_session,
+        Err(e) => {
+            driver.discard(state.webdriver.stop_grace).await;
+            return Err(e);
+        }
+    };
+    output.assign(uuid);
+
+    let (exited, abandoned) = {
+        let mut shard = state.browsers.shard(&uuid).write().await;
+        let Some(browser) = shard.get_mut(&uuid) else {
+            drop(shard);
+            driver.discard(state.webdriver.stop_grace).await;
+            return Err(String::from("Session has ended"));
+        };
+        let browser = Browser::exclusive(browser).await;
+        let (process, sandbox, port) = driver.claim();
+        browser.address = address;
+        browser.upstream = upstream;
+        browser.output = output;
+        browser.http = http;
+        browser.driver_session = driver_session.clone();
+        browser.recoveries += 1;
+        // The exited WebDriver's port is released right away, being skipped while still in use
+        browser.port = port;
+        (
+            mem::replace(&mut browser.process, process.map(Mutex::new)),
+            mem::replace(&mut browser.sandbox, sandbox),
+        )
+    };
+    info!(
+        "Recovered {:?} at {} (Session {:?})",
+        uuid, address, driver_session
+    );
+
+    // Whatever the exited WebDriver left behind, e.g. its browser, by the process group recorded when it was spawned
+    if let Some(exited) = exited {
+        stop::stop_driver(&mut exited.into_inner(), Duration::ZERO).await;
+    }
+    if let Some(abandoned) = abandoned {
+        abandoned.remove(Duration::ZERO).await;
+    }
+
+    Ok(())
+}
+
+/// Points a request to `/session/{from}/...` at `/session/{to}/...`
+pub fn redirect(request: &mut Request, from: Uuid, to: &str) {
+    let Some(path_and_query) = request.uri().path_and_query() else {
+        return;
+    };
+    let redirected = path_and_query.as_str().replacen(
+        &format!("/session/{}", from),
+        &format!("/session/{}", to),
+        1,
+    );
+    if let Ok(uri) = redirected.parse::<Uri>() {
+        *request.uri_mut() = uri;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::Body;
+    #[cfg(target_os = "linux")]
+    use tokio::io::AsyncReadExt;
+    #[cfg(target_os = "linux")]
+    use tokio::process::Command;
+
+    #[test]
+    fn redirects_to_driver_session() {
+        let (from, to) = (Uuid::new_v4(), Uuid::new_v4());
+        let mut request = Request::builder()
+            .uri(format!("/session/{}/element?using=css", from))
+            .body(Body::empty())
+            .unwrap();
+
+        redirect(&mut request, from, &to.to_string());
+        assert_eq!(
+            request.uri().to_string(),
+            format!("/session/{}/element?using=css", to)
+        );
+    }
+
+    #[cfg(target_os = "linux")]
+    #[tokio::test]
+    async fn stops_what_exited_drivers_left_behind() {
+        let mut command = Command::new("sh");
+        command
+            .args(["-c", "sleep 60 >/dev/null & echo $!"])
+            .stdout(std::process::Stdio::piped())
+            .process_group(0);
+        let mut exited = stop::Driver::from(command.spawn().unwrap());
+        let mut stdout = String::new();
+        exited
+            .process
+            .stdout
+            .take()
+            .unwrap()
+            .read_to_string(&mut stdout)
+            .await
+            .unwrap();
+        exited.process.wait().await.unwrap();
+        let browser = format!("/proc/{}/stat", stdout.trim());
+        // Zombies are left to whichever process adopted the orphan
+        let running = || std::fs::read_to_string(&browser).is_ok_and(|stat| !stat.contains(") Z "));
+        assert!(running());
+
+        stop::stop_driver(&mut exited, Duration::ZERO).await;
+        for _ in 0..50 {
+            if !running() {
+                return;
+            }
+            tokio::time::sleep(Duration::from_millis(20)).await;
+        }
+        panic!("{} outlived its WebDriver", stdout.trim());
+    }
+}
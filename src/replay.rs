@@ -0,0 +1,214 @@
+use crate::cassette::{self, Interaction};
+use crate::{set_session_id, unknown_command, unknown_session, w3c};
+use async_lock::{Mutex, RwLock};
+use axum::Router;
+use axum::body::to_bytes;
+use axum::extract::{Request, State};
+use axum::http::{Method, StatusCode};
+use axum::response::{IntoResponse, Json, Response};
+use log::{debug, info, warn};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use uuid::Uuid;
+
+/// Serves sessions from cassettes recorded with `--cassette-dir` instead of spawning WebDrivers (see `--replay-dir`)
+pub struct Replay {
+    directory: PathBuf,
+    sessions: RwLock<HashMap<Uuid, Mutex<Tape>>>,
+}
+
+/// A cassette being replayed, each interaction being answered once
+struct Tape {
+    name: String,
+    interactions: Vec<Interaction>,
+    replayed: Vec<bool>,
+}
+
+impl Tape {
+    /// The first interaction not replayed yet of the same method and path
+    fn next(&mut self, method: &Method, path: &str) -> Option<&Interaction> {
+        let index =
+            self.interactions
+                .iter()
+                .zip(&self.replayed)
+                .position(|(interaction, replayed)| {
+                    !replayed && interaction.method == method.as_str() && interaction.path == path
+                })?;
+        self.replayed[index] = true;
+        Some(&self.interactions[index])
+    }
+}
+
+impl Replay {
+    pub fn new(directory: PathBuf) -> Self {
+        Self {
+            directory,
+            sessions: RwLock::new(HashMap::new()),
+        }
+    }
+}
+
+/// Routes of a replaying instance, taking the place of [`crate::proxy`]
+pub fn router() -> Router<Arc<Replay>> {
+    Router::new().fallback(replay)
+}
+
+async fn replay(State(replay): State<Arc<Replay>>, request: Request) -> Result<Response, Response> {
+    let method = request.method().clone();
+    let path = request.uri().path().trim_end_matches('/').to_owned();
+
+    if (method == Method::GET || method == Method::HEAD) && path == "/status" {
+        let body = serde_json::json!({ "value": { "ready": true, "message": "Replaying" } });
+        return Ok(Json(body).into_response());
+    }
+
+    if method == Method::POST && path == "/session" {
+        let body = to_bytes(request.into_body(), usize::MAX)
+            .await
+            .map_err(|e| {
+                w3c::error(
+                    StatusCode::BAD_REQUEST,
+                    w3c::INVALID_ARGUMENT,
+                    e.to_string(),
+                )
+            })?;
+        let requested: Value = serde_json::from_slice(&body).unwrap_or_default();
+        let name = match cassette::requested(&requested) {
+            Ok(Some(name)) => name,
+            Ok(None) => {
+                return Err(not_created(format!(
+                    "Sessions are replayed, but {} is missing",
+                    cassette::CAPABILITY
+                )));
+            }
+            Err(message) => return Err(not_created(message)),
+        };
+        let interactions = cassette::read(&replay.directory, &name)
+            .await
+            .map_err(|e| not_created(format!("Unable to read cassette {:?}: {}", name, e)))?;
+
+        let mut tape = Tape {
+            replayed: vec![false; interactions.len()],
+            interactions,
+            name,
+        };
+        let Some(created) = tape.next(&method, &path) else {
+            return Err(not_created(format!(
+                "Cassette {:?} does not begin with a new session",
+                tape.name
+            )));
+        };
+        let status = created.status;
+        let mut response = created.response.clone();
+        let session_id = Uuid::new_v4();
+        set_session_id(&mut response, session_id);
+        info!("Replaying {:?} as {:?}", tape.name, session_id);
+        replay
+            .sessions
+            .write()
+            .await
+            .insert(session_id, Mutex::new(tape));
+
+        return Ok(answer(status, response));
+    }
+
+    let Some(mut uuid) = path.strip_prefix("/session/") else {
+        return Err(unknown_command(&method, &path));
+    };
+    let command = uuid.find('/').map_or("", |i| &uuid[i..]).to_owned();
+    if let Some(i) = uuid.find('/') {
+        uuid = &uuid[..i];
+    }
+    let Ok(uuid) = uuid.parse::<Uuid>() else {
+        return Err(w3c::error(
+            StatusCode::NOT_FOUND,
+            w3c::INVALID_SESSION_ID,
+            format!("No active session with ID {}", uuid),
+        ));
+    };
+
+    let answered = {
+        let sessions = replay.sessions.read().await;
+        let Some(tape) = sessions.get(&uuid) else {
+            debug!("{:?} not found", uuid);
+            return Err(unknown_session(uuid));
+        };
+        let mut tape = tape.lock().await;
+        match tape.next(&method, &command) {
+            Some(interaction) => Ok(answer(interaction.status, interaction.response.clone())),
+            // Sessions left to expire were recorded without
+            None if method == Method::DELETE && command.is_empty() => {
+                Ok(answer(200, serde_json::json!({ "value": null })))
+            }
+            None => {
+                warn!(
+                    "Cassette {:?} holds no further answer to {} {}",
+                    tape.name, method, command
+                );
+                Err(w3c::error(
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    w3c::UNKNOWN_ERROR,
+                    format!(
+                        "Cassette {:?} holds no further answer to {} {}",
+                        tape.name, method, command
+                    ),
+                ))
+            }
+        }
+    };
+
+    if method == Method::DELETE && command.is_empty() {
+        replay.sessions.write().await.remove(&uuid);
+        info!("Removed {:?}", uuid);
+    }
+
+    answered
+}
+
+fn answer(status: u16, body: Value) -> Response {
+    let status = StatusCode::from_u16(status).unwrap_or(StatusCode::OK);
+    (status, Json(body)).into_response()
+}
+
+fn not_created(message: String) -> Response {
+    info!("Rejected session ({})", message);
+    w3c::error(StatusCode::BAD_REQUEST, w3c::SESSION_NOT_CREATED, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn replays_in_recorded_order() {
+        let interaction = |method: &str, path: &str, title: &str| Interaction {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            body: Value::Null,
+            status: 200,
+            response: json!({ "value": title }),
+            fault: None,
+        };
+        let interactions = vec![
+            interaction("GET", "/title", "Login"),
+            interaction("POST", "/url", ""),
+            interaction("GET", "/title", "Dashboard"),
+        ];
+        let mut tape = Tape {
+            name: String::from("login"),
+            replayed: vec![false; interactions.len()],
+            interactions,
+        };
+
+        let mut title = || {
+            tape.next(&Method::GET, "/title")
+                .map(|interaction| interaction.response["value"].clone())
+        };
+        assert_eq!(title(), Some(json!("Login")));
+        assert_eq!(title(), Some(json!("Dashboard")));
+        assert_eq!(title(), None);
+    }
+}
@@ -0,0 +1,116 @@
+use std::net::IpAddr;
+use std::path::Path;
+
+/// WebDrivers whose own access restrictions are known
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Flavor {
+    Chromedriver,
+    Geckodriver,
+}
+
+impl Flavor {
+    /// Recognises a WebDriver by what it reported on `--version`, or else by its file name
+    pub fn detect(path: &Path, version: Option<&str>) -> Option<Self> {
+        let name = path
+            .file_name()
+            .map(|name| name.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let reported = version.map(str::to_lowercase).unwrap_or_default();
+        [reported, name].into_iter().find_map(|identity| {
+            if identity.contains("chromedriver") {
+                Some(Flavor::Chromedriver)
+            } else if identity.contains("geckodriver") {
+                Some(Flavor::Geckodriver)
+            } else {
+                None
+            }
+        })
+    }
+}
+
+/// Restricts spawned WebDrivers to requests passed on by this instance, see `--no-safety-flags`
+pub struct SafetyFlags {
+    /// Origins requests may carry, those allowed by `--cors-origin`
+    pub origins: Vec<String>,
+}
+
+impl SafetyFlags {
+    /// Flags for a WebDriver reached at `host`, except those already among `parameters`
+    pub fn flags(&self, flavor: Flavor, host: IpAddr, parameters: Option<&str>) -> Vec<String> {
+        // Each flag along with its values
+        let mut flags: Vec<Vec<String>> = Vec::new();
+        match flavor {
+            Flavor::Chromedriver => {
+                // Connections to a WebDriver on `host` originate from that address
+                flags.push(vec![format!("--allowed-ips={}", host)]);
+                if !self.origins.is_empty() {
+                    flags.push(vec![format!(
+                        "--allowed-origins={}",
+                        self.origins.join(",")
+                    )]);
+                }
+            }
+            Flavor::Geckodriver => {
+                // Accepts IP addresses in the Host header anyhow, and rejects requests with an Origin by default
+                let origins = self.origins.iter().filter(|origin| *origin != "*");
+                if origins.clone().next().is_some() {
+                    flags.push(
+                        [String::from("--allow-origins")]
+                            .into_iter()
+                            .chain(origins.cloned())
+                            .collect(),
+                    );
+                }
+            }
+        }
+
+        let overridden = |flag: &str| {
+            let name = flag.split('=').next().unwrap_or(flag);
+            parameters.is_some_and(|parameters| {
+                parameters
+                    .split(' ')
+                    .any(|parameter| parameter.split('=').next() == Some(name))
+            })
+        };
+        flags
+            .into_iter()
+            .filter(|flag| !overridden(&flag[0]))
+            .flatten()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::Ipv4Addr;
+
+    #[test]
+    fn restricts_chromedriver() {
+        let flavor = Flavor::detect(
+            Path::new("/usr/bin/driver"),
+            Some("ChromeDriver 120.0.6099.109 (3419140ab665596f21b385ce136419fde0924272)"),
+        );
+        assert_eq!(flavor, Some(Flavor::Chromedriver));
+
+        let safety = SafetyFlags {
+            origins: vec![String::from("https://dashboard.example.com")],
+        };
+        let host = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        assert_eq!(
+            safety.flags(Flavor::Chromedriver, host, None),
+            [
+                "--allowed-ips=127.0.0.1",
+                "--allowed-origins=https://dashboard.example.com"
+            ]
+        );
+        assert_eq!(
+            safety.flags(
+                Flavor::Chromedriver,
+                host,
+                Some("--allowed-ips=10.0.0.1 --verbose")
+            ),
+            ["--allowed-origins=https://dashboard.example.com"]
+        );
+    }
+}
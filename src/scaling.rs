@@ -0,0 +1,158 @@
+use crate::{Browsers, WATCH_INTERVAL};
+use log::{info, warn};
+use reqwest::Client;
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::process::Command;
+use tokio::time::sleep;
+
+/// Time a watermark hook is given to accept an event
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Level {
+    High,
+    Normal,
+    Low,
+}
+
+impl Level {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Level::High => "high",
+            Level::Normal => "normal",
+            Level::Low => "low",
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct Crossing {
+    event: Level,
+    sessions: usize,
+    max_sessions: Option<usize>,
+    /// Milliseconds since the Unix epoch
+    timestamp: u64,
+}
+
+/// Notifies infrastructure (e.g. to add or remove instances) once the number of sessions reaches a watermark
+pub struct Watermarks {
+    /// Sessions at or above which `high` is signalled
+    pub high: Option<usize>,
+    /// Sessions at or below which `low` is signalled
+    pub low: Option<usize>,
+    /// Run through the shell with `SESSIONDRIVER_WATERMARK` and `SESSIONDRIVER_SESSIONS` set
+    pub command: Option<String>,
+    /// Posted a JSON event
+    pub url: Option<String>,
+    pub max_sessions: Option<usize>,
+}
+
+impl Watermarks {
+    pub fn level(&self, sessions: usize) -> Level {
+        if self.high.is_some_and(|high| sessions >= high) {
+            Level::High
+        } else if self.low.is_some_and(|low| sessions <= low) {
+            Level::Low
+        } else {
+            Level::Normal
+        }
+    }
+
+    /// Signals every crossing of a watermark, but not the level sessions are at on start
+    pub fn watch(self, browsers: Browsers, http: Client) {
+        if self.high.is_none() && self.low.is_none() {
+            return;
+        }
+        tokio::spawn(async move {
+            let mut level = self.level(browsers.len().await);
+            loop {
+                sleep(WATCH_INTERVAL).await;
+
+                let sessions = browsers.len().await;
+                let current = self.level(sessions);
+                if current == level {
+                    continue;
+                }
+                level = current;
+                if current != Level::Normal {
+                    self.signal(&http, current, sessions).await;
+                }
+            }
+        });
+    }
+
+    async fn signal(&self, http: &Client, level: Level, sessions: usize) {
+        info!("Reached {:?} watermark ({} sessions)", level, sessions);
+        let event = Crossing {
+            event: level,
+            sessions,
+            max_sessions: self.max_sessions,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis() as u64)
+                .unwrap_or_default(),
+        };
+
+        if let Some(url) = &self.url {
+            let delivered = http
+                .post(url)
+                .timeout(DELIVERY_TIMEOUT)
+                .json(&event)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = delivered {
+                warn!("Unable to deliver {:?} watermark event: {}", level, e);
+            }
+        }
+
+        if let Some(command) = &self.command {
+            #[cfg(unix)]
+            let mut shell = {
+                let mut shell = Command::new("sh");
+                shell.arg("-c");
+                shell
+            };
+            #[cfg(not(unix))]
+            let mut shell = {
+                let mut shell = Command::new("cmd");
+                shell.arg("/C");
+                shell
+            };
+            let status = shell
+                .arg(command)
+                .env("SESSIONDRIVER_WATERMARK", level.as_str())
+                .env("SESSIONDRIVER_SESSIONS", sessions.to_string())
+                .status()
+                .await;
+            match status {
+                Ok(status) if status.success() => {}
+                Ok(status) => warn!("Watermark command exited with {}", status),
+                Err(e) => warn!("Unable to run watermark command: {}", e),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn levels_between_watermarks() {
+        let watermarks = Watermarks {
+            high: Some(8),
+            low: Some(2),
+            command: None,
+            url: None,
+            max_sessions: Some(10),
+        };
+
+        assert_eq!(watermarks.level(0), Level::Low);
+        assert_eq!(watermarks.level(2), Level::Low);
+        assert_eq!(watermarks.level(5), Level::Normal);
+        assert_eq!(watermarks.level(8), Level::High);
+    }
+}
@@ -0,0 +1,85 @@
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use log::{debug, info, warn};
+use reqwest::Client;
+use serde::Deserialize;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Time a browser is given to take a final screenshot
+const CAPTURE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Keeps a final screenshot of sessions which are deleted or expire
+pub struct ScreenshotArchive {
+    pub directory: PathBuf,
+    pub retention: Duration,
+}
+
+impl ScreenshotArchive {
+    /// Takes a screenshot of the session at `session_url` (see [`crate::Browser::session_url`])
+    pub async fn capture(&self, http: &Client, session_url: &str, session: Uuid) {
+        if let Err(e) = self.try_capture(http, session_url, session).await {
+            warn!("Unable to archive screenshot of {:?}: {}", session, e);
+        }
+        if let Err(e) = self.prune().await {
+            warn!("Unable to prune screenshots in {:?}: {}", self.directory, e);
+        }
+    }
+
+    async fn try_capture(
+        &self,
+        http: &Client,
+        session_url: &str,
+        session: Uuid,
+    ) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
+        #[derive(Deserialize)]
+        struct Value {
+            value: String,
+        }
+
+        let screenshot: Value = http
+            .get(format!("{}/screenshot", session_url))
+            .timeout(CAPTURE_TIMEOUT)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        let png = STANDARD.decode(screenshot.value)?;
+
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+        let path = self
+            .directory
+            .join(format!("{}-{}.png", session, timestamp));
+        tokio::fs::create_dir_all(&self.directory).await?;
+        tokio::fs::write(&path, png).await?;
+        info!("Archived screenshot of {:?} to {:?}", session, path);
+
+        Ok(())
+    }
+
+    /// Removes screenshots older than the retention period
+    async fn prune(&self) -> std::io::Result<()> {
+        let mut entries = tokio::fs::read_dir(&self.directory).await?;
+        while let Some(entry) = entries.next_entry().await? {
+            let path = entry.path();
+            if path.extension().is_none_or(|extension| extension != "png") {
+                continue;
+            }
+
+            let age = entry
+                .metadata()
+                .await?
+                .modified()?
+                .elapsed()
+                .unwrap_or_default();
+            if age > self.retention {
+                debug!("Removing expired screenshot {:?}", path);
+                tokio::fs::remove_file(&path).await?;
+            }
+        }
+
+        Ok(())
+    }
+}
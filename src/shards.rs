@@ -0,0 +1,160 @@
+use async_lock::RwLock;
+use std::collections::HashMap;
+use std::collections::hash_map::Entry;
+use uuid::Uuid;
+
+/// Number of independently locked parts sessions are spread across
+const SHARDS: usize = 32;
+
+/// Sessions by ID, split into shards so that creating or removing a session only locks out those of its shard
+///
+/// A shard's lock is only held to look a session up, yet it may be waited on under it (e.g. for requests to let go of a
+/// session being recovered), which is why this is not a map with blocking locks such as `dashmap`.
+pub struct Shards<T> {
+    shards: Box<[RwLock<HashMap<Uuid, T>>]>,
+}
+
+impl<T> Default for Shards<T> {
+    fn default() -> Self {
+        Self {
+            shards: (0..SHARDS).map(|_| RwLock::default()).collect(),
+        }
+    }
+}
+
+impl<T> Shards<T> {
+    /// Shard holding `id`, whose guard is used to look it up
+    pub fn shard(&self, id: &Uuid) -> &RwLock<HashMap<Uuid, T>> {
+        // Random (v4) IDs spread evenly
+        &self.shards[(id.as_u128() % SHARDS as u128) as usize]
+    }
+
+    pub fn shards(&self) -> impl Iterator<Item = &RwLock<HashMap<Uuid, T>>> {
+        self.shards.iter()
+    }
+
+    pub async fn insert(&self, id: Uuid, value: T) {
+        self.shard(&id).write().await.insert(id, value);
+    }
+
+    /// Inserts `value` unless `id` is taken, handing it back if it is
+    pub async fn try_insert(&self, id: Uuid, value: T) -> Result<(), T> {
+        match self.shard(&id).write().await.entry(id) {
+            Entry::Occupied(_) => Err(value),
+            Entry::Vacant(entry) => {
+                entry.insert(value);
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn remove(&self, id: &Uuid) -> Option<T> {
+        self.shard(id).write().await.remove(id)
+    }
+
+    pub async fn contains(&self, id: &Uuid) -> bool {
+        self.shard(id).read().await.contains_key(id)
+    }
+
+    pub async fn len(&self) -> usize {
+        let mut len = 0;
+        for shard in self.shards() {
+            len += shard.read().await.len();
+        }
+        len
+    }
+
+    pub async fn ids(&self) -> Vec<Uuid> {
+        let mut ids = Vec::new();
+        for shard in self.shards() {
+            ids.extend(shard.read().await.keys().copied());
+        }
+        ids
+    }
+
+    pub async fn drain(&self) -> Vec<(Uuid, T)> {
+        let mut drained = Vec::new();
+        for shard in self.shards() {
+            drained.extend(shard.write().await.drain());
+        }
+        drained
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Arc;
+    use std::time::Instant;
+    use tokio::task::JoinSet;
+
+    #[tokio::test]
+    async fn spreads_sessions() {
+        let shards = Shards::default();
+        let ids: Vec<Uuid> = (0..256).map(|_| Uuid::new_v4()).collect();
+        for (i, id) in ids.iter().enumerate() {
+            shards.insert(*id, i).await;
+        }
+
+        assert_eq!(shards.len().await, 256);
+        assert!(
+            shards
+                .shards()
+                .all(|shard| shard.try_read().unwrap().len() < 256)
+        );
+        assert_eq!(shards.shard(&ids[3]).read().await.get(&ids[3]), Some(&3));
+        assert_eq!(shards.try_insert(ids[3], 256).await, Err(256));
+        assert_eq!(shards.remove(&ids[3]).await, Some(3));
+        assert!(!shards.contains(&ids[3]).await);
+        assert_eq!(shards.drain().await.len(), 255);
+        assert_eq!(shards.len().await, 0);
+    }
+
+    /// Run with `cargo test --release -- --ignored --nocapture benchmark_against_a_single_lock`
+    #[tokio::test(flavor = "multi_thread")]
+    #[ignore]
+    async fn benchmark_against_a_single_lock() {
+        const TASKS: usize = 256;
+        const SESSIONS: usize = 1000;
+
+        let single = Arc::new(RwLock::new(HashMap::new()));
+        let started = Instant::now();
+        let mut tasks = JoinSet::new();
+        for _ in 0..TASKS {
+            let single = single.clone();
+            tasks.spawn(async move {
+                for i in 0..SESSIONS {
+                    let id = Uuid::new_v4();
+                    single.write().await.insert(id, i);
+                    assert!(single.read().await.contains_key(&id));
+                    single.write().await.remove(&id);
+                }
+            });
+        }
+        tasks.join_all().await;
+        let single = started.elapsed();
+
+        let shards = Arc::new(Shards::default());
+        let started = Instant::now();
+        let mut tasks = JoinSet::new();
+        for _ in 0..TASKS {
+            let shards = shards.clone();
+            tasks.spawn(async move {
+                for i in 0..SESSIONS {
+                    let id = Uuid::new_v4();
+                    shards.insert(id, i).await;
+                    assert!(shards.contains(&id).await);
+                    shards.remove(&id).await;
+                }
+            });
+        }
+        tasks.join_all().await;
+        let sharded = started.elapsed();
+
+        println!(
+            "Single lock: {:?}, {} shards: {:?}",
+            single, SHARDS, sharded
+        );
+        assert!(sharded < single);
+    }
+}
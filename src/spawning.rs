@@ -0,0 +1,65 @@
+use async_lock::{Mutex, Semaphore, SemaphoreGuard};
+use log::debug;
+use std::time::{Duration, Instant};
+use tokio::time::sleep;
+
+/// Keeps bursts of new sessions from starting every WebDriver at once, queueing them instead
+pub struct SpawnQueue {
+    /// Unlimited if unset
+    slots: Option<Semaphore>,
+    stagger: Duration,
+    last: Mutex<Option<Instant>>,
+}
+
+impl SpawnQueue {
+    pub fn new(max_spawns: Option<usize>, stagger: Duration) -> Self {
+        Self {
+            slots: max_spawns.map(Semaphore::new),
+            stagger,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Waits for a free slot and `stagger` to pass since the previous spawn, the slot being held until dropped
+    pub async fn enter(&self) -> Option<SemaphoreGuard<'_>> {
+        let slot = match &self.slots {
+            Some(slots) => match slots.try_acquire() {
+                Some(slot) => Some(slot),
+                None => {
+                    debug!("Queued spawn (Too many WebDrivers starting)");
+                    Some(slots.acquire().await)
+                }
+            },
+            None => None,
+        };
+
+        if !self.stagger.is_zero() {
+            let mut last = self.last.lock().await;
+            if let Some(last) = *last {
+                sleep(self.stagger.saturating_sub(last.elapsed())).await;
+            }
+            *last = Some(Instant::now());
+        }
+
+        slot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn queues_spawns() {
+        let queue = SpawnQueue::new(Some(1), Duration::from_millis(50));
+
+        let started = Instant::now();
+        let slot = queue.enter().await;
+        let waiting = tokio::time::timeout(Duration::from_millis(100), queue.enter()).await;
+        assert!(waiting.is_err());
+
+        drop(slot);
+        queue.enter().await;
+        assert!(started.elapsed() >= Duration::from_millis(100));
+    }
+}
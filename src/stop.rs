@@ -0,0 +1,105 @@
+#[cfg(not(unix))]
+use crate::persist;
+use log::{debug, info, warn};
+use std::time::Duration;
+#[cfg(unix)]
+use std::time::Instant;
+use tokio::process::Child;
+use tokio::time::timeout;
+
+/// A WebDriver started as a child process
+///
+/// Its ID, which is also the ID of its process group on Unix, is kept from the start, as the child no longer knows it
+/// once it has been waited for (e.g. to find out it exited), whereas browsers it started may still be running.
+pub struct Driver {
+    pub process: Child,
+    pub pid: Option<u32>,
+}
+
+impl From<Child> for Driver {
+    fn from(process: Child) -> Self {
+        let pid = process.id();
+        Self { process, pid }
+    }
+}
+
+/// Asks a WebDriver to exit and kills it along with what it started (i.e. browsers) if it is still running after `grace`
+///
+/// WebDrivers run in a process group of their own on Unix, which is signalled as a whole, whereas on Windows the process
+/// tree is terminated right away. Without any `grace`, they are killed right away.
+pub async fn stop_driver(driver: &mut Driver, grace: Duration) {
+    let Some(pid) = driver.pid else {
+        return;
+    };
+
+    #[cfg(unix)]
+    if !grace.is_zero() {
+        signal_group(pid, libc::SIGTERM);
+    }
+    #[cfg(not(unix))]
+    persist::terminate(pid, Duration::ZERO).await;
+
+    // Waited for in any case, so that no zombie process is left behind
+    match timeout(grace, driver.process.wait()).await {
+        Ok(Ok(status)) => info!("WebDriver {} exited with {}", pid, status),
+        Ok(Err(e)) => warn!("Unable to wait for WebDriver {}: {}", pid, e),
+        Err(_) => {
+            if !grace.is_zero() {
+                warn!(
+                    "Killing WebDriver {} (Still running after {:?})",
+                    pid, grace
+                );
+            }
+            if let Err(e) = driver.process.kill().await {
+                warn!("Unable to kill WebDriver {}: {}", pid, e);
+            }
+        }
+    }
+    // Browsers may outlive the WebDriver which started them
+    #[cfg(unix)]
+    signal_group(pid, libc::SIGKILL);
+}
+
+/// Asks the process group `pgid`, which was not started by this process (e.g. adopted after a restart), to exit and
+/// kills what is left of it after `grace`
+#[cfg(unix)]
+pub async fn stop_group(pgid: u32, grace: Duration) {
+    let asked = Instant::now();
+    // Without any grace, the group is killed right away
+    if !grace.is_zero() && !signal_group(pgid, libc::SIGTERM) {
+        return;
+    }
+    // Signal 0 only tells whether any process of the group is left
+    while asked.elapsed() < grace && signal_group(pgid, 0) {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+    }
+    signal_group(pgid, libc::SIGKILL);
+    info!("Terminated process group {}", pgid);
+}
+
+/// Sends `signal` to every process of the group `pgid`, returning whether there were any
+#[cfg(unix)]
+fn signal_group(pgid: u32, signal: libc::c_int) -> bool {
+    // -1 would signal every process this one may signal, and 0 its own group
+    let Ok(pgid) = libc::pid_t::try_from(pgid) else {
+        return false;
+    };
+    if pgid <= 1 {
+        warn!("Refusing to signal process group {}", pgid);
+        return false;
+    }
+
+    // SAFETY: kill has no preconditions
+    if unsafe { libc::kill(-pgid, signal) } == 0 {
+        if signal != 0 {
+            debug!("Sent signal {} to process group {}", signal, pgid);
+        }
+        return true;
+    }
+    let e = std::io::Error::last_os_error();
+    // Nothing is left of the group
+    if e.raw_os_error() != Some(libc::ESRCH) {
+        warn!("Unable to signal process group {}: {}", pgid, e);
+    }
+    false
+}
@@ -0,0 +1,52 @@
+use crate::Browsers;
+#[cfg(unix)]
+use log::{debug, warn};
+#[cfg(unix)]
+use sd_notify::NotifyState;
+#[cfg(unix)]
+use std::time::Duration;
+
+/// Tells systemd (`Type=notify`) that requests are being served
+pub fn ready() {
+    #[cfg(unix)]
+    notify(NotifyState::Ready);
+}
+
+pub fn stopping() {
+    #[cfg(unix)]
+    notify(NotifyState::Stopping);
+}
+
+/// Pings the systemd watchdog (`WatchdogSec=`) as long as sessions can still be looked up
+pub fn watchdog(browsers: Browsers) {
+    #[cfg(unix)]
+    {
+        let mut usec = 0;
+        if !sd_notify::watchdog_enabled(false, &mut usec) {
+            return;
+        }
+
+        let interval = Duration::from_micros(usec) / 2;
+        debug!("Pinging watchdog every {:?}", interval);
+        tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                match tokio::time::timeout(interval, browsers.len()).await {
+                    Ok(_) => notify(NotifyState::Watchdog),
+                    Err(_) => warn!("Skipped watchdog ping (Sessions are locked)"),
+                }
+            }
+        });
+    }
+
+    #[cfg(not(unix))]
+    let _ = browsers;
+}
+
+#[cfg(unix)]
+fn notify(state: NotifyState) {
+    if let Err(e) = sd_notify::notify(false, &[state]) {
+        warn!("Unable to notify systemd: {}", e);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::admin::unix_seconds;
+use crate::auth::Quota;
+use crate::labels::Selector;
+use crate::{AppState, Browser, Browsers};
+use axum::Router;
+use axum::extract::{Extension, Query, State};
+use axum::response::Json;
+use axum::routing::get;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A team sharing this instance, which only sees the sessions it created itself
+pub struct Tenant {
+    pub name: String,
+    pub tokens: Vec<String>,
+    /// Limits the concurrent sessions of all of the tenant's tokens together
+    pub quota: Quota,
+    /// Takes the place of `--tti` for the tenant's sessions
+    pub tti: Option<Duration>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case", deny_unknown_fields)]
+struct Definition {
+    #[serde(default)]
+    tokens: Vec<String>,
+    max_sessions: Option<usize>,
+    tti: Option<String>,
+}
+
+/// Reads a TOML file with a table per tenant, e.g. `[checkout]` followed by `tokens`, `max-sessions` and `tti`
+pub fn read(path: &Path) -> Result<Vec<Tenant>, String> {
+    let contents = std::fs::read_to_string(path).map_err(|e| e.to_string())?;
+    parse(&contents).map_err(|e| format!("Unable to parse {:?}: {}", path, e))
+}
+
+fn parse(contents: &str) -> Result<Vec<Tenant>, String> {
+    let definitions: BTreeMap<String, Definition> =
+        toml::from_str(contents).map_err(|e| e.to_string())?;
+
+    definitions
+        .into_iter()
+        .map(|(name, definition)| {
+            // Files sessions leave behind are kept in a directory named after their tenant
+            let valid = |c: char| c.is_ascii_alphanumeric() || c == '-' || c == '_';
+            if name.is_empty() || !name.chars().all(valid) {
+                return Err(format!(
+                    "Tenant {:?} may only be named using letters, digits, '-' and '_'",
+                    name
+                ));
+            }
+            let tti = match definition.tti {
+                Some(tti) => Some(humantime::parse_duration(&tti).map_err(|e| e.to_string())?),
+                None => None,
+            };
+            Ok(Tenant {
+                name,
+                tokens: definition.tokens,
+                quota: Quota::new(definition.max_sessions),
+                tti,
+            })
+        })
+        .collect()
+}
+
+/// Whether a request by `tenant` may access `browser`, sessions without a tenant being those of requests without one
+pub fn owns(tenant: Option<&Tenant>, browser: &Browser) -> bool {
+    same(tenant, browser.tenant.as_deref())
+}
+
+/// Whether `tenant` is `other`, which is also the case if neither is a tenant
+pub fn same(tenant: Option<&Tenant>, other: Option<&Tenant>) -> bool {
+    tenant.map(|t| t.name.as_str()) == other.map(|t| t.name.as_str())
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/sessiondriver/sessions", get(sessions))
+}
+
+/// Sessions of the requesting tenant, only those having the labels given as e.g. `?labels=team=checkout` if any
+async fn sessions(
+    State(browsers): State<Browsers>,
+    tenant: Option<Extension<Arc<Tenant>>>,
+    Query(selector): Query<Selector>,
+) -> Json<serde_json::Value> {
+    let tenant = tenant.as_ref().map(|Extension(tenant)| tenant.as_ref());
+    let mut sessions = Vec::new();
+    for shard in browsers.shards() {
+        let shard = shard.read().await;
+        sessions.extend(
+            shard
+                .iter()
+                .filter(|(_, browser)| owns(tenant, browser) && selector.matches(&browser.labels))
+                .map(|(id, browser)| {
+                    serde_json::json!({
+                        "id": id,
+                        "created": unix_seconds(browser.created),
+                        "capabilities": browser.capabilities,
+                        "labels": browser.labels,
+                    })
+                }),
+        );
+    }
+
+    Json(serde_json::json!({ "value": sessions }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_tenants() {
+        let tenants = parse(
+            r#"
+            [checkout]
+            tokens = ["a", "b"]
+            max-sessions = 5
+            tti = "10m"
+
+            [search]
+            tokens = ["c"]
+            "#,
+        )
+        .unwrap();
+
+        assert_eq!(tenants[0].name, "checkout");
+        assert_eq!(tenants[0].tokens, ["a", "b"]);
+        assert_eq!(tenants[0].tti, Some(Duration::from_secs(600)));
+        assert_eq!(tenants[1].tti, None);
+        assert!(parse("[search]\ntoken = \"c\"").is_err());
+        assert!(parse("[\"../search\"]\ntokens = [\"c\"]").is_err());
+    }
+}
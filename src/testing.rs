@@ -0,0 +1,210 @@
+//! Throwaway SessionDriver and WebDriver instances for integration tests, and a [`MockManager`] for unit tests
+//! (Feature `testing`)
+//!
+//! ```no_run
+//! # async fn example() -> std::io::Result<()> {
+//! use sessiondriver::testing::Instance;
+//!
+//! let instance = Instance::sessiondriver("geckodriver").await?;
+//! let pool = sessiondriver::Pool::builder(sessiondriver::Manager::new(instance.config(None)))
+//!     .max_size(2)
+//!     .build()
+//!     .unwrap();
+//! # Ok(())
+//! # }
+//! ```
+
+use crate::local::LocalDriver;
+use crate::{Config, Error};
+use deadpool::managed;
+use fantoccini::wd::Capabilities;
+use std::collections::VecDeque;
+use std::ffi::{OsStr, OsString};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+/// A SessionDriver or WebDriver for a test, which is stopped once dropped
+pub struct Instance {
+    driver: LocalDriver,
+}
+
+impl Instance {
+    /// Starts `program` with `--port` and `args` on an ephemeral port, waiting until it is ready
+    pub async fn start<P, I, S>(program: P, args: I) -> std::io::Result<Self>
+    where
+        P: AsRef<OsStr>,
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let driver = LocalDriver::start(program, args).await?;
+        Ok(Self { driver })
+    }
+
+    /// Starts the `sessiondriver` executable found in `PATH`, spawning `webdriver` for each session
+    pub async fn sessiondriver<W: AsRef<OsStr>>(webdriver: W) -> std::io::Result<Self> {
+        let mut webdriver_arg = OsString::from("--webdriver=");
+        webdriver_arg.push(webdriver);
+        Self::start(
+            "sessiondriver",
+            [OsStr::new("--host=127.0.0.1"), &webdriver_arg],
+        )
+        .await
+    }
+
+    /// Starts the `geckodriver` executable found in `PATH`, serving a single session
+    pub async fn geckodriver() -> std::io::Result<Self> {
+        Self::start("geckodriver", ["--host=127.0.0.1"]).await
+    }
+
+    pub fn port(&self) -> u16 {
+        self.driver.port()
+    }
+
+    pub fn url(&self) -> String {
+        self.driver.url()
+    }
+
+    /// Configuration of a pool connecting to this instance
+    pub fn config(&self, capabilities: Option<Capabilities>) -> Config {
+        Config::new(self.url(), capabilities)
+    }
+}
+
+/// What a [`MockManager`] does when asked to create or recycle a session
+#[derive(Debug, Clone, PartialEq)]
+pub enum Outcome {
+    Succeed,
+    Fail,
+    /// Succeeds after the delay, e.g. to run into timeouts of the pool
+    Delay(Duration),
+}
+
+/// Session handed out by a [`MockManager`], numbered in order of creation
+#[derive(Debug, PartialEq, Eq)]
+pub struct MockSession {
+    pub id: usize,
+}
+
+pub type MockPool = managed::Pool<MockManager>;
+
+/// Takes the place of [`crate::Manager`] without any WebDriver, following scripted outcomes
+///
+/// Outcomes are followed in the order they were scripted in, sessions being created and recycled successfully once
+/// they are used up. Scripts can be extended while the manager is in use through [`managed::Pool::manager`].
+///
+/// ```
+/// # async fn example() {
+/// use sessiondriver::testing::{MockManager, MockPool, Outcome};
+///
+/// let manager = MockManager::new();
+/// manager.on_create([Outcome::Fail]);
+/// let pool = MockPool::builder(manager).max_size(1).build().unwrap();
+///
+/// assert!(pool.get().await.is_err());
+/// assert_eq!(pool.get().await.unwrap().id, 0);
+/// # }
+/// ```
+#[derive(Debug, Default)]
+pub struct MockManager {
+    create: Mutex<VecDeque<Outcome>>,
+    recycle: Mutex<VecDeque<Outcome>>,
+    created: AtomicUsize,
+    recycled: AtomicUsize,
+}
+
+impl MockManager {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Scripts the outcomes of the next creations
+    pub fn on_create<I: IntoIterator<Item = Outcome>>(&self, outcomes: I) {
+        self.create
+            .lock()
+            .expect("MockManager lock poisoned")
+            .extend(outcomes);
+    }
+
+    /// Scripts the outcomes of the next recycles, i.e. whether sessions handed back are healthy
+    pub fn on_recycle<I: IntoIterator<Item = Outcome>>(&self, outcomes: I) {
+        self.recycle
+            .lock()
+            .expect("MockManager lock poisoned")
+            .extend(outcomes);
+    }
+
+    /// Number of sessions created successfully so far
+    pub fn created(&self) -> usize {
+        self.created.load(Ordering::Relaxed)
+    }
+
+    /// Number of sessions recycled successfully so far
+    pub fn recycled(&self) -> usize {
+        self.recycled.load(Ordering::Relaxed)
+    }
+}
+
+/// Takes the next scripted outcome, waiting out a delay
+async fn follow(script: &Mutex<VecDeque<Outcome>>) -> Result<(), ()> {
+    let outcome = script
+        .lock()
+        .expect("MockManager lock poisoned")
+        .pop_front()
+        .unwrap_or(Outcome::Succeed);
+    match outcome {
+        Outcome::Succeed => Ok(()),
+        Outcome::Fail => Err(()),
+        Outcome::Delay(delay) => {
+            tokio::time::sleep(delay).await;
+            Ok(())
+        }
+    }
+}
+
+impl managed::Manager for MockManager {
+    type Type = MockSession;
+    type Error = Error;
+
+    async fn create(&self) -> Result<MockSession, Error> {
+        follow(&self.create)
+            .await
+            .map_err(|_| Error::Other(std::io::Error::other("Scripted failure")))?;
+
+        Ok(MockSession {
+            id: self.created.fetch_add(1, Ordering::Relaxed),
+        })
+    }
+
+    async fn recycle(
+        &self,
+        _: &mut MockSession,
+        _: &managed::Metrics,
+    ) -> managed::RecycleResult<Error> {
+        follow(&self.recycle)
+            .await
+            .map_err(|_| managed::RecycleError::message("Scripted failure"))?;
+        self.recycled.fetch_add(1, Ordering::Relaxed);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn follows_script() {
+        let manager = MockManager::new();
+        manager.on_recycle([Outcome::Fail]);
+        let pool = MockPool::builder(manager).max_size(1).build().unwrap();
+
+        drop(pool.get().await.unwrap());
+        // Replaced, as the first session fails to be recycled
+        assert_eq!(pool.get().await.unwrap().id, 1);
+        drop(pool.get().await.unwrap());
+        assert_eq!(pool.manager().created(), 2);
+        assert_eq!(pool.manager().recycled(), 1);
+    }
+}
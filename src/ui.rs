@@ -0,0 +1,36 @@
+use axum::response::{Html, IntoResponse, Response};
+
+/// Dashboard over the administrative API, which asks for the admin token itself
+const DASHBOARD: &str = include_str!("ui.html");
+
+/// Description of SessionDriver's own endpoints
+const OPENAPI: &str = include_str!("openapi.json");
+
+pub async fn dashboard() -> Html<&'static str> {
+    Html(DASHBOARD)
+}
+
+pub async fn openapi() -> Response {
+    ([("Content-Type", "application/json")], OPENAPI).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn documents_every_admin_route() {
+        let document: serde_json::Value = serde_json::from_str(OPENAPI).unwrap();
+
+        for path in [
+            "/admin/sessions",
+            "/admin/sessions/{id}",
+            "/admin/sessions/{id}/commands",
+            "/admin/sessions/{id}/logs",
+            "/admin/stats",
+            "/admin/webdriver",
+        ] {
+            assert!(document["paths"].get(path).is_some(), "{} is missing", path);
+        }
+    }
+}
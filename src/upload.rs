@@ -0,0 +1,178 @@
+use crate::tenant::{self, Tenant};
+use crate::{AppState, Browsers, bad_request_error, internal_server_error, w3c};
+use axum::Router;
+use axum::body::Bytes;
+use axum::extract::{DefaultBodyLimit, Extension, Path, State};
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
+use axum::routing::post;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use log::{debug, info, warn};
+use serde::Deserialize;
+use std::io::{Cursor, Error, ErrorKind, Read};
+use std::path::PathBuf;
+use std::sync::Arc;
+use uuid::Uuid;
+use zip::ZipArchive;
+
+/// Directory the files pushed for a single session are written to, removed along with the session
+pub struct Uploads {
+    pub directory: PathBuf,
+}
+
+/// Number of bytes of a request's body and of the file extracted from it, see `--upload-limit`
+#[derive(Clone, Copy)]
+struct Limit(u64);
+
+#[derive(Deserialize)]
+struct FileUpload {
+    /// Base64 encoded zip archive holding a single file
+    file: String,
+}
+
+impl Uploads {
+    pub fn new(root: &std::path::Path) -> Self {
+        Self {
+            directory: root.join(Uuid::new_v4().to_string()),
+        }
+    }
+
+    /// Extracts the only file of `archive` into a directory of its own and returns its path, rejecting files of more than
+    /// `limit` bytes
+    pub fn store(&self, archive: Vec<u8>, limit: u64) -> std::io::Result<PathBuf> {
+        let mut archive = ZipArchive::new(Cursor::new(archive))?;
+        let files: Vec<usize> = (0..archive.len())
+            .filter(|&i| archive.by_index(i).is_ok_and(|entry| entry.is_file()))
+            .collect();
+        let [index] = files[..] else {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Expected 1 file, found {}", files.len()),
+            ));
+        };
+
+        let mut entry = archive.by_index(index)?;
+        let too_large = || {
+            Error::new(
+                ErrorKind::InvalidInput,
+                format!("File exceeds {} bytes", limit),
+            )
+        };
+        if entry.size() > limit {
+            return Err(too_large());
+        }
+        let name = entry
+            .enclosed_name()
+            .and_then(|path| path.file_name().map(PathBuf::from))
+            .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "Invalid file name"))?;
+        // Uploads of files with the same name must not overwrite one another
+        let directory = self.directory.join(Uuid::new_v4().to_string());
+        std::fs::create_dir_all(&directory)?;
+        let path = directory.join(name);
+        // The size an archive states may be a lie
+        let copied = std::io::copy(
+            &mut (&mut entry).take(limit + 1),
+            &mut std::fs::File::create(&path)?,
+        )?;
+        if copied > limit {
+            std::fs::remove_dir_all(&directory)?;
+            return Err(too_large());
+        }
+
+        Ok(path)
+    }
+
+    pub async fn remove(self) {
+        match tokio::fs::remove_dir_all(&self.directory).await {
+            Ok(()) => debug!("Removed uploads {:?}", self.directory),
+            Err(e) if e.kind() == ErrorKind::NotFound => {}
+            Err(e) => warn!("Unable to remove uploads {:?}: {}", self.directory, e),
+        }
+    }
+}
+
+/// Selenium's file upload, which makes `<input type="file">` work with files of the client, accepting bodies of up to
+/// `limit` bytes
+pub fn router(limit: usize) -> Router<AppState> {
+    Router::new()
+        .route("/session/{id}/se/file", post(upload))
+        .layer(DefaultBodyLimit::max(limit))
+        .layer(Extension(Limit(limit as u64)))
+}
+
+async fn upload(
+    State(browsers): State<Browsers>,
+    Path(id): Path<Uuid>,
+    tenant: Option<Extension<Arc<Tenant>>>,
+    Extension(Limit(limit)): Extension<Limit>,
+    body: Bytes,
+) -> Result<Response, Response> {
+    // Clients do not agree on sending a Content-Type
+    let upload: FileUpload = serde_json::from_slice(&body).map_err(bad_request_error)?;
+    let archive = STANDARD.decode(upload.file).map_err(bad_request_error)?;
+    let directory = browsers
+        .shard(&id)
+        .read()
+        .await
+        .get(&id)
+        .filter(|b| tenant::owns(tenant.as_ref().map(|Extension(t)| t.as_ref()), b))
+        .and_then(|b| b.uploads.as_ref())
+        .map(|uploads| uploads.directory.clone());
+    let Some(directory) = directory else {
+        return Err(w3c::error(
+            StatusCode::NOT_FOUND,
+            w3c::INVALID_SESSION_ID,
+            format!("No active session with ID {} accepting uploads", id),
+        ));
+    };
+
+    let path = tokio::task::spawn_blocking(move || Uploads { directory }.store(archive, limit))
+        .await
+        .map_err(internal_server_error)?
+        .map_err(|e| match e.kind() {
+            ErrorKind::InvalidInput | ErrorKind::InvalidData => bad_request_error(e),
+            _ => internal_server_error(e),
+        })?;
+    info!("Uploaded {:?} for {:?}", path, id);
+
+    Ok(Json(serde_json::json!({ "value": path })).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use zip::ZipWriter;
+    use zip::write::SimpleFileOptions;
+
+    fn archive(names: &[&str]) -> Vec<u8> {
+        let mut writer = ZipWriter::new(Cursor::new(Vec::new()));
+        for name in names {
+            writer
+                .start_file(*name, SimpleFileOptions::default())
+                .unwrap();
+            writer.write_all(b"content").unwrap();
+        }
+        writer.finish().unwrap().into_inner()
+    }
+
+    #[test]
+    fn extracts_a_single_file() {
+        let root = std::env::temp_dir().join(Uuid::new_v4().to_string());
+        let uploads = Uploads::new(&root);
+
+        let path = uploads
+            .store(archive(&["nested/report.pdf"]), 1024)
+            .unwrap();
+        assert!(path.starts_with(&uploads.directory));
+        assert_eq!(path.file_name().unwrap(), "report.pdf");
+        assert_eq!(std::fs::read(&path).unwrap(), b"content");
+        assert!(uploads.store(archive(&["../report.pdf"]), 1024).is_err());
+        assert!(uploads.store(archive(&["a.txt", "b.txt"]), 1024).is_err());
+        // "content" takes up 7 bytes
+        assert!(uploads.store(archive(&["large.txt"]), 6).is_err());
+
+        std::fs::remove_dir_all(root).unwrap();
+    }
+}
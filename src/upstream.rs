@@ -0,0 +1,74 @@
+use reqwest::header::{self, HeaderMap, HeaderName, HeaderValue};
+
+/// Headers removed from or added to requests before they are passed on to a WebDriver or node
+#[derive(Debug, Default)]
+pub struct UpstreamHeaders {
+    /// Client headers meant only for this instance, see `--strip-header`
+    pub strip: Vec<HeaderName>,
+    /// Replacing those sent by clients, see `--inject-header`
+    pub inject: Vec<(HeaderName, HeaderValue)>,
+}
+
+impl UpstreamHeaders {
+    /// Strips `Authorization` along with `strip` unless `forward_authorization` is set
+    pub fn new(
+        mut strip: Vec<HeaderName>,
+        inject: Vec<(HeaderName, HeaderValue)>,
+        forward_authorization: bool,
+    ) -> Self {
+        if !forward_authorization && !strip.contains(&header::AUTHORIZATION) {
+            strip.push(header::AUTHORIZATION);
+        }
+        Self { strip, inject }
+    }
+
+    pub fn apply(&self, headers: &mut HeaderMap) {
+        for name in &self.strip {
+            headers.remove(name);
+        }
+        for (name, value) in &self.inject {
+            headers.insert(name.clone(), value.clone());
+        }
+    }
+}
+
+/// Parses `--inject-header`
+pub fn parse_header(s: &str) -> Result<(HeaderName, HeaderValue), String> {
+    let invalid = || format!("Expected <name>: <value>, got {:?}", s);
+    let (name, value) = s.split_once(':').ok_or_else(invalid)?;
+    let name = HeaderName::try_from(name.trim()).map_err(|_| invalid())?;
+    let mut value = HeaderValue::try_from(value.trim()).map_err(|_| invalid())?;
+    value.set_sensitive(true);
+
+    Ok((name, value))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn strips_and_injects() {
+        let headers = UpstreamHeaders::new(
+            vec![HeaderName::from_static("x-tenant")],
+            vec![parse_header("Authorization: Basic dXNlcjpwYXNz").unwrap()],
+            false,
+        );
+        let mut map = HeaderMap::new();
+        map.insert(
+            header::AUTHORIZATION,
+            HeaderValue::from_static("Bearer proxy"),
+        );
+        map.insert("x-tenant", HeaderValue::from_static("team-a"));
+        map.insert(
+            header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        headers.apply(&mut map);
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map[header::AUTHORIZATION], "Basic dXNlcjpwYXNz");
+        assert_eq!(map[header::CONTENT_TYPE], "application/json");
+        assert!(parse_header("Authorization").is_err());
+    }
+}
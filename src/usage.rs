@@ -0,0 +1,200 @@
+use crate::{Browsers, admin};
+use log::warn;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// CPU and memory used by the processes of a session's WebDriver (i.e. along with its browser)
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct Snapshot {
+    /// Percent of a single core since the previous sample
+    pub cpu: f64,
+    /// Resident memory in bytes
+    pub rss: u64,
+    pub processes: usize,
+    pub peak_cpu: f64,
+    pub peak_rss: u64,
+}
+
+/// Latest sample of a session's processes, see [`sample`]
+#[derive(Default)]
+pub struct Usage(Mutex<Option<Sampled>>);
+
+struct Sampled {
+    /// CPU time in clock ticks
+    ticks: u64,
+    at: Instant,
+    snapshot: Snapshot,
+}
+
+/// Totals of the processes in a process group
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Group {
+    ticks: u64,
+    /// Resident memory in pages
+    pages: u64,
+    processes: usize,
+}
+
+impl Usage {
+    pub fn snapshot(&self) -> Option<Snapshot> {
+        self.0
+            .lock()
+            .expect("Usage lock poisoned")
+            .as_ref()
+            .map(|sampled| sampled.snapshot)
+    }
+
+    fn record(&self, group: Group, clock_ticks: u64, page_size: u64) {
+        let now = Instant::now();
+        let mut sampled = self.0.lock().expect("Usage lock poisoned");
+        let previous = sampled.as_ref();
+
+        let cpu = match previous {
+            Some(previous) if clock_ticks > 0 => {
+                let seconds = now.duration_since(previous.at).as_secs_f64();
+                let used = group.ticks.saturating_sub(previous.ticks) as f64 / clock_ticks as f64;
+                if seconds > 0.0 {
+                    used / seconds * 100.0
+                } else {
+                    0.0
+                }
+            }
+            _ => 0.0,
+        };
+        let rss = group.pages * page_size;
+        let (peak_cpu, peak_rss) = previous
+            .map(|previous| (previous.snapshot.peak_cpu, previous.snapshot.peak_rss))
+            .unwrap_or_default();
+
+        *sampled = Some(Sampled {
+            ticks: group.ticks,
+            at: now,
+            snapshot: Snapshot {
+                cpu,
+                rss,
+                processes: group.processes,
+                peak_cpu: peak_cpu.max(cpu),
+                peak_rss: peak_rss.max(rss),
+            },
+        });
+    }
+}
+
+/// Samples the process group of every session's WebDriver each `interval` (Only on Linux, through `/proc`)
+pub fn sample(browsers: Browsers, interval: Duration) {
+    if !cfg!(target_os = "linux") || interval.is_zero() {
+        return;
+    }
+    // SAFETY: sysconf has no preconditions
+    #[cfg(unix)]
+    let (clock_ticks, page_size) = unsafe {
+        (
+            libc::sysconf(libc::_SC_CLK_TCK).max(0) as u64,
+            libc::sysconf(libc::_SC_PAGESIZE).max(0) as u64,
+        )
+    };
+    #[cfg(not(unix))]
+    let (clock_ticks, page_size) = (0, 0);
+
+    tokio::spawn(async move {
+        loop {
+            tokio::time::sleep(interval).await;
+
+            let groups = match tokio::task::spawn_blocking(groups).await {
+                Ok(Ok(groups)) => groups,
+                Ok(Err(e)) => {
+                    warn!("Unable to sample processes: {}", e);
+                    continue;
+                }
+                Err(_) => continue,
+            };
+            for shard in browsers.shards() {
+                for browser in shard.read().await.values() {
+                    // WebDrivers lead a process group of their own, see `spawn_command`
+                    let Some(pid) = admin::pid(browser).await else {
+                        continue;
+                    };
+                    let group = groups.get(&pid).copied().unwrap_or_default();
+                    browser.usage.record(group, clock_ticks, page_size);
+                }
+            }
+        }
+    });
+}
+
+/// Totals of every process group
+fn groups() -> std::io::Result<HashMap<u32, Group>> {
+    let mut groups: HashMap<u32, Group> = HashMap::new();
+    for entry in std::fs::read_dir("/proc")? {
+        let entry = entry?;
+        if !entry
+            .file_name()
+            .to_string_lossy()
+            .bytes()
+            .all(|b| b.is_ascii_digit())
+        {
+            continue;
+        }
+        // Processes may exit while being read
+        let Ok(stat) = std::fs::read_to_string(entry.path().join("stat")) else {
+            continue;
+        };
+        if let Some((pgrp, process)) = parse_stat(&stat) {
+            let group = groups.entry(pgrp).or_default();
+            group.ticks += process.ticks;
+            group.pages += process.pages;
+            group.processes += 1;
+        }
+    }
+
+    Ok(groups)
+}
+
+/// The process group and usage of a process from `/proc/<pid>/stat`
+fn parse_stat(stat: &str) -> Option<(u32, Group)> {
+    // The command name may contain spaces and parentheses itself
+    let (_, fields) = stat.rsplit_once(')')?;
+    let fields: Vec<&str> = fields.split_whitespace().collect();
+    // Counted from the state, which is the third field
+    let field = |n: usize| {
+        fields
+            .get(n - 3)
+            .and_then(|field| field.parse::<u64>().ok())
+    };
+
+    let pgrp = field(5)? as u32;
+    let ticks = field(14)? + field(15)?;
+    let pages = field(24)?;
+    Some((
+        pgrp,
+        Group {
+            ticks,
+            pages,
+            processes: 1,
+        },
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_stat() {
+        let stat = "4242 (Web Content (x)) S 4200 4200 4200 0 -1 4194560 1000 0 0 0 150 50 0 0 20 0 \
+                    30 0 123456 2000000000 25000 18446744073709551615";
+        assert_eq!(
+            parse_stat(stat),
+            Some((
+                4200,
+                Group {
+                    ticks: 200,
+                    pages: 25000,
+                    processes: 1
+                }
+            ))
+        );
+    }
+}
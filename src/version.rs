@@ -0,0 +1,28 @@
+use crate::{AppState, Backend, WebDriverMeta};
+use axum::Router;
+use axum::extract::State;
+use axum::response::Json;
+use axum::routing::get;
+use std::sync::Arc;
+
+pub fn router() -> Router<AppState> {
+    Router::new().route("/sessiondriver/version", get(version))
+}
+
+/// Version of SessionDriver and of the WebDriver executable new sessions are started with
+async fn version(State(webdriver_meta): State<Arc<WebDriverMeta>>) -> Json<serde_json::Value> {
+    let webdriver = match &webdriver_meta.backend {
+        Backend::Process(path) => serde_json::json!({
+            "path": *path.read().await,
+            "version": *webdriver_meta.driver_version.read().await,
+        }),
+        Backend::Docker(_) | Backend::Kubernetes(_) | Backend::Remote(_) => serde_json::Value::Null,
+    };
+
+    Json(serde_json::json!({
+        "value": {
+            "version": env!("CARGO_PKG_VERSION"),
+            "webdriver": webdriver,
+        }
+    }))
+}
@@ -0,0 +1,47 @@
+use axum::http::StatusCode;
+use axum::response::{IntoResponse, Json, Response};
+
+/// Error codes of the WebDriver specification used for failures of this instance itself
+pub const INVALID_ARGUMENT: &str = "invalid argument";
+pub const INVALID_SESSION_ID: &str = "invalid session id";
+pub const SESSION_NOT_CREATED: &str = "session not created";
+pub const UNKNOWN_COMMAND: &str = "unknown command";
+pub const UNKNOWN_ERROR: &str = "unknown error";
+pub const UNKNOWN_METHOD: &str = "unknown method";
+
+/// Answers with a body as a WebDriver would, so that clients surface `message` rather than failing to parse it
+pub fn error(status: StatusCode, error: &str, message: impl Into<String>) -> Response {
+    let body = serde_json::json!({
+        "value": {
+            "error": error,
+            "message": message.into(),
+            "stacktrace": "",
+        }
+    });
+
+    (status, Json(body)).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::body::to_bytes;
+
+    #[tokio::test]
+    async fn answers_as_webdriver() {
+        let response = error(StatusCode::NOT_FOUND, INVALID_SESSION_ID, "Unknown session");
+        assert_eq!(response.status(), StatusCode::NOT_FOUND);
+        assert_eq!(response.headers()["content-type"], "application/json");
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        assert_eq!(
+            serde_json::from_slice::<serde_json::Value>(&body).unwrap(),
+            serde_json::json!({
+                "value": {
+                    "error": "invalid session id",
+                    "message": "Unknown session",
+                    "stacktrace": "",
+                }
+            })
+        );
+    }
+}
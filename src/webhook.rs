@@ -0,0 +1,91 @@
+use log::{debug, warn};
+use reqwest::Client;
+use serde::Serialize;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use uuid::Uuid;
+
+/// Time a webhook receiver is given to accept an event
+const DELIVERY_TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum EventKind {
+    Created,
+    Deleted,
+    Expired,
+    Killed,
+    Crashed,
+    Expiring,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Event {
+    pub event: EventKind,
+    pub session: Uuid,
+    /// Milliseconds since the Unix epoch
+    pub timestamp: u64,
+    pub capabilities: serde_json::Value,
+    /// Seconds until the session expires unless used, only sent along with `expiring`
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub expires_in: Option<u64>,
+}
+
+impl Event {
+    fn new(event: EventKind, session: Uuid, capabilities: &serde_json::Value) -> Self {
+        Self {
+            event,
+            session,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|duration| duration.as_millis() as u64)
+                .unwrap_or_default(),
+            capabilities: capabilities.clone(),
+            expires_in: None,
+        }
+    }
+}
+
+/// Posts session lifecycle events to a single URL
+pub struct Webhook {
+    pub url: String,
+}
+
+impl Webhook {
+    /// Delivers an event in the background so sessions are never held up by the receiver
+    pub fn notify(
+        &self,
+        http: &Client,
+        event: EventKind,
+        session: Uuid,
+        capabilities: &serde_json::Value,
+    ) {
+        self.deliver(http, Event::new(event, session, capabilities));
+    }
+
+    /// Tells that an idle session expires in `remaining`, so its client may use it or save its state in time
+    pub fn warn(
+        &self,
+        http: &Client,
+        session: Uuid,
+        capabilities: &serde_json::Value,
+        remaining: Duration,
+    ) {
+        let mut event = Event::new(EventKind::Expiring, session, capabilities);
+        event.expires_in = Some(remaining.as_secs());
+        self.deliver(http, event);
+    }
+
+    fn deliver(&self, http: &Client, event: Event) {
+        let request = http.post(&self.url).timeout(DELIVERY_TIMEOUT).json(&event);
+
+        tokio::spawn(async move {
+            match request.send().await.and_then(|r| r.error_for_status()) {
+                Ok(_) => debug!("Delivered {:?} event of {:?}", event.event, event.session),
+                Err(e) => warn!(
+                    "Unable to deliver {:?} event of {:?}: {}",
+                    event.event, event.session, e
+                ),
+            }
+        });
+    }
+}